@@ -0,0 +1,38 @@
+#!/usr/bin/env bash
+# Builds the fusion image and runs a job spec with its default entrypoint,
+# the results must be published next to the spec
+set -euo pipefail
+
+cd "$(dirname "$0")"
+IMAGE=${IMAGE:-datalake-fusion:smoke}
+DATA=$(mktemp -d)
+# files written by the container are owned by root
+trap 'docker run --rm -v "$DATA:/data" --entrypoint rm "$IMAGE" -rf /data/table /data/out; rm -rf "$DATA"' EXIT
+
+docker build -t "$IMAGE" .
+
+# table written with a plain DataFusion session of the image
+docker run --rm -v "$DATA:/data" --entrypoint datalake-fusion "$IMAGE" --local query \
+    --sql "SELECT * FROM (VALUES (1, 'a'), (2, 'b'), (3, 'c')) AS t(id, name)" \
+    --out /data/table/ --format parquet >/dev/null
+
+cat > "$DATA/spec.json" <<SPEC
+{
+  "version": 1,
+  "request_id": "smoke",
+  "query": "SELECT count(*) AS n FROM t0",
+  "tables": [{"name": "t0", "path": "file:///data/table/"}],
+  "formats": ["parquet", "json"],
+  "row_limit": 10,
+  "output": "file:///data/out/"
+}
+SPEC
+
+docker run --rm -v "$DATA:/data" -e JOB_SPEC_URI=file:///data/spec.json "$IMAGE"
+
+for file in smoke.parquet smoke.json smoke.manifest.json smoke.job.json; do
+    test -s "$DATA/out/$file" || { echo "missing $file" >&2; exit 1; }
+done
+grep -q '"n":3' "$DATA/out/smoke.json" || { echo "unexpected result: $(cat "$DATA/out/smoke.json")" >&2; exit 1; }
+grep -q '"status":"succeeded"' "$DATA/out/smoke.job.json" || { echo "job did not succeed" >&2; exit 1; }
+echo "smoke test passed"
@@ -0,0 +1,296 @@
+use std::path::Path;
+
+use chrono::Utc;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::{Result, eyre::eyre};
+
+use crate::TableRef;
+use crate::utils::constants::{PROFILE_BINS, PROFILE_TOP_K, STANDALONE_EXECUTORS};
+use crate::utils::jobspec::{JOB_SPEC_VERSION, JobSpec, OutputFormat};
+use crate::utils::params::QueryParam;
+use crate::utils::profile::ProfileSpec;
+use crate::utils::standalone::StandaloneConfig;
+
+#[derive(Parser, Debug)]
+#[command(name = "datalake-fusion", about = "Runs datalake queries on an in-process Ballista cluster or a local DataFusion session")]
+pub struct Cli {
+    /// Execute in this process with a plain DataFusion session instead of Ballista
+    #[arg(long, global = true, conflicts_with = "standalone")]
+    pub local: bool,
+
+    /// Start Ballista scheduler and executors in this process
+    #[arg(long, global = true)]
+    pub standalone: bool,
+
+    /// Executors of the standalone cluster
+    #[arg(long, global = true, default_value_t = STANDALONE_EXECUTORS)]
+    pub executors: usize,
+
+    /// Task slots of every standalone executor, number of cpus when not given
+    #[arg(long, global = true)]
+    pub concurrent_tasks: Option<usize>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a job spec written by the lambda, JOB_SPEC_URI when not given
+    Run {
+        /// s3:// or file:// uri or local path of {request_id}.spec.json
+        #[arg(long)]
+        spec: Option<String>,
+    },
+    /// Run a query against local or s3 tables and write results to a directory
+    Query {
+        #[arg(long)]
+        sql: String,
+        /// Table used in the query, name=path, repeatable
+        #[arg(long = "table", value_parser = parse_table)]
+        tables: Vec<TableRef>,
+        /// Results directory, local path or s3:// prefix
+        #[arg(long)]
+        out: String,
+        /// Bind values for $1, $2, ... as json array: [{"type": "integer", "value": 1}]
+        #[arg(long)]
+        params: Option<String>,
+        /// Max rows in the result
+        #[arg(long)]
+        limit: Option<u64>,
+        #[arg(long = "format", value_enum, default_values_t = [Format::Parquet, Format::Json])]
+        formats: Vec<Format>,
+    },
+    /// Profile a parquet table and write {request_id}.profile.json to a directory
+    Profile {
+        /// Local path or s3:// url of the table
+        path: String,
+        /// Results directory, local path or s3:// prefix
+        #[arg(long)]
+        out: String,
+        /// Column to profile, repeatable, all columns when not given
+        #[arg(long = "column")]
+        columns: Vec<String>,
+        /// Most frequent values per column
+        #[arg(long, default_value_t = PROFILE_TOP_K)]
+        top_k: usize,
+        /// Histogram bins of numeric and date columns
+        #[arg(long, default_value_t = PROFILE_BINS)]
+        bins: usize,
+    },
+    /// Print the schema of a parquet file or directory
+    Schema { path: String },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Parquet,
+    Json,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Parquet => OutputFormat::Parquet,
+            Format::Json => OutputFormat::Json,
+        }
+    }
+}
+
+impl Cli {
+    /// Stock Ballista executors don't resolve s3 credentials like fusion does and lack the domain udfs
+    /// and the sampled table codec, queries run on an in-process cluster or session only
+    pub fn check_mode(&self) -> Result<()> {
+        if !self.local && !self.standalone {
+            return Err(eyre!("remote Ballista clusters are not supported, use --standalone or --local"));
+        }
+        Ok(())
+    }
+
+    pub fn standalone_config(&self) -> StandaloneConfig {
+        let default = StandaloneConfig::default();
+        StandaloneConfig {
+            executors: self.executors,
+            concurrent_tasks: self.concurrent_tasks.unwrap_or(default.concurrent_tasks),
+            ..default
+        }
+    }
+}
+
+/// name=path
+pub fn parse_table(value: &str) -> Result<TableRef, String> {
+    let (name, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=path, got {value}"))?;
+    if name.is_empty() || path.is_empty() {
+        return Err(format!("expected name=path, got {value}"));
+    }
+    Ok(TableRef {
+        name: name.to_string(),
+        path: path.to_string(),
+        sample: None,
+    })
+}
+
+/// Local paths become absolute file:// urls, urls are kept as is
+pub fn to_url(path: &str) -> Result<String> {
+    if path.contains("://") {
+        return Ok(path.to_string());
+    }
+    let path = std::path::absolute(Path::new(path))?;
+    Ok(format!("file://{}", path.display()))
+}
+
+/// Spec for an ad hoc query, results are written as {out}/{request_id}.{extension}
+pub fn query_spec(
+    sql: String,
+    tables: Vec<TableRef>,
+    out: &str,
+    params: Option<&str>,
+    limit: Option<u64>,
+    formats: &[Format],
+) -> Result<JobSpec> {
+    let params: Vec<QueryParam> = match params {
+        Some(params) => serde_json::from_str(params).map_err(|e| eyre!("invalid --params: {e}"))?,
+        None => vec![],
+    };
+    let spec = JobSpec {
+        version: JOB_SPEC_VERSION,
+        request_id: local_request_id(),
+        query: sql,
+        params,
+        tables,
+        formats: formats.iter().copied().map(OutputFormat::from).collect(),
+        row_limit: limit,
+        output: output_url(out)?,
+        callback: None,
+        resources: Default::default(),
+        trace: Default::default(),
+        profile: None,
+    };
+    spec.validate()?;
+    Ok(spec)
+}
+
+/// Spec profiling the table at path, the profile is written as {out}/{request_id}.profile.json
+pub fn profile_spec(path: &str, out: &str, profile: ProfileSpec) -> Result<JobSpec> {
+    profile.validate()?;
+    let table = TableRef {
+        name: "t0".to_string(),
+        path: to_url(path)?,
+        sample: None,
+    };
+    let spec = JobSpec {
+        version: JOB_SPEC_VERSION,
+        request_id: local_request_id(),
+        query: String::new(),
+        params: vec![],
+        tables: vec![table],
+        formats: vec![],
+        row_limit: None,
+        output: output_url(out)?,
+        callback: None,
+        resources: Default::default(),
+        trace: Default::default(),
+        profile: Some(profile),
+    };
+    spec.validate()?;
+    Ok(spec)
+}
+
+fn local_request_id() -> String {
+    format!("local-{}", Utc::now().format("%Y%m%d%H%M%S%3f"))
+}
+
+/// Results directory as url ending with a slash
+fn output_url(out: &str) -> Result<String> {
+    let mut output = to_url(out)?;
+    if !output.ends_with('/') {
+        output.push('/');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("t0=/data/t0", true)]
+    #[case("t0=s3://bucket/t0/", true)]
+    #[case("t0", false)]
+    #[case("=/data/t0", false)]
+    #[case("t0=", false)]
+    fn parse_table_test(#[case] value: &str, #[case] is_ok: bool) {
+        assert_eq!(parse_table(value).is_ok(), is_ok);
+    }
+
+    #[test]
+    fn query_spec_test() {
+        let tables = vec![parse_table("t0=/data/t0").unwrap()];
+        let params = r#"[{"type": "integer", "value": 1}]"#;
+        let spec = query_spec(
+            "SELECT * FROM t0 WHERE a = $1".to_string(),
+            tables,
+            "s3://bucket/out",
+            Some(params),
+            Some(10),
+            &[Format::Parquet],
+        )
+        .unwrap();
+        assert_eq!(spec.output, "s3://bucket/out/");
+        assert_eq!(spec.formats, vec![OutputFormat::Parquet]);
+        assert_eq!(spec.params, vec![QueryParam::Integer(1)]);
+        assert!(spec.result_url("parquet").starts_with("s3://bucket/out/local-"));
+    }
+
+    #[test]
+    fn profile_spec_test() {
+        let profile = ProfileSpec { columns: Some(vec!["a".to_string()]), ..Default::default() };
+        let spec = profile_spec("s3://bucket/t0/", "s3://bucket/out", profile.clone()).unwrap();
+        assert_eq!(spec.tables[0].path, "s3://bucket/t0/");
+        assert_eq!(spec.profile, Some(profile));
+        assert!(spec.result_url("profile.json").starts_with("s3://bucket/out/local-"));
+        let profile = ProfileSpec { bins: 0, ..Default::default() };
+        assert!(profile_spec("s3://bucket/t0/", "s3://bucket/out", profile).is_err());
+    }
+
+    #[test]
+    fn cli_parse_test() {
+        let cli = Cli::try_parse_from([
+            "datalake-fusion", "--local", "query", "--sql", "SELECT 1", "--table", "a=/x", "--table", "b=/y", "--out", "/tmp/out",
+        ])
+        .unwrap();
+        assert!(cli.local);
+        let Command::Query { tables, formats, .. } = cli.command else {
+            panic!("expected query command");
+        };
+        assert_eq!(tables.len(), 2);
+        assert_eq!(formats, vec![Format::Parquet, Format::Json]);
+        assert!(Cli::try_parse_from(["datalake-fusion", "query", "--sql", "SELECT 1"]).is_err());
+    }
+
+    #[test]
+    fn cli_standalone_test() {
+        let cli = Cli::try_parse_from(["datalake-fusion", "--standalone", "--executors", "3", "run"]).unwrap();
+        assert!(cli.standalone);
+        assert_eq!(cli.standalone_config().executors, 3);
+        assert!(Cli::try_parse_from(["datalake-fusion", "--standalone", "--local", "run"]).is_err());
+        // docker-compose appends the flag to the image entrypoint
+        assert!(Cli::try_parse_from(["datalake-fusion", "run", "--standalone"]).unwrap().standalone);
+        assert!(Cli::try_parse_from(["datalake-fusion", "--scheduler", "localhost:50050", "run"]).is_err());
+    }
+
+    #[rstest]
+    #[case(&["datalake-fusion", "run"], false)]
+    #[case(&["datalake-fusion", "schema", "/tmp/t"], false)]
+    #[case(&["datalake-fusion", "--standalone", "run"], true)]
+    #[case(&["datalake-fusion", "run", "--standalone"], true)]
+    #[case(&["datalake-fusion", "schema", "--local", "/tmp/t"], true)]
+    fn cli_check_mode_test(#[case] args: &[&str], #[case] is_ok: bool) {
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.check_mode().is_ok(), is_ok);
+    }
+}
@@ -1,32 +1,189 @@
+pub mod cli;
 pub mod utils;
 
+use std::time::Instant;
+
 use color_eyre::Result;
-use datafusion::{dataframe::DataFrameWriteOptions, prelude::SessionContext};
+use datafusion::prelude::SessionContext;
+use serde::Deserialize;
+use tracing::Instrument;
 
-use crate::utils::constants::*;
+use crate::utils::jobspec::{JobSpec, OutputFormat};
+use crate::utils::manifest::ResultManifest;
+use crate::utils::metrics::MetricsRecorder;
+use crate::utils::params::param_values;
+use crate::utils::profile::{ProfileSpec, profile_table};
+use crate::utils::publish::{discard_staging, publish};
+use crate::utils::sampling::{TableSample, register_sampled};
+use crate::utils::sqlpolicy::SqlPolicy;
+use crate::utils::storage::{object_store, put_object};
+use crate::utils::telemetry::record_bytes_scanned;
+use crate::utils::tracing::query_hash;
 
-pub async fn handler(
-    ctx: SessionContext,
-    table_path: String, 
-    table_name: String,
-    request_id: String, 
-    query: String,
-) -> Result<()> {
-    dbg!("registering data path");
-    ctx.register_parquet(
-        table_name,
-        table_path,
-        Default::default(),
-    )
-    .await?;
-
-    let write_dir_path = &format!("s3://{BUCKET_TARGET}/{PREFIX_TARGET}{request_id}.parquet");
-    let write_dir_path2 = &format!("s3://{BUCKET_TARGET}/{PREFIX_TARGET}{request_id}.json");
-
-    dbg!("running task");
-    let df = ctx.sql(&query).await?;
-    df.clone().write_json(write_dir_path2, DataFrameWriteOptions::default(), None).await?;    
-    df.write_parquet(write_dir_path, Default::default(), Default::default()).await?;
+/// Table referenced in the query, name is generated by the lambda
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TableRef {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub sample: Option<TableSample>, // TABLESAMPLE of the table in the query
+}
+
+#[tracing::instrument(name = "query", skip_all, fields(request_id = %spec.request_id, query_hash = %query_hash(&spec.query)))]
+pub async fn handler(ctx: SessionContext, spec: &JobSpec) -> Result<ResultManifest> {
+    if spec.profile.is_none() {
+        tracing::info!("validating query");
+        SqlPolicy::from_env().validate(&spec.query)?;
+    }
+
+    let mut samples = vec![];
+    for table in &spec.tables {
+        let register = async {
+            let start = Instant::now();
+            match &table.sample {
+                Some(sample) => {
+                    let report = register_sampled(&ctx, table, sample).await?;
+                    tracing::info!({ seed = report.seed, row_groups = report.row_groups }, "sampled table");
+                    samples.push(report);
+                }
+                None => ctx.register_parquet(&table.name, &table.path, Default::default()).await?,
+            }
+            tracing::info!({ elapsed_ms = start.elapsed().as_millis() as u64 }, "registered table");
+            Ok::<_, color_eyre::Report>(())
+        };
+        register
+            .instrument(tracing::info_span!("register", table_name = %table.name, path = %table.path))
+            .await?;
+    }
+
+    if let Some(profile) = &spec.profile {
+        return write_profile(&ctx, spec, profile).instrument(tracing::info_span!("profile")).await;
+    }
+
+    let plan = async {
+        let start = Instant::now();
+        let mut df = ctx.sql_with_options(&spec.query, SqlPolicy::sql_options()).await?;
+        if !spec.params.is_empty() {
+            // values are bound to the plan, never spliced into the query text
+            df = df.with_param_values(param_values(&spec.params)?)?;
+        }
+        tracing::info!({ elapsed_ms = start.elapsed().as_millis() as u64 }, "planned query");
+        Ok::<_, color_eyre::Report>(df)
+    };
+    let mut df = plan.instrument(tracing::info_span!("plan")).await?;
+    if let Some(limit) = spec.row_limit {
+        df = df.limit(0, Some(limit as usize + 1))?;
+    }
+
+    // the query runs once, into a staged file of at most row_limit + 1 rows, the extra row
+    // only tells that the result was cut. Result files are written from that file
+    let mut recorder = MetricsRecorder::default();
+    // readers never see partial files, results are staged, verified, then copied
+    let published = async {
+        let source = spec.staging_url("source.parquet");
+        let rows = recorder.write("query", df, &source, OutputFormat::Parquet).await?;
+        let mut manifest = ResultManifest::new(spec.request_id.clone(), rows, spec.row_limit);
+        tracing::info!({ rows = manifest.rows, truncated = manifest.truncated }, "counted rows");
+        write_staged(&ctx, &mut recorder, spec, &source, manifest.truncated).await?;
+        manifest.files = publish(&ctx, spec, manifest.rows).await?;
+        Ok::<_, color_eyre::Report>(manifest)
+    }
+    .await;
+    let mut manifest = match published {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            discard_staging(&ctx, spec).await;
+            return Err(e);
+        }
+    };
+    manifest.samples = samples;
+    let report = recorder.report(&spec.request_id);
+    record_bytes_scanned(report.bytes_scanned());
+    report.write(&ctx, &spec.result_url("metrics.json")).await?;
+    // manifest is written last, its presence means results are complete
+    manifest.write(&ctx, &spec.result_url("manifest.json")).await?;
 
+    Ok(manifest)
+}
+
+/// Profile of the only table as {request_id}.profile.json, manifest rows are the rows of the table
+async fn write_profile(ctx: &SessionContext, spec: &JobSpec, profile: &ProfileSpec) -> Result<ResultManifest> {
+    let start = Instant::now();
+    let table = profile_table(ctx, &spec.tables[0], profile).await?;
+    tracing::info!({ rows = table.rows, columns = table.columns.len(), elapsed_ms = start.elapsed().as_millis() as u64 }, "profiled table");
+    put_object(ctx, &spec.result_url("profile.json"), serde_json::to_vec(&table)?).await?;
+    let manifest = ResultManifest::new(spec.request_id.clone(), table.rows, None);
+    manifest.write(ctx, &spec.result_url("manifest.json")).await?;
+    Ok(manifest)
+}
+
+/// Write the result formats from the staged query output. A complete parquet result is the
+/// output itself, everything else reads it back in file order, so every format has the same rows
+async fn write_staged(
+    ctx: &SessionContext,
+    recorder: &mut MetricsRecorder,
+    spec: &JobSpec,
+    source: &str,
+    truncated: bool,
+) -> Result<()> {
+    let mut state = ctx.state();
+    state.config_mut().options_mut().optimizer.repartition_file_scans = false;
+    let reader = SessionContext::new_with_state(state);
+    for format in &spec.formats {
+        let url = spec.staging_url(format.extension());
+        if *format == OutputFormat::Parquet && !truncated {
+            let (store, from) = object_store(ctx, source)?;
+            let (_, to) = object_store(ctx, &url)?;
+            store.copy(&from, &to).await?;
+            continue;
+        }
+        let mut df = reader.read_parquet(source, Default::default()).await?;
+        if truncated && let Some(limit) = spec.row_limit {
+            df = df.limit(0, Some(limit as usize))?;
+        }
+        recorder.write(&format!("write_{}", format.extension()), df, &url, *format).await?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::prelude::{NdJsonReadOptions, ParquetReadOptions};
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn handler_truncated_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-handler-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let spec: JobSpec = serde_json::from_value(json!({
+            "version": 1,
+            "request_id": "id-1",
+            // random rows, a second execution would return other ones
+            "query": "SELECT random() AS r FROM generate_series(1, 100) LIMIT 11",
+            "tables": [],
+            "formats": ["parquet", "json"],
+            "row_limit": 10,
+            "output": format!("file://{}/", dir.display()),
+        }))
+        .unwrap();
+        let ctx = SessionContext::new();
+        let manifest = handler(ctx.clone(), &spec).await.unwrap();
+        assert_eq!((manifest.rows, manifest.truncated), (10, true));
+        assert!(manifest.files.iter().all(|f| f.rows == 10));
+
+        let values = |sql: &'static str| {
+            let ctx = ctx.clone();
+            async move {
+                let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+                datafusion::arrow::util::pretty::pretty_format_batches(&batches).unwrap().to_string()
+            }
+        };
+        ctx.register_parquet("p", &spec.result_url("parquet"), ParquetReadOptions::default()).await.unwrap();
+        ctx.register_json("j", &spec.result_url("json"), NdJsonReadOptions::default()).await.unwrap();
+        assert_eq!(values("SELECT r FROM p ORDER BY r").await, values("SELECT r FROM j ORDER BY r").await);
+        assert!(!dir.join("id-1.staging").join("id-1.source.parquet").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,39 +1,151 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use awscreds::Credentials;
-use color_eyre::Result;
-use ballista::extension::SessionContextExt;
-use ballista_core::object_store::state_with_s3_support;
+use chrono::Utc;
+use clap::Parser;
+use color_eyre::{Result, eyre::eyre};
 use datafusion::prelude::SessionContext;
+use tokio::signal::unix::{SignalKind, signal};
 
+use datalake_fusion::cli::{Cli, Command, profile_spec, query_spec, to_url};
 use datalake_fusion::handler;
+use datalake_fusion::utils::callback::{CallbackConfig, CallbackPayload, deliver};
 use datalake_fusion::utils::constants::*;
+use datalake_fusion::utils::jobrecord::{CallbackAttempt, ErrorKind, JobRecord, JobStatus};
+use datalake_fusion::utils::jobspec::JobSpec;
+use datalake_fusion::utils::profile::ProfileSpec;
+use datalake_fusion::utils::resources::ResourceSpec;
+use datalake_fusion::utils::standalone::standalone_context;
+use datalake_fusion::utils::storage::{StorageConfig, state_with_storage};
+use datalake_fusion::utils::telemetry::{record_job, set_parent};
+use datalake_fusion::utils::tracing::init_tracing;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    cli.check_mode()?;
+    let telemetry = init_tracing()?;
+    let result = run(cli).await;
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+    result
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let storage = StorageConfig::from_env();
+    match &cli.command {
+        Command::Run { spec } => {
+            let spec_uri = spec
+                .clone()
+                .or_else(|| JOB_SPEC_URI.clone())
+                .ok_or_else(|| eyre!("--spec or JOB_SPEC_URI must be set."))?;
+            // the spec carries the runtime limits, it is read before the session is built
+            let bootstrap = SessionContext::new_with_state(state_with_storage(&storage, &ResourceSpec::default()).await?);
+            let spec = JobSpec::load(&bootstrap, &to_url(&spec_uri)?).await?;
+            let ctx = session(&cli, &storage, &spec.resources).await?;
+            run_job(ctx, spec).await
+        }
+        Command::Query { sql, tables, out, params, limit, formats } => {
+            let ctx = session(&cli, &storage, &ResourceSpec::default()).await?;
+            let spec = query_spec(sql.clone(), tables.clone(), out, params.as_deref(), *limit, formats)?;
+            let manifest = handler(ctx, &spec).await?;
+            for format in &spec.formats {
+                tracing::info!("written {}", spec.result_url(format.extension()));
+            }
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+            Ok(())
+        }
+        Command::Profile { path, out, columns, top_k, bins } => {
+            let ctx = session(&cli, &storage, &ResourceSpec::default()).await?;
+            let profile = ProfileSpec {
+                columns: (!columns.is_empty()).then(|| columns.clone()),
+                top_k: *top_k,
+                bins: *bins,
+            };
+            let spec = profile_spec(path, out, profile)?;
+            let manifest = handler(ctx, &spec).await?;
+            tracing::info!("written {}", spec.result_url("profile.json"));
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+            Ok(())
+        }
+        Command::Schema { path } => {
+            let ctx = session(&cli, &storage, &ResourceSpec::default()).await?;
+            let df = ctx.read_parquet(to_url(path)?, Default::default()).await?;
+            for field in df.schema().fields() {
+                let nullable = if field.is_nullable() { "" } else { " not null" };
+                println!("{}: {}{nullable}", field.name(), field.data_type());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Ballista session, in-process cluster with --standalone or plain DataFusion one with --local
+async fn session(cli: &Cli, storage: &StorageConfig, resources: &ResourceSpec) -> Result<SessionContext> {
+    let state = state_with_storage(storage, resources).await?;
+    let ctx = if cli.local {
+        tracing::info!("running locally");
+        SessionContext::new_with_state(state)
+    } else {
+        let config = cli.standalone_config();
+        tracing::info!("starting standalone cluster: {config:?}");
+        standalone_context(state, &config).await?
+    };
+    Ok(ctx)
+}
+
+/// Run the spec, then update the job record and notify the callback
+#[tracing::instrument(name = "job", skip_all, fields(request_id = %spec.request_id, xray_trace_id = spec.trace.xray_trace_id))]
+async fn run_job(ctx: SessionContext, spec: JobSpec) -> Result<()> {
     let now = Instant::now();
-    dbg!("initing state");
-    let creds = Credentials::default()?;
-    let aws_access_key_id = creds.access_key.unwrap_or_default();
-    let aws_secret_access_key = creds.secret_key.unwrap_or_default();
-    let aws_session_token = creds.security_token.unwrap_or_default();
-    let state = state_with_s3_support()?;
-    let url = format!("df://{URL}");
-    dbg!(&url);
-    let ctx = SessionContext::remote_with_state(&url, state).await?;
-    ctx.sql(&format!("SET s3.access_key_id = '{aws_access_key_id}'")).await?;
-    ctx.sql(&format!("SET s3.secret_access_key = '{aws_secret_access_key}'")).await?;
-    ctx.sql(&format!("SET s3.session_token = '{aws_session_token}'")).await?;
-    let table_path = TABLE_PATH.to_string();
-    dbg!(&table_path);
-    let table_name = TABLE_NAME.to_string();
-    dbg!(&table_name);
-    let query = QUERY.to_string();
-    dbg!(&query);
-    let request_id = REQUEST_ID.to_string();
-    dbg!(&request_id);
-    dbg!("starting handler");
-    handler(ctx, table_path, table_name, request_id, query).await?;
-    dbg!("finishing handler, elapsed: {:.2?}", now.elapsed());
-    Ok(())
+    set_parent(&tracing::Span::current(), spec.trace.traceparent.as_deref());
+    // created by the lambda right before the task was launched
+    let mut record = JobRecord::load_or_new(&ctx, &spec).await?;
+    let queue_time = (Utc::now() - record.created_at).to_std().ok();
+    tracing::info!({ queue_ms = queue_time.map(|t| t.as_millis() as u64) }, "starting job");
+    // ecs stops the task with SIGTERM, the job is reported as cancelled then
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let timeout = spec.resources.timeout().unwrap_or(Duration::MAX);
+    let (status, manifest, error) = tokio::select! {
+        result = tokio::time::timeout(timeout, handler(ctx.clone(), &spec)) => match result {
+            Ok(Ok(manifest)) => (JobStatus::Succeeded, Some(manifest), None),
+            Ok(Err(e)) => (JobStatus::Failed, None, Some((ErrorKind::classify(&e), e.to_string()))),
+            Err(_) => (JobStatus::Failed, None, Some((ErrorKind::Timeout, format!("query timed out after {timeout:?}")))),
+        },
+        _ = sigterm.recv() => (JobStatus::Cancelled, None, Some((ErrorKind::Internal, "task was stopped".to_string()))),
+        _ = tokio::signal::ctrl_c() => (JobStatus::Cancelled, None, Some((ErrorKind::Internal, "task was interrupted".to_string()))),
+    };
+    let elapsed_ms = now.elapsed().as_millis() as u64;
+    record_job(status, error.as_ref().map(|(kind, _)| *kind), now.elapsed(), queue_time);
+    match &error {
+        Some((kind, e)) => tracing::error!({ ?status, error_kind = ?kind, elapsed_ms }, "job failed: {e}"),
+        None => tracing::info!({ ?status, elapsed_ms }, "job finished"),
+    }
+
+    record.finish(status, error.clone());
+    if let Some(callback) = &spec.callback {
+        let payload = CallbackPayload {
+            request_id: spec.request_id.clone(),
+            status,
+            manifest,
+            result_urls: callback.result_urls.clone(),
+            error: error.as_ref().map(|(_, e)| e.clone()),
+            error_kind: error.as_ref().map(|(kind, _)| *kind),
+            finished_at: record.updated_at,
+        };
+        record.callback_url = Some(callback.url.clone());
+        match CallbackConfig::new(callback) {
+            Ok(config) => record.callback_attempts.extend(deliver(&config, &payload).await),
+            Err(e) => {
+                tracing::error!("callback not delivered: {e}");
+                record.callback_attempts.push(CallbackAttempt { attempt: 1, at: Utc::now(), status_code: None, error: Some(e.to_string()) });
+            }
+        }
+    }
+    record.save(&ctx, &spec).await?;
+
+    match error {
+        Some((kind, e)) => Err(eyre!("{kind:?}: {e}")),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,409 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use aes_gcm::{Aes256Gcm, Key, aead::Aead};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Utc};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, eyre},
+};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::net::lookup_host;
+use url::{Host, Url};
+
+use crate::utils::constants::*;
+use crate::utils::jobrecord::{CallbackAttempt, ErrorKind, JobStatus};
+use crate::utils::jobspec::CallbackSpec;
+use crate::utils::manifest::ResultManifest;
+
+pub const SIGNATURE_HEADER: &str = "x-datalake-signature";
+pub const TIMESTAMP_HEADER: &str = "x-datalake-timestamp";
+
+/// Where and how to deliver the completion webhook
+#[derive(Debug, Clone)]
+pub struct CallbackConfig {
+    pub url: String,
+    pub secret: Option<String>,
+    pub max_attempts: u32,
+    pub backoff: Duration, // doubled after every failed attempt
+    pub timeout: Duration,
+    pub allow_private: bool, // loopback, link-local and private addresses, for tests and local runs
+}
+
+impl CallbackConfig {
+    /// Fails when the spec has a secret which can't be decrypted with CALLBACK_SECRET_KEY
+    pub fn new(spec: &CallbackSpec) -> Result<Self> {
+        let secret = match &spec.encrypted_secret {
+            Some(encrypted) => {
+                let key = CALLBACK_SECRET_KEY.as_ref().ok_or_eyre("callback secret is set but CALLBACK_SECRET_KEY is not")?;
+                Some(decrypt_secret(key, encrypted)?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            url: spec.url.clone(),
+            secret,
+            max_attempts: CALLBACK_MAX_ATTEMPTS,
+            backoff: Duration::from_millis(CALLBACK_BACKOFF_MS),
+            timeout: Duration::from_secs(CALLBACK_TIMEOUT),
+            allow_private: *ALLOW_PRIVATE_CALLBACKS,
+        })
+    }
+}
+
+/// Secret encrypted by the lambda, base64(nonce || aes-256-gcm ciphertext)
+pub fn decrypt_secret(key: &[u8; 32], encrypted: &str) -> Result<String> {
+    use aes_gcm::KeyInit; // hmac's Mac has new_from_slice too
+
+    let data = STANDARD.decode(encrypted)?;
+    let Some((nonce, ciphertext)) = data.split_at_checked(12) else {
+        return Err(eyre!("callback secret is too short"));
+    };
+    let nonce: [u8; 12] = nonce.try_into()?;
+    let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(*key));
+    let secret = cipher
+        .decrypt(&nonce.into(), ciphertext)
+        .map_err(|_| eyre!("callback secret can't be decrypted"))?;
+    Ok(String::from_utf8(secret)?)
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CallbackPayload {
+    pub request_id: String,
+    pub status: JobStatus,
+    pub manifest: Option<ResultManifest>,
+    pub result_urls: Option<Value>, // presigned by the lambda when the query was submitted
+    pub error: Option<String>,
+    pub error_kind: Option<ErrorKind>,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// sha256=hex(hmac_sha256(secret, "{timestamp}.{body}")), timestamp protects from replays
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Address the callback host is pinned to, None for ip urls. The lambda checked the url at submit time,
+/// the host may resolve elsewhere by now
+pub async fn resolve_public(url: &str, allow_private: bool) -> Result<Option<(String, SocketAddr)>> {
+    let url = Url::parse(url)?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let (domain, addrs): (Option<&str>, Vec<SocketAddr>) = match url.host() {
+        Some(Host::Ipv4(ip)) => (None, vec![SocketAddr::new(IpAddr::V4(ip), port)]),
+        Some(Host::Ipv6(ip)) => (None, vec![SocketAddr::new(IpAddr::V6(ip), port)]),
+        Some(Host::Domain(domain)) => (Some(domain), lookup_host((domain, port)).await?.collect()),
+        None => return Err(eyre!("callback url without host: {url}")),
+    };
+    if !allow_private && let Some(addr) = addrs.iter().find(|addr| !is_public(&addr.ip())) {
+        return Err(eyre!("callback url resolves to a non public address: {url}: {}", addr.ip()));
+    }
+    match (domain, addrs.first()) {
+        (_, None) => Err(eyre!("callback host doesn't resolve: {url}")),
+        (Some(domain), Some(addr)) => Ok(Some((domain.to_string(), *addr))),
+        (None, Some(_)) => Ok(None),
+    }
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(&v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+/// Same ranges the lambda rejects when the query is submitted
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local() // 169.254.0.0/16, ec2 and ecs metadata endpoints
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64) // 100.64.0.0/10, carrier-grade nat
+        || (a == 198 && (b & 0xfe) == 18) // 198.18.0.0/15, benchmarking
+        || a >= 240)
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local() // fc00::/7, includes the ecs metadata fd00:ec2::254
+        || ip.is_unicast_link_local()
+        || ip.segments()[0] == 0x2001 && ip.segments()[1] == 0xdb8) // documentation
+}
+
+/// POST payload until it is accepted, server errors, 408 and 429 are retried, other 4xx are final.
+/// Redirects are not followed, the connection goes to the address checked before the first attempt
+pub async fn deliver(config: &CallbackConfig, payload: &CallbackPayload) -> Vec<CallbackAttempt> {
+    let mut attempts = vec![];
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            attempts.push(CallbackAttempt { attempt: 1, at: Utc::now(), status_code: None, error: Some(e.to_string()) });
+            return attempts;
+        }
+    };
+    let pinned = match resolve_public(&config.url, config.allow_private).await {
+        Ok(pinned) => pinned,
+        Err(e) => {
+            attempts.push(CallbackAttempt { attempt: 1, at: Utc::now(), status_code: None, error: Some(e.to_string()) });
+            return attempts;
+        }
+    };
+    let mut builder = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some((domain, addr)) = &pinned {
+        builder = builder.resolve(domain, *addr);
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            attempts.push(CallbackAttempt { attempt: 1, at: Utc::now(), status_code: None, error: Some(e.to_string()) });
+            return attempts;
+        }
+    };
+
+    let mut backoff = config.backoff;
+    for attempt in 1..=config.max_attempts {
+        let at = Utc::now();
+        let mut request = client
+            .post(&config.url)
+            .header("content-type", "application/json")
+            .header(TIMESTAMP_HEADER, at.timestamp().to_string())
+            .body(body.clone());
+        if let Some(secret) = &config.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, at.timestamp(), &body));
+        }
+        let (status_code, error, retry) = match request.send().await {
+            Ok(resp) => {
+                let status = resp.status();
+                let retry = status.is_server_error() || status.as_u16() == 408 || status.as_u16() == 429;
+                let error = (!status.is_success()).then(|| format!("http status {status}"));
+                (Some(status.as_u16()), error, retry)
+            }
+            Err(e) => (None, Some(e.to_string()), true),
+        };
+        let done = error.is_none() || !retry;
+        tracing::info!({ attempt, status_code, error }, "callback delivered");
+        attempts.push(CallbackAttempt { attempt, at, status_code, error });
+        if done {
+            break;
+        }
+        if attempt < config.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    attempts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Received request headers (lowercase) and body
+    type Received = Arc<Mutex<Vec<(Vec<(String, String)>, Vec<u8>)>>>;
+
+    /// HTTP stub answering with given status codes, one per request
+    async fn stub_server(statuses: Vec<u16>) -> (String, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received: Received = Arc::new(Mutex::new(vec![]));
+        let received_clone = received.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut data = vec![];
+                let mut buf = [0; 4096];
+                let (head_len, content_length) = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                    if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&data[..pos]).to_lowercase();
+                        let content_length = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("content-length:"))
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                            .unwrap_or_default();
+                        break (pos + 4, content_length);
+                    }
+                };
+                while data.len() < head_len + content_length {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                }
+                let head = String::from_utf8_lossy(&data[..head_len]).to_string();
+                let headers = head
+                    .lines()
+                    .skip(1)
+                    .filter_map(|l| l.split_once(':'))
+                    .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+                    .collect();
+                let body = data[head_len..head_len + content_length].to_vec();
+                received_clone.lock().unwrap().push((headers, body));
+                // redirects point to the metadata endpoint, they must not be followed
+                let resp = format!(
+                    "HTTP/1.1 {status} X\r\nlocation: http://169.254.170.2/v2/credentials\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                );
+                socket.write_all(resp.as_bytes()).await.unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    fn config(url: String) -> CallbackConfig {
+        CallbackConfig {
+            url,
+            secret: Some("secret".to_string()),
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_secs(5),
+            allow_private: true, // stub server listens on loopback
+        }
+    }
+
+    fn payload() -> CallbackPayload {
+        CallbackPayload {
+            request_id: "id".to_string(),
+            status: JobStatus::Succeeded,
+            manifest: Some(ResultManifest::new("id".to_string(), 10, Some(1000))),
+            result_urls: None,
+            error: None,
+            error_kind: None,
+            finished_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_retries_and_signs_test() {
+        let (url, received) = stub_server(vec![500, 200]).await;
+        let attempts = deliver(&config(url), &payload()).await;
+        let codes: Vec<Option<u16>> = attempts.iter().map(|a| a.status_code).collect();
+        assert_eq!(codes, vec![Some(500), Some(200)]);
+        assert!(attempts[1].error.is_none());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        let header = |name: &str| headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone()).unwrap();
+        let timestamp: i64 = header(TIMESTAMP_HEADER).parse().unwrap();
+        assert_eq!(header(SIGNATURE_HEADER), sign("secret", timestamp, body));
+        let body: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body["status"], "succeeded");
+        assert_eq!(body["manifest"]["rows"], 10);
+    }
+
+    #[tokio::test]
+    async fn deliver_stops_on_client_error_test() {
+        let (url, _received) = stub_server(vec![404]).await;
+        let attempts = deliver(&config(url), &payload()).await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status_code, Some(404));
+    }
+
+    #[tokio::test]
+    async fn deliver_gives_up_test() {
+        let (url, _received) = stub_server(vec![503, 503, 503]).await;
+        let attempts = deliver(&config(url), &payload()).await;
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts.iter().all(|a| a.error.is_some()));
+    }
+
+    #[tokio::test]
+    async fn deliver_does_not_follow_redirects_test() {
+        let (url, received) = stub_server(vec![302]).await;
+        let attempts = deliver(&config(url), &payload()).await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status_code, Some(302));
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deliver_pinned_host_test() {
+        let (url, received) = stub_server(vec![200]).await;
+        let url = url.replace("127.0.0.1", "localhost");
+        let (domain, addr) = resolve_public(&url, true).await.unwrap().unwrap();
+        assert_eq!((domain.as_str(), addr.ip().is_loopback()), ("localhost", true));
+        // stub listens on ipv4 only
+        if addr.is_ipv4() {
+            let attempts = deliver(&config(url), &payload()).await;
+            assert_eq!(attempts[0].status_code, Some(200));
+            assert_eq!(received.lock().unwrap().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_rejects_private_address_test() {
+        let (url, received) = stub_server(vec![200]).await;
+        let config = CallbackConfig { allow_private: false, ..config(url) };
+        let attempts = deliver(&config, &payload()).await;
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status_code, None);
+        assert!(attempts[0].error.as_ref().unwrap().contains("non public address"));
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[rstest]
+    #[case("http://169.254.169.254/latest/meta-data/", false)]
+    #[case("http://169.254.170.2/v2/credentials", false)]
+    #[case("http://10.1.2.3:8080/hook", false)]
+    #[case("http://[fd00:ec2::254]/hook", false)]
+    #[case("http://[::ffff:127.0.0.1]/hook", false)]
+    #[case("http://localhost/hook", false)]
+    #[case("https://8.8.8.8/hook", true)]
+    #[tokio::test]
+    async fn resolve_public_test(#[case] url: &str, #[case] is_ok: bool) {
+        assert_eq!(resolve_public(url, false).await.is_ok(), is_ok);
+        assert!(resolve_public(url, true).await.is_ok());
+    }
+
+    #[test]
+    fn decrypt_secret_test() {
+        use aes_gcm::{KeyInit, aead::{AeadCore, OsRng}};
+
+        let key = [7; 32];
+        let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(key));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, b"s3cr3t".as_slice()).unwrap();
+        let encrypted = STANDARD.encode([&nonce[..], &ciphertext].concat());
+
+        assert_eq!(decrypt_secret(&key, &encrypted).unwrap(), "s3cr3t");
+        assert!(decrypt_secret(&[8; 32], &encrypted).is_err());
+        assert!(decrypt_secret(&key, "c2hvcnQ=").is_err());
+        assert!(decrypt_secret(&key, "not base64").is_err());
+    }
+
+    #[test]
+    fn sign_test() {
+        assert_eq!(
+            sign("secret", 1700000000, b"{}"),
+            "sha256=".to_string() + &hex::encode({
+                let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+                mac.update(b"1700000000.{}");
+                mac.finalize().into_bytes()
+            })
+        );
+    }
+}
@@ -1,63 +1,89 @@
 use std::{env as std_env, sync::LazyLock};
 
+use base64::{Engine, engine::general_purpose::STANDARD};
 use dotenvy::dotenv;
 
 pub mod env {
-    pub const TABLE_PATH_ENV_VAR: &str = "TABLE_PATH";
-    pub const TABLE_NAME_ENV_VAR: &str = "TABLE_NAME";
-    pub const REQ_ID_ENV_VAR: &str = "REQUEST_ID"; 
-    pub const QUERY_ENV_VAR: &str = "QUERY";
+    pub const JOB_SPEC_URI_ENV_VAR: &str = "JOB_SPEC_URI";
+    pub const S3_ENDPOINT_ENV_VAR: &str = "S3_ENDPOINT";
+    pub const DENIED_FUNCTIONS_ENV_VAR: &str = "DENIED_FUNCTIONS";
+    pub const CALLBACK_SECRET_KEY_ENV_VAR: &str = "CALLBACK_SECRET_KEY";
+    pub const ALLOW_PRIVATE_CALLBACKS_ENV_VAR: &str = "ALLOW_PRIVATE_CALLBACKS";
+    pub const OTEL_ENDPOINT_ENV_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
 }
 
 pub const BUCKET_TARGET: &str = "bucket";
 pub const PREFIX_TARGET: &str = "prefix";
 pub const REGION: &str = "eu-central-1";
 pub const TABLE: &str = "table";
-pub const URL: &str = "localhost:50050";
-pub const CHUNK_SIZE: u64 = 10_000_000; // 10 MiB
-pub const PARALLEL_THRESHOLD: u64 = 300_000_000; // 300 MiB
-pub const CHUNKS_WORKERS: usize = 10; // max workers chunks for file
 pub const MAX_ATTEMPTS: usize = 5;
-pub const AWS_MAX_RETRIES: u32 = 10;
-pub const MAX_CHUNKS: u64 = 10_000; // 10 GiB
-pub const CHUNKS_MAX_RETRY: u64 = 5; // max retry for chunk
+pub const CHUNK_SIZE: usize = 10 * 1024 * 1024; // 10 MiB, writer buffer and size of uploaded parts
+pub const CHUNKS_WORKERS: usize = 4; // parts uploaded concurrently per file
+pub const CHUNKS_MAX_RETRY: usize = 5; // retries of every s3 request, uploaded parts included
+pub const CHUNK_RETRY_BACKOFF_MS: u64 = 200; // doubled after every failed attempt
+pub const CALLBACK_MAX_ATTEMPTS: u32 = 5;
+pub const CALLBACK_BACKOFF_MS: u64 = 500; // doubled after every failed delivery
+pub const CALLBACK_TIMEOUT: u64 = 10; // seconds per delivery attempt
+pub const CREDENTIALS_REFRESH_MARGIN: u64 = 300; // seconds before expiry credentials are renewed
+pub const STANDALONE_EXECUTORS: usize = 1;
+pub const STANDALONE_READY_TIMEOUT: u64 = 30; // seconds for in-process scheduler and executors to start
+pub const FOOTER_READS: usize = 16; // parquet footers read concurrently when sampling or profiling a table
+pub const PROFILE_TOP_K: usize = 10; // most frequent values of every column in a profile
+pub const PROFILE_MAX_TOP_K: usize = 100;
+pub const PROFILE_BINS: usize = 20; // histogram bins of numeric and date columns
+pub const PROFILE_MAX_BINS: usize = 100;
 
-pub static TABLE_PATH: LazyLock<String> = LazyLock::new(|| {
+/// Job spec written by the lambda, s3://bucket/prefix/{request_id}.spec.json
+pub static JOB_SPEC_URI: LazyLock<Option<String>> = LazyLock::new(|| {
     dotenv().ok();
-    let secret = std_env::var(env::TABLE_PATH_ENV_VAR)
-        .expect("TABLE_PATH_ENV_VAR must be set.");
-    if secret.is_empty() {
-        panic!("TABLE_PATH_ENV_VAR must not be empty.");
-    }
-    secret
+    std_env::var(env::JOB_SPEC_URI_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
 });
 
-pub static TABLE_NAME: LazyLock<String> = LazyLock::new(|| {
+/// Custom S3 endpoint (MinIO, localstack), requests use path-style addressing when set
+pub static S3_ENDPOINT: LazyLock<Option<String>> = LazyLock::new(|| {
     dotenv().ok();
-    let secret = std_env::var(env::TABLE_NAME_ENV_VAR)
-        .expect("TABLE_NAME_ENV_VAR must be set.");
-    if secret.is_empty() {
-        panic!("TABLE_NAME_ENV_VAR must not be empty.");
-    }
-    secret
+    std_env::var(env::S3_ENDPOINT_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
 });
 
-pub static REQUEST_ID: LazyLock<String> = LazyLock::new(|| {
+/// Functions user queries may not call, comma separated list, case insensitive
+pub static DENIED_FUNCTIONS: LazyLock<Vec<String>> = LazyLock::new(|| {
     dotenv().ok();
-    let secret = std_env::var(env::REQ_ID_ENV_VAR)
-        .expect("REQUEST_ID must be set.");
-    if secret.is_empty() {
-        panic!("REQUEST_ID must not be empty.");
-    }
-    secret
+    std_env::var(env::DENIED_FUNCTIONS_ENV_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
 });
 
-pub static QUERY: LazyLock<String> = LazyLock::new(|| {
+/// OTLP/HTTP collector, http://localhost:4318, traces and metrics are exported when set and built with the otel feature
+pub static OTEL_ENDPOINT: LazyLock<Option<String>> = LazyLock::new(|| {
     dotenv().ok();
-    let secret = std_env::var(env::QUERY_ENV_VAR)
-        .expect("QUERY_ENV_VAR must be set.");
-    if secret.is_empty() {
-        panic!("QUERY_ENV_VAR must not be empty.");
-    }
-    secret
+    std_env::var(env::OTEL_ENDPOINT_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+});
+
+/// Base64 aes-256 key shared with the lambda, decrypts the callback secret of the job spec
+pub static CALLBACK_SECRET_KEY: LazyLock<Option<[u8; 32]>> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::CALLBACK_SECRET_KEY_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            STANDARD.decode(v.trim()).ok()
+                .and_then(|key| key.try_into().ok())
+                .expect("CALLBACK_SECRET_KEY must be a base64 32 byte key.")
+        })
+});
+
+/// Deliver callbacks to loopback, link-local and private addresses, for tests and local runs
+pub static ALLOW_PRIVATE_CALLBACKS: LazyLock<bool> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::ALLOW_PRIVATE_CALLBACKS_ENV_VAR)
+        .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
 });
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::Region;
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use object_store::CredentialProvider;
+use object_store::aws::AwsCredential;
+use tokio::sync::Mutex;
+
+use crate::utils::constants::*;
+
+/// Object store credentials from the AWS default chain: env, profile, web identity,
+/// ECS container and instance metadata. Credentials are cached and refreshed before they expire,
+/// so long queries keep working after the session token of the task role rotates
+pub struct AwsCredentialProvider {
+    chain: SharedCredentialsProvider,
+    cached: Mutex<Option<(Arc<AwsCredential>, Option<SystemTime>)>>,
+}
+
+impl AwsCredentialProvider {
+    pub fn new(chain: SharedCredentialsProvider) -> Self {
+        Self {
+            chain,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub async fn from_default_chain(region: &str) -> Self {
+        let chain = DefaultCredentialsChain::builder()
+            .region(Region::new(region.to_string()))
+            .build()
+            .await;
+        Self::new(SharedCredentialsProvider::new(chain))
+    }
+}
+
+// credentials never end up in logs
+impl fmt::Debug for AwsCredentialProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AwsCredentialProvider").finish_non_exhaustive()
+    }
+}
+
+/// Credentials without expiry (static keys) are never refreshed
+pub fn needs_refresh(expiry: Option<SystemTime>, now: SystemTime) -> bool {
+    expiry.is_some_and(|expiry| expiry <= now + Duration::from_secs(CREDENTIALS_REFRESH_MARGIN))
+}
+
+#[async_trait]
+impl CredentialProvider for AwsCredentialProvider {
+    type Credential = AwsCredential;
+
+    async fn get_credential(&self) -> object_store::Result<Arc<AwsCredential>> {
+        let mut cached = self.cached.lock().await;
+        if let Some((credential, expiry)) = cached.as_ref()
+            && !needs_refresh(*expiry, SystemTime::now())
+        {
+            return Ok(credential.clone());
+        }
+        let credentials = self
+            .chain
+            .provide_credentials()
+            .await
+            .map_err(|e| object_store::Error::Generic {
+                store: "S3",
+                source: Box::new(e),
+            })?;
+        let credential = Arc::new(AwsCredential {
+            key_id: credentials.access_key_id().to_string(),
+            secret_key: credentials.secret_access_key().to_string(),
+            token: credentials.session_token().map(str::to_string),
+        });
+        *cached = Some((credential.clone(), credentials.expiry()));
+        Ok(credential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_credential_types::Credentials;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(None, false)]
+    #[case(Some(3600), false)]
+    #[case(Some(60), true)]
+    #[case(Some(0), true)]
+    fn needs_refresh_test(#[case] expires_in: Option<u64>, #[case] expected: bool) {
+        let now = SystemTime::now();
+        let expiry = expires_in.map(|secs| now + Duration::from_secs(secs));
+        assert_eq!(needs_refresh(expiry, now), expected);
+    }
+
+    fn provider(expires_in: u64) -> AwsCredentialProvider {
+        let expiry = SystemTime::now() + Duration::from_secs(expires_in);
+        let credentials = Credentials::new("key", "secret", Some("token".to_string()), Some(expiry), "test");
+        AwsCredentialProvider::new(SharedCredentialsProvider::new(credentials))
+    }
+
+    #[tokio::test]
+    async fn get_credential_cached_test() {
+        let provider = provider(3600);
+        let first = provider.get_credential().await.unwrap();
+        let second = provider.get_credential().await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.key_id, "key");
+        assert_eq!(first.token.as_deref(), Some("token"));
+    }
+
+    #[tokio::test]
+    async fn get_credential_refreshed_test() {
+        let provider = provider(10);
+        let first = provider.get_credential().await.unwrap();
+        let second = provider.get_credential().await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(!format!("{provider:?}").contains("secret"));
+    }
+}
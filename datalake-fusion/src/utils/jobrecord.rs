@@ -0,0 +1,149 @@
+use chrono::{DateTime, Utc};
+use color_eyre::{Report, Result};
+use datafusion::error::DataFusionError;
+use datafusion::prelude::SessionContext;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::jobspec::{JobSpec, JobSpecError};
+use crate::utils::publish::PublishError;
+use crate::utils::sqlpolicy::SqlPolicyError;
+use crate::utils::storage::{get_object, put_object};
+
+/// Same format as the lambda job record
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Submitted,
+    Succeeded,
+    Failed,
+    Cancelled,
+    Expired,
+}
+
+/// Why the job failed, reported with the error message
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    ResourcesExhausted, // memory pool or spill space limit
+    Timeout,
+    Query, // invalid query, plan or job spec
+    Storage,
+    Launch, // set by the lambda when the task could not be started
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn classify(report: &Report) -> Self {
+        for cause in report.chain() {
+            if let Some(e) = cause.downcast_ref::<DataFusionError>() {
+                return match e.find_root() {
+                    DataFusionError::ResourcesExhausted(_) => ErrorKind::ResourcesExhausted,
+                    DataFusionError::ObjectStore(_) => ErrorKind::Storage,
+                    DataFusionError::SQL(..)
+                    | DataFusionError::Plan(_)
+                    | DataFusionError::SchemaError(..)
+                    | DataFusionError::NotImplemented(_) => ErrorKind::Query,
+                    // errors from ballista executors arrive as text
+                    e if e.to_string().contains("Resources exhausted") => ErrorKind::ResourcesExhausted,
+                    _ => ErrorKind::Internal,
+                };
+            }
+            if cause.is::<object_store::Error>() || cause.is::<PublishError>() {
+                return ErrorKind::Storage;
+            }
+            if cause.is::<JobSpecError>() || cause.is::<SqlPolicyError>() {
+                return ErrorKind::Query;
+            }
+        }
+        ErrorKind::Internal
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CallbackAttempt {
+    pub attempt: u32,
+    pub at: DateTime<Utc>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// State of a query job, {request_id}.job.json, created by the lambda and updated here
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct JobRecord {
+    pub request_id: String,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub principal: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub row_limit: Option<u64>,
+    #[serde(default)]
+    pub expired_reason: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_kind: Option<ErrorKind>,
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    #[serde(default)]
+    pub callback_attempts: Vec<CallbackAttempt>,
+}
+
+impl JobRecord {
+    pub fn new(request_id: &str, row_limit: Option<u64>) -> Self {
+        let now = Utc::now();
+        Self {
+            request_id: request_id.to_string(),
+            status: JobStatus::Submitted,
+            principal: None,
+            created_at: now,
+            updated_at: now,
+            row_limit,
+            expired_reason: None,
+            error: None,
+            error_kind: None,
+            callback_url: None,
+            callback_attempts: vec![],
+        }
+    }
+
+    /// Record written by the lambda, new one when the task was started without it
+    pub async fn load_or_new(ctx: &SessionContext, spec: &JobSpec) -> Result<Self> {
+        match get_object(ctx, &spec.result_url("job.json")).await? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(Self::new(&spec.request_id, spec.row_limit)),
+        }
+    }
+
+    /// Record is kept next to the results of the spec
+    pub async fn save(&self, ctx: &SessionContext, spec: &JobSpec) -> Result<()> {
+        put_object(ctx, &spec.result_url("job.json"), serde_json::to_vec(self)?).await
+    }
+
+    pub fn finish(&mut self, status: JobStatus, error: Option<(ErrorKind, String)>) {
+        self.status = status;
+        self.error_kind = error.as_ref().map(|(kind, _)| *kind);
+        self.error = error.map(|(_, message)| message);
+        self.updated_at = Utc::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(DataFusionError::ResourcesExhausted("Failed to allocate".to_string()).into(), ErrorKind::ResourcesExhausted)]
+    #[case(DataFusionError::Context("sort".to_string(), Box::new(DataFusionError::ResourcesExhausted("pool".to_string()))).into(), ErrorKind::ResourcesExhausted)]
+    #[case(DataFusionError::Execution("Resources exhausted: Failed to allocate".to_string()).into(), ErrorKind::ResourcesExhausted)]
+    #[case(DataFusionError::Plan("table not found".to_string()).into(), ErrorKind::Query)]
+    #[case(JobSpecError::EmptyQuery.into(), ErrorKind::Query)]
+    #[case(eyre!("something else"), ErrorKind::Internal)]
+    fn error_kind_classify_test(#[case] report: Report, #[case] expected: ErrorKind) {
+        assert_eq!(ErrorKind::classify(&report), expected);
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use color_eyre::{Result, eyre::eyre};
+use datafusion::prelude::SessionContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::TableRef;
+use crate::utils::params::QueryParam;
+use crate::utils::profile::ProfileSpec;
+use crate::utils::resources::ResourceSpec;
+use crate::utils::storage::get_object;
+
+/// Spec versions this build can run
+pub const JOB_SPEC_VERSION: u32 = 1;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum JobSpecError {
+    #[error("unsupported job spec version {0}, expected {JOB_SPEC_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("invalid request id: {0}")]
+    InvalidRequestId(String),
+    #[error("job spec has no query")]
+    EmptyQuery,
+    #[error("job spec has no result formats")]
+    NoFormats,
+    #[error("table {0} is registered twice")]
+    DuplicateTable(String),
+    #[error("invalid output location: {0}")]
+    InvalidOutput(String),
+    #[error("profile job needs one table, got {0}")]
+    ProfileTables(usize),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Parquet,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+pub struct CallbackSpec {
+    pub url: String,
+    #[serde(default)]
+    pub encrypted_secret: Option<String>, // base64(nonce || aes-256-gcm ciphertext), see CALLBACK_SECRET_KEY
+    #[serde(default)]
+    pub result_urls: Option<Value>,
+}
+
+// spec is dumped to the task log, the secret is not
+impl fmt::Debug for CallbackSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackSpec")
+            .field("url", &self.url)
+            .field("encrypted_secret", &self.encrypted_secret.as_ref().map(|_| "***"))
+            .field("result_urls", &self.result_urls)
+            .finish()
+    }
+}
+
+/// Trace of the lambda request which started the job, logged with every event of the job
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct TraceContext {
+    pub xray_trace_id: Option<String>,
+    pub traceparent: Option<String>, // w3c trace context of the lambda span, set with the otel feature
+}
+
+/// Job written by the lambda as {request_id}.spec.json, the task gets its uri in JOB_SPEC_URI
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct JobSpec {
+    pub version: u32,
+    pub request_id: String,
+    pub query: String,
+    #[serde(default)]
+    pub params: Vec<QueryParam>,
+    pub tables: Vec<TableRef>,
+    pub formats: Vec<OutputFormat>,
+    #[serde(default)]
+    pub row_limit: Option<u64>,
+    pub output: String, // results location, files are {output}{request_id}.{extension}
+    #[serde(default)]
+    pub callback: Option<CallbackSpec>,
+    #[serde(default)]
+    pub resources: ResourceSpec,
+    #[serde(default)]
+    pub trace: TraceContext,
+    #[serde(default)]
+    pub profile: Option<ProfileSpec>, // profile the only table instead of running a query
+}
+
+impl JobSpec {
+    pub async fn load(ctx: &SessionContext, uri: &str) -> Result<Self> {
+        let data = get_object(ctx, uri)
+            .await?
+            .ok_or_else(|| eyre!("job spec not found: {uri}"))?;
+        let spec: Self = serde_json::from_slice(&data)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn validate(&self) -> Result<(), JobSpecError> {
+        if self.version != JOB_SPEC_VERSION {
+            return Err(JobSpecError::UnsupportedVersion(self.version));
+        }
+        let is_valid_id = !self.request_id.is_empty()
+            && self
+                .request_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid_id {
+            return Err(JobSpecError::InvalidRequestId(self.request_id.clone()));
+        }
+        match &self.profile {
+            Some(_) if self.tables.len() != 1 => return Err(JobSpecError::ProfileTables(self.tables.len())),
+            Some(_) => {}
+            None if self.query.trim().is_empty() => return Err(JobSpecError::EmptyQuery),
+            None if self.formats.is_empty() => return Err(JobSpecError::NoFormats),
+            None => {}
+        }
+        let mut names = HashSet::new();
+        for table in &self.tables {
+            if !names.insert(table.name.as_str()) {
+                return Err(JobSpecError::DuplicateTable(table.name.clone()));
+            }
+        }
+        if !(self.output.starts_with("s3://") || self.output.starts_with("file://")) {
+            return Err(JobSpecError::InvalidOutput(self.output.clone()));
+        }
+        Ok(())
+    }
+
+    /// {output}{request_id}.{extension}
+    pub fn result_url(&self, extension: &str) -> String {
+        format!("{}{}.{extension}", self.output, self.request_id)
+    }
+
+    /// Results are written here first and copied to result_url once verified
+    pub fn staging_prefix(&self) -> String {
+        format!("{}{}.staging/", self.output, self.request_id)
+    }
+
+    /// {output}{request_id}.staging/{request_id}.{extension}
+    pub fn staging_url(&self, extension: &str) -> String {
+        format!("{}{}.{extension}", self.staging_prefix(), self.request_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn spec() -> Value {
+        json!({
+            "version": 1,
+            "request_id": "id-1",
+            "query": "SELECT * FROM t0 WHERE a = $1 LIMIT 1001",
+            "params": [{"type": "integer", "value": 1}],
+            "tables": [{"name": "t0", "path": "s3://bucket/table/"}],
+            "formats": ["parquet", "json"],
+            "row_limit": 1000,
+            "output": "s3://bucket/prefix/",
+            "callback": {"url": "https://example.com/hook", "encrypted_secret": "s3cr3t", "result_urls": {}},
+            "trace": {"xray_trace_id": "Root=1-5759e988-bd862e3fe1be46a994272793"}
+        })
+    }
+
+    #[test]
+    fn job_spec_parse_test() {
+        let spec: JobSpec = serde_json::from_value(spec()).unwrap();
+        assert_eq!(spec.validate(), Ok(()));
+        assert_eq!(spec.params, vec![QueryParam::Integer(1)]);
+        assert_eq!(spec.formats, vec![OutputFormat::Parquet, OutputFormat::Json]);
+        assert_eq!(spec.result_url("manifest.json"), "s3://bucket/prefix/id-1.manifest.json");
+        assert_eq!(spec.staging_url("parquet"), "s3://bucket/prefix/id-1.staging/id-1.parquet");
+        assert!(!format!("{spec:?}").contains("s3cr3t"));
+        assert!(spec.trace.xray_trace_id.is_some());
+    }
+
+    #[test]
+    fn profile_spec_parse_test() {
+        let mut spec = spec();
+        spec["query"] = json!("");
+        spec["formats"] = json!([]);
+        spec["profile"] = json!({"columns": ["a"], "bins": 10});
+        let mut spec: JobSpec = serde_json::from_value(spec).unwrap();
+        assert_eq!(spec.validate(), Ok(()));
+        spec.tables.push(spec.tables[0].clone());
+        assert_eq!(spec.validate(), Err(JobSpecError::ProfileTables(2)));
+        let profile = spec.profile.unwrap();
+        assert_eq!((profile.columns, profile.top_k, profile.bins), (Some(vec!["a".to_string()]), 10, 10));
+    }
+
+    #[rstest]
+    #[case("version", json!(2), JobSpecError::UnsupportedVersion(2))]
+    #[case("request_id", json!("../id"), JobSpecError::InvalidRequestId("../id".to_string()))]
+    #[case("query", json!(" "), JobSpecError::EmptyQuery)]
+    #[case("formats", json!([]), JobSpecError::NoFormats)]
+    #[case("tables", json!([{"name": "t0", "path": "a"}, {"name": "t0", "path": "b"}]), JobSpecError::DuplicateTable("t0".to_string()))]
+    #[case("output", json!("/tmp/results/"), JobSpecError::InvalidOutput("/tmp/results/".to_string()))]
+    fn job_spec_validate_test(#[case] field: &str, #[case] value: Value, #[case] expected: JobSpecError) {
+        let mut spec = spec();
+        spec[field] = value;
+        let spec: JobSpec = serde_json::from_value(spec).unwrap();
+        assert_eq!(spec.validate(), Err(expected));
+    }
+}
@@ -0,0 +1,86 @@
+use color_eyre::Result;
+use datafusion::prelude::SessionContext;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::jobspec::OutputFormat;
+use crate::utils::sampling::SampleReport;
+use crate::utils::storage::put_object;
+
+/// Published result file, checksum is of the file as stored
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ResultFile {
+    pub format: OutputFormat,
+    pub rows: u64,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Summary of the query result, written next to the result files as {request_id}.manifest.json
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ResultManifest {
+    pub request_id: String,
+    pub rows: u64,
+    pub row_limit: Option<u64>,
+    pub truncated: bool, // query returned more rows than row_limit
+    #[serde(default)]
+    pub files: Vec<ResultFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<SampleReport>, // tables read with TABLESAMPLE, the result is a sample
+}
+
+impl ResultManifest {
+    /// Rows in the result and whether they were cut, rows is the count of the query with probe row
+    pub fn new(request_id: String, rows: u64, row_limit: Option<u64>) -> Self {
+        let truncated = row_limit.is_some_and(|limit| rows > limit);
+        let rows = row_limit.map_or(rows, |limit| rows.min(limit));
+        Self {
+            request_id,
+            rows,
+            row_limit,
+            truncated,
+            files: vec![],
+            samples: vec![],
+        }
+    }
+
+    /// Put manifest with the object store the session resolves url with
+    pub async fn write(&self, ctx: &SessionContext, url: &str) -> Result<()> {
+        put_object(ctx, url, serde_json::to_vec(self)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(1001, Some(1000), 1000, true)]
+    #[case(1000, Some(1000), 1000, false)]
+    #[case(10, Some(1000), 10, false)]
+    #[case(5000, None, 5000, false)]
+    fn manifest_new_test(
+        #[case] rows: u64,
+        #[case] row_limit: Option<u64>,
+        #[case] expected_rows: u64,
+        #[case] expected_truncated: bool,
+    ) {
+        let manifest = ResultManifest::new("id".to_string(), rows, row_limit);
+        assert_eq!(manifest.rows, expected_rows);
+        assert_eq!(manifest.truncated, expected_truncated);
+    }
+
+    #[tokio::test]
+    async fn manifest_write_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-manifest-test");
+        let path = dir.join("id.manifest.json");
+        let url = format!("file://{}", path.display());
+        let manifest = ResultManifest::new("id".to_string(), 1001, Some(1000));
+        manifest.write(&SessionContext::new(), &url).await.unwrap();
+        let written: ResultManifest =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written, manifest);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
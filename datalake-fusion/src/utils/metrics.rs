@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use color_eyre::{Result, eyre::eyre};
+use datafusion::arrow::array::{RecordBatch, UInt64Array};
+use datafusion::datasource::file_format::format_as_file_type;
+use datafusion::datasource::file_format::json::JsonFormatFactory;
+use datafusion::datasource::file_format::parquet::ParquetFormatFactory;
+use datafusion::logical_expr::LogicalPlanBuilder;
+use datafusion::physical_plan::{ExecutionPlan, collect, displayable};
+use datafusion::prelude::{DataFrame, SessionContext};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+use crate::utils::jobspec::OutputFormat;
+use crate::utils::storage::put_object;
+
+/// Metrics of one operator of the physical plan, summed over partitions.
+/// Times are nanoseconds, e.g. output_rows, elapsed_compute, bytes_scanned,
+/// row_groups_pruned_statistics, spilled_bytes
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct OperatorMetrics {
+    pub depth: usize, // 0 is the root of the plan
+    pub operator: String,
+    pub metrics: BTreeMap<String, usize>,
+}
+
+/// One execution of the job: the query into staging, then a write per result format
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct StageMetrics {
+    pub name: String,
+    pub elapsed_ms: u64,
+    pub operators: Vec<OperatorMetrics>,
+}
+
+/// Written next to the results as {request_id}.metrics.json. On Ballista operators run on
+/// executors, the plan of a stage then only has the distributed query operator
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct QueryMetrics {
+    pub request_id: String,
+    pub elapsed_ms: u64,
+    pub stages: Vec<StageMetrics>,
+}
+
+/// Runs the plans of a job and keeps their metrics
+#[derive(Debug)]
+pub struct MetricsRecorder {
+    started: Instant,
+    stages: Vec<StageMetrics>,
+}
+
+impl Default for MetricsRecorder {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            stages: vec![],
+        }
+    }
+}
+
+impl MetricsRecorder {
+    pub async fn run(&mut self, name: &str, df: DataFrame) -> Result<Vec<RecordBatch>> {
+        let start = Instant::now();
+        let task_ctx = Arc::new(df.task_ctx());
+        let plan = df
+            .create_physical_plan()
+            .instrument(tracing::info_span!("physical_plan", stage = name))
+            .await?;
+        let batches = collect(plan.clone(), task_ctx)
+            .instrument(tracing::info_span!("execute", stage = name))
+            .await?;
+        let elapsed_ms = start.elapsed().as_millis() as u64;
+        tracing::info!({ stage = name, elapsed_ms }, "stage finished");
+        self.stages.push(StageMetrics {
+            name: name.to_string(),
+            elapsed_ms,
+            operators: plan_metrics(&plan),
+        });
+        Ok(batches)
+    }
+
+    /// Write the dataframe as a single file, same plan as DataFrame::write_parquet / write_json,
+    /// returns the rows written
+    pub async fn write(&mut self, name: &str, df: DataFrame, path: &str, format: OutputFormat) -> Result<u64> {
+        let file_type = match format {
+            OutputFormat::Json => format_as_file_type(Arc::new(JsonFormatFactory::new())),
+            OutputFormat::Parquet => format_as_file_type(Arc::new(ParquetFormatFactory::new())),
+        };
+        let (state, plan) = df.into_parts();
+        let plan = LogicalPlanBuilder::copy_to(plan, path.to_string(), file_type, Default::default(), vec![])?.build()?;
+        let batches = self.run(name, DataFrame::new(state, plan)).await?;
+        // COPY TO returns a single count row
+        let rows = batches
+            .first()
+            .and_then(|b| b.column(0).as_any().downcast_ref::<UInt64Array>())
+            .map(|a| a.value(0))
+            .ok_or_else(|| eyre!("unexpected write result"))?;
+        Ok(rows)
+    }
+
+    pub fn report(&self, request_id: &str) -> QueryMetrics {
+        QueryMetrics {
+            request_id: request_id.to_string(),
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            stages: self.stages.clone(),
+        }
+    }
+}
+
+/// Operators depth first, root first
+pub fn plan_metrics(plan: &Arc<dyn ExecutionPlan>) -> Vec<OperatorMetrics> {
+    let mut operators = vec![];
+    let mut stack = vec![(0, plan.clone())];
+    while let Some((depth, plan)) = stack.pop() {
+        let metrics = plan
+            .metrics()
+            .map(|m| {
+                m.aggregate_by_name()
+                    .timestamps_removed()
+                    .iter()
+                    .map(|m| (m.value().name().to_string(), m.value().as_usize()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        operators.push(OperatorMetrics {
+            depth,
+            operator: displayable(plan.as_ref()).one_line().to_string().trim_end().to_string(),
+            metrics,
+        });
+        for child in plan.children().into_iter().rev() {
+            stack.push((depth + 1, child.clone()));
+        }
+    }
+    operators
+}
+
+impl QueryMetrics {
+    /// Bytes read by all scans of all stages
+    pub fn bytes_scanned(&self) -> u64 {
+        self.stages
+            .iter()
+            .flat_map(|s| &s.operators)
+            .filter_map(|o| o.metrics.get("bytes_scanned"))
+            .map(|b| *b as u64)
+            .sum()
+    }
+
+    pub async fn write(&self, ctx: &SessionContext, url: &str) -> Result<()> {
+        put_object(ctx, url, serde_json::to_vec(self)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::prelude::ParquetReadOptions;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn metrics_recorder_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-metrics-test");
+        let path = format!("file://{}/t0.parquet", dir.display());
+        let ctx = SessionContext::new();
+        let mut recorder = MetricsRecorder::default();
+        let df = ctx.sql("SELECT value AS a FROM generate_series(1, 1000)").await.unwrap();
+        assert_eq!(recorder.write("query", df, &path, OutputFormat::Parquet).await.unwrap(), 1000);
+
+        ctx.register_parquet("t0", &path, ParquetReadOptions::default()).await.unwrap();
+        let df = ctx.sql("SELECT a FROM t0 WHERE a > 900").await.unwrap();
+        let json = format!("file://{}/t1.json", dir.display());
+        assert_eq!(recorder.write("write_json", df, &json, OutputFormat::Json).await.unwrap(), 100);
+
+        let report = recorder.report("id");
+        let names: Vec<_> = report.stages.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["query", "write_json"]);
+        let write = &report.stages[1];
+        assert_eq!(write.operators[0].depth, 0);
+        let scan = write
+            .operators
+            .iter()
+            .find(|o| o.operator.starts_with("DataSourceExec"))
+            .unwrap();
+        assert!(scan.metrics["bytes_scanned"] > 0);
+        assert!(scan.metrics.contains_key("row_groups_pruned_statistics"));
+        assert_eq!(report.bytes_scanned(), scan.metrics["bytes_scanned"] as u64);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,2 +1,20 @@
-pub mod aws;
+pub mod callback;
 pub mod constants;
+pub mod credentials;
+pub mod jobrecord;
+pub mod jobspec;
+pub mod manifest;
+pub mod metrics;
+pub mod params;
+pub mod profile;
+pub mod publish;
+pub mod resources;
+pub mod sampling;
+pub mod sketches;
+pub mod sqlpolicy;
+pub mod standalone;
+pub mod storage;
+pub mod telemetry;
+pub mod tracing;
+pub mod udfs;
+pub mod upload;
@@ -0,0 +1,64 @@
+use color_eyre::Result;
+use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use datafusion::common::{ParamValues, ScalarValue};
+use serde::{Deserialize, Serialize};
+
+/// Typed bind value for $1, $2, ... placeholders,
+/// serialized as {"type": "integer", "value": 42}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum QueryParam {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Date(String),
+    Timestamp(String),
+}
+
+impl QueryParam {
+    pub fn to_scalar(&self) -> Result<ScalarValue> {
+        let value = match self {
+            QueryParam::String(v) => ScalarValue::Utf8(Some(v.clone())),
+            QueryParam::Integer(v) => ScalarValue::Int64(Some(*v)),
+            QueryParam::Float(v) => ScalarValue::Float64(Some(*v)),
+            QueryParam::Bool(v) => ScalarValue::Boolean(Some(*v)),
+            QueryParam::Date(v) => ScalarValue::Utf8(Some(v.clone())).cast_to(&DataType::Date32)?,
+            QueryParam::Timestamp(v) => ScalarValue::Utf8(Some(v.clone()))
+                .cast_to(&DataType::Timestamp(TimeUnit::Microsecond, None))?,
+        };
+        Ok(value)
+    }
+}
+
+/// Values for positional placeholders, $1 is the first param
+pub fn param_values(params: &[QueryParam]) -> Result<ParamValues> {
+    let values = params
+        .iter()
+        .map(QueryParam::to_scalar)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(ParamValues::List(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(QueryParam::String("foo".to_string()), ScalarValue::Utf8(Some("foo".to_string())))]
+    #[case(QueryParam::Integer(42), ScalarValue::Int64(Some(42)))]
+    #[case(QueryParam::Float(4.2), ScalarValue::Float64(Some(4.2)))]
+    #[case(QueryParam::Bool(false), ScalarValue::Boolean(Some(false)))]
+    #[case(QueryParam::Date("1970-01-02".to_string()), ScalarValue::Date32(Some(1)))]
+    #[case(QueryParam::Timestamp("1970-01-01T00:00:01Z".to_string()), ScalarValue::TimestampMicrosecond(Some(1_000_000), None))]
+    fn to_scalar_test(#[case] input: QueryParam, #[case] expected: ScalarValue) {
+        assert_eq!(input.to_scalar().unwrap(), expected);
+    }
+
+    #[test]
+    fn to_scalar_invalid_date_test() {
+        assert!(QueryParam::Date("foo".to_string()).to_scalar().is_err());
+    }
+}
@@ -0,0 +1,512 @@
+use std::sync::Arc;
+
+use chrono::DateTime;
+use color_eyre::Result;
+use datafusion::arrow::array::{Array, AsArray, RecordBatch};
+use datafusion::arrow::datatypes::{DataType, Int64Type, Schema, TimeUnit, UInt64Type};
+use datafusion::arrow::util::display::array_value_to_string;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::parquet::arrow::arrow_reader::statistics::StatisticsConverter;
+use datafusion::parquet::file::metadata::ParquetMetaData;
+use datafusion::prelude::SessionContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::task::JoinSet;
+
+use crate::TableRef;
+use crate::utils::constants::*;
+use crate::utils::sampling::{parquet_files, read_footer};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProfileError {
+    #[error("column {0} is not in the table")]
+    UnknownColumn(String),
+    #[error("top_k must be between 1 and {PROFILE_MAX_TOP_K}, got {0}")]
+    InvalidTopK(usize),
+    #[error("bins must be between 1 and {PROFILE_MAX_BINS}, got {0}")]
+    InvalidBins(usize),
+}
+
+/// What to profile, {"columns": ["a", "b"], "top_k": 10, "bins": 20}, all columns when not given
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ProfileSpec {
+    pub columns: Option<Vec<String>>,
+    pub top_k: usize,
+    pub bins: usize,
+}
+
+impl Default for ProfileSpec {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            top_k: PROFILE_TOP_K,
+            bins: PROFILE_BINS,
+        }
+    }
+}
+
+impl ProfileSpec {
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if !(1..=PROFILE_MAX_TOP_K).contains(&self.top_k) {
+            return Err(ProfileError::InvalidTopK(self.top_k));
+        }
+        if !(1..=PROFILE_MAX_BINS).contains(&self.bins) {
+            return Err(ProfileError::InvalidBins(self.bins));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TopValue {
+    pub value: String,
+    pub count: u64,
+}
+
+/// Values in [lower, upper), the last bin includes upper
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HistogramBin {
+    pub lower: Value,
+    pub upper: Value,
+    pub count: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ColumnProfile {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+    pub null_count: u64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub distinct: Option<u64>, // hll estimate
+    pub top_values: Vec<TopValue>, // approximate counts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histogram: Vec<HistogramBin>, // numeric and date columns
+    pub from_statistics: bool, // null count, min and max were read from parquet footers
+}
+
+/// Profile of a table, written as {request_id}.profile.json
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TableProfile {
+    pub path: String,
+    pub rows: u64,
+    pub files: usize,
+    pub row_groups: usize,
+    pub columns: Vec<ColumnProfile>,
+}
+
+/// Footer statistics of a column over all row groups, None when some row group has none
+#[derive(Debug, Default)]
+struct ColumnStats {
+    null_count: u64,
+    min: Option<ScalarValue>,
+    max: Option<ScalarValue>,
+}
+
+/// Profile a registered parquet table. Null counts, min and max come from footer statistics
+/// when every row group has exact ones, the rest is computed by one scan plus one query per histogram
+pub async fn profile_table(ctx: &SessionContext, table: &TableRef, spec: &ProfileSpec) -> Result<TableProfile> {
+    spec.validate()?;
+    let schema = ctx.table_provider(&table.name).await?.schema();
+    let fields = match &spec.columns {
+        Some(columns) => columns
+            .iter()
+            .map(|c| schema.field_with_name(c).map_err(|_| ProfileError::UnknownColumn(c.clone())))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => schema.fields().iter().map(|f| f.as_ref()).collect(),
+    };
+
+    let url = ListingTableUrl::parse(&table.path)?;
+    let (store, files) = parquet_files(ctx, &url).await?;
+    let mut footers = vec![];
+    for chunk in files.chunks(FOOTER_READS) {
+        let mut reads = JoinSet::new();
+        for file in chunk {
+            let (store, file) = (store.clone(), file.clone());
+            reads.spawn(async move { read_footer(store, &file).await });
+        }
+        while let Some(footer) = reads.join_next().await {
+            footers.push(footer??);
+        }
+    }
+    let rows = footers.iter().map(|f| f.file_metadata().num_rows() as u64).sum::<u64>();
+    let row_groups = footers.iter().map(|f| f.num_row_groups()).sum();
+    let stats: Vec<_> = fields
+        .iter()
+        .map(|f| footer_stats(f.name(), &schema, &footers))
+        .collect();
+
+    // single scan for whatever the footers don't have
+    let mut select = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        let column = quote(field.name());
+        if stats[i].is_none() {
+            select.push(format!("count({column}) AS n{i}"));
+            if !field.data_type().is_nested() {
+                select.push(format!("min({column}) AS min{i}, max({column}) AS max{i}"));
+            }
+        }
+        if !field.data_type().is_nested() {
+            select.push(format!("hll_estimate(hll_sketch({column})) AS d{i}"));
+            select.push(format!("approx_top_k({column}, {}) AS t{i}", spec.top_k));
+        }
+    }
+    let scan = match select.is_empty() {
+        true => None,
+        false => {
+            let sql = format!("SELECT {} FROM {}", select.join(", "), quote(&table.name));
+            ctx.sql(&sql).await?.collect().await?.into_iter().find(|b| b.num_rows() > 0)
+        }
+    };
+    let scanned = |name: &str| -> Result<Option<ScalarValue>> {
+        let Some(batch) = &scan else { return Ok(None) };
+        let Some(column) = batch.column_by_name(name) else { return Ok(None) };
+        Ok(Some(ScalarValue::try_from_array(column, 0)?).filter(|v| !v.is_null()))
+    };
+
+    let mut columns = vec![];
+    for (i, (field, stats)) in fields.iter().zip(stats).enumerate() {
+        let from_statistics = stats.is_some();
+        let stats = match stats {
+            Some(stats) => stats,
+            None => {
+                let count = match scanned(&format!("n{i}"))? {
+                    Some(ScalarValue::Int64(Some(count))) => count as u64,
+                    _ => 0,
+                };
+                ColumnStats {
+                    null_count: rows.saturating_sub(count),
+                    min: scanned(&format!("min{i}"))?,
+                    max: scanned(&format!("max{i}"))?,
+                }
+            }
+        };
+        let distinct = match scanned(&format!("d{i}"))? {
+            Some(ScalarValue::Int64(Some(distinct))) => Some(distinct as u64),
+            _ => None,
+        };
+        let top_values = match &scan {
+            Some(batch) => top_values(batch, &format!("t{i}")),
+            None => vec![],
+        };
+        let histogram = match (&stats.min, &stats.max) {
+            (Some(min), Some(max)) => histogram(ctx, &table.name, field.name(), min, max, spec.bins, rows.saturating_sub(stats.null_count)).await?,
+            _ => vec![],
+        };
+        columns.push(ColumnProfile {
+            name: field.name().clone(),
+            data_type: field.data_type().to_string(),
+            nullable: field.is_nullable(),
+            null_count: stats.null_count,
+            min: stats.min.as_ref().and_then(to_json),
+            max: stats.max.as_ref().and_then(to_json),
+            distinct,
+            top_values,
+            histogram,
+            from_statistics,
+        });
+    }
+
+    Ok(TableProfile {
+        path: table.path.clone(),
+        rows,
+        files: files.len(),
+        row_groups,
+        columns,
+    })
+}
+
+fn footer_stats(column: &str, schema: &Schema, footers: &[Arc<ParquetMetaData>]) -> Option<ColumnStats> {
+    let mut stats = ColumnStats::default();
+    for footer in footers {
+        let converter = StatisticsConverter::try_new(column, schema, footer.file_metadata().schema_descr()).ok()?;
+        converter.parquet_column_index()?;
+        let row_groups = footer.row_groups();
+        let null_counts = converter.row_group_null_counts(row_groups).ok()?;
+        let mins = converter.row_group_mins(row_groups).ok()?;
+        let maxes = converter.row_group_maxes(row_groups).ok()?;
+        // truncated string statistics are bounds, not values
+        let exact_mins = converter.row_group_is_min_value_exact(row_groups).ok()?;
+        let exact_maxes = converter.row_group_is_max_value_exact(row_groups).ok()?;
+        for (i, rg) in row_groups.iter().enumerate() {
+            if null_counts.is_null(i) {
+                return None;
+            }
+            let null_count = null_counts.value(i);
+            stats.null_count += null_count;
+            // all null chunks have no min and max
+            if null_count == rg.num_rows() as u64 {
+                continue;
+            }
+            if mins.is_null(i) || maxes.is_null(i) || !exact_mins.value(i) || !exact_maxes.value(i) {
+                return None;
+            }
+            let min = ScalarValue::try_from_array(&mins, i).ok()?;
+            let max = ScalarValue::try_from_array(&maxes, i).ok()?;
+            if stats.min.as_ref().is_none_or(|m| min < *m) {
+                stats.min = Some(min);
+            }
+            if stats.max.as_ref().is_none_or(|m| max > *m) {
+                stats.max = Some(max);
+            }
+        }
+    }
+    Some(stats)
+}
+
+fn top_values(batch: &RecordBatch, name: &str) -> Vec<TopValue> {
+    let Some(list) = batch.column_by_name(name).and_then(|c| c.as_list_opt::<i32>()) else {
+        return vec![];
+    };
+    if list.is_null(0) {
+        return vec![];
+    }
+    let items = list.value(0);
+    let items = items.as_struct();
+    let values = items.column(0).as_string::<i32>();
+    let counts = items.column(1).as_primitive::<UInt64Type>();
+    values
+        .iter()
+        .zip(counts.iter())
+        .filter_map(|(value, count)| Some(TopValue { value: value?.to_string(), count: count? }))
+        .collect()
+}
+
+/// Equal width bins between min and max, dates and timestamps are binned by epoch seconds
+async fn histogram(
+    ctx: &SessionContext,
+    table: &str,
+    column: &str,
+    min: &ScalarValue,
+    max: &ScalarValue,
+    bins: usize,
+    values: u64,
+) -> Result<Vec<HistogramBin>> {
+    let data_type = min.data_type();
+    let (Some(lower), Some(upper)) = (axis_value(min), axis_value(max)) else {
+        return Ok(vec![]);
+    };
+    if upper <= lower {
+        return Ok(vec![HistogramBin { lower: edge(lower, &data_type), upper: edge(upper, &data_type), count: values }]);
+    }
+    let column = quote(column);
+    let value = match data_type {
+        DataType::Date32 | DataType::Date64 | DataType::Timestamp(..) => format!("CAST(date_part('epoch', {column}) AS DOUBLE)"),
+        _ => format!("CAST({column} AS DOUBLE)"),
+    };
+    let width = (upper - lower) / bins as f64;
+    let sql = format!(
+        "SELECT LEAST(CAST(floor(({value} - ({lower:?})) / {width:?}) AS BIGINT), {}) AS bin, count(*) AS count \
+         FROM {} WHERE {column} IS NOT NULL GROUP BY 1",
+        bins - 1,
+        quote(table),
+    );
+    let mut counts = vec![0; bins];
+    for batch in ctx.sql(&sql).await?.collect().await? {
+        let bin = batch.column(0).as_primitive::<Int64Type>();
+        let count = batch.column(1).as_primitive::<Int64Type>();
+        for (bin, count) in bin.iter().zip(count.iter()) {
+            if let (Some(bin), Some(count)) = (bin, count) {
+                counts[bin.clamp(0, bins as i64 - 1) as usize] += count as u64;
+            }
+        }
+    }
+    let histogram = counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| HistogramBin {
+            lower: edge(lower + width * i as f64, &data_type),
+            upper: edge(if i + 1 == bins { upper } else { lower + width * (i + 1) as f64 }, &data_type),
+            count,
+        })
+        .collect();
+    Ok(histogram)
+}
+
+/// Position of a value on the histogram axis, None for columns without one
+fn axis_value(value: &ScalarValue) -> Option<f64> {
+    let seconds = match value {
+        ScalarValue::Date32(Some(days)) => *days as f64 * 86_400.0,
+        ScalarValue::Date64(Some(ms)) => *ms as f64 / 1e3,
+        ScalarValue::TimestampSecond(Some(v), _) => *v as f64,
+        ScalarValue::TimestampMillisecond(Some(v), _) => *v as f64 / 1e3,
+        ScalarValue::TimestampMicrosecond(Some(v), _) => *v as f64 / 1e6,
+        ScalarValue::TimestampNanosecond(Some(v), _) => *v as f64 / 1e9,
+        v if v.data_type().is_numeric() => match v.cast_to(&DataType::Float64).ok()? {
+            ScalarValue::Float64(Some(v)) if v.is_finite() => v,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(seconds)
+}
+
+/// Bin edge as the ui shows it, dates and timestamps as iso strings
+fn edge(value: f64, data_type: &DataType) -> Value {
+    let datetime = || DateTime::from_timestamp(value.floor() as i64, ((value - value.floor()) * 1e9) as u32);
+    match data_type {
+        DataType::Date32 | DataType::Date64 => datetime().map_or(Value::Null, |d| d.format("%Y-%m-%d").to_string().into()),
+        DataType::Timestamp(unit, _) => {
+            let format = match unit {
+                TimeUnit::Second => "%Y-%m-%dT%H:%M:%S",
+                _ => "%Y-%m-%dT%H:%M:%S%.3f",
+            };
+            datetime().map_or(Value::Null, |d| d.format(format).to_string().into())
+        }
+        _ => serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number),
+    }
+}
+
+/// Integers and floats as numbers, everything else as arrow displays it
+fn to_json(value: &ScalarValue) -> Option<Value> {
+    if value.is_null() {
+        return None;
+    }
+    let data_type = value.data_type();
+    let json = if data_type.is_signed_integer() {
+        match value.cast_to(&DataType::Int64).ok()? {
+            ScalarValue::Int64(Some(v)) => v.into(),
+            _ => return None,
+        }
+    } else if data_type.is_unsigned_integer() {
+        match value.cast_to(&DataType::UInt64).ok()? {
+            ScalarValue::UInt64(Some(v)) => v.into(),
+            _ => return None,
+        }
+    } else if data_type.is_floating() {
+        match value.cast_to(&DataType::Float64).ok()? {
+            ScalarValue::Float64(Some(v)) => serde_json::Number::from_f64(v).map_or_else(|| v.to_string().into(), Value::Number),
+            _ => return None,
+        }
+    } else if let ScalarValue::Boolean(Some(v)) = value {
+        (*v).into()
+    } else {
+        array_value_to_string(&value.to_array().ok()?, 0).ok()?.into()
+    };
+    Some(json)
+}
+
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::dataframe::DataFrameWriteOptions;
+    use datafusion::prelude::SessionConfig;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::udfs::register_udfs;
+
+    /// 2 files of 2 row groups with 50 rows each, id 0..199, every 10th name is null
+    async fn profile(dir: &str, spec: &ProfileSpec, statistics: &str) -> Result<TableProfile> {
+        let dir = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let config = SessionConfig::new()
+            .set_usize("datafusion.execution.parquet.max_row_group_size", 50)
+            .set_str("datafusion.execution.parquet.statistics_enabled", statistics);
+        let mut state = SessionContext::new_with_config(config).state();
+        register_udfs(&mut state)?;
+        let ctx = SessionContext::new_with_state(state);
+        for file in 0..2 {
+            let sql = format!(
+                "SELECT id, CASE WHEN id % 10 = 0 THEN NULL ELSE 'n' || (id % 3) END AS name, \
+                 CAST(id % 4 AS DOUBLE) AS score, CAST(CAST(19723 + id AS INT) AS DATE) AS day \
+                 FROM (SELECT value + {} AS id FROM generate_series(0, 99))",
+                file * 100
+            );
+            ctx.sql(&sql)
+                .await?
+                .repartition(datafusion::logical_expr::Partitioning::RoundRobinBatch(1))?
+                .write_parquet(
+                    dir.join(format!("{file}.parquet")).to_str().unwrap(),
+                    DataFrameWriteOptions::new().with_single_file_output(true),
+                    None,
+                )
+                .await?;
+        }
+        let table = TableRef {
+            name: "t0".to_string(),
+            path: format!("{}/", dir.display()),
+            sample: None,
+        };
+        ctx.register_parquet(&table.name, &table.path, Default::default()).await?;
+        let profile = profile_table(&ctx, &table, spec).await;
+        std::fs::remove_dir_all(dir)?;
+        profile
+    }
+
+    #[tokio::test]
+    async fn profile_table_test() {
+        let spec = ProfileSpec { top_k: 3, bins: 4, ..Default::default() };
+        let profile = profile("datalake-fusion-profile", &spec, "page").await.unwrap();
+        assert_eq!((profile.rows, profile.files, profile.row_groups), (200, 2, 4));
+        let columns: Vec<_> = profile.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(columns, ["id", "name", "score", "day"]);
+
+        let id = &profile.columns[0];
+        assert!(id.from_statistics);
+        assert_eq!((id.null_count, id.min.clone(), id.max.clone()), (0, Some(json!(0)), Some(json!(199))));
+        assert_eq!(id.distinct, Some(198)); // hll estimate
+        assert_eq!(id.histogram.iter().map(|b| b.count).collect::<Vec<_>>(), [50, 50, 50, 50]);
+        assert_eq!((id.histogram[0].lower.clone(), id.histogram[3].upper.clone()), (json!(0.0), json!(199.0)));
+
+        let name = &profile.columns[1];
+        assert_eq!(name.null_count, 20);
+        assert_eq!((name.min.clone(), name.max.clone()), (Some(json!("n0")), Some(json!("n2"))));
+        assert_eq!(name.distinct, Some(3));
+        assert_eq!(name.top_values.len(), 3);
+        assert_eq!(name.top_values.iter().map(|t| t.count).sum::<u64>(), 180);
+        assert!(name.histogram.is_empty());
+
+        let score = &profile.columns[2];
+        assert_eq!(score.top_values.iter().map(|t| t.count).collect::<Vec<_>>(), [50, 50, 50]);
+        assert_eq!(score.histogram.iter().map(|b| b.count).collect::<Vec<_>>(), [50, 50, 50, 50]);
+
+        let day = &profile.columns[3];
+        assert_eq!((day.min.clone(), day.max.clone()), (Some(json!("2024-01-01")), Some(json!("2024-07-18"))));
+        assert_eq!(day.histogram[0].lower, json!("2024-01-01"));
+        assert_eq!(day.histogram.iter().map(|b| b.count).sum::<u64>(), 200);
+    }
+
+    #[tokio::test]
+    async fn profile_without_statistics_test() {
+        let spec = ProfileSpec { columns: Some(vec!["name".to_string(), "day".to_string()]), ..Default::default() };
+        let profile = profile("datalake-fusion-profile-scan", &spec, "none").await.unwrap();
+        assert_eq!(profile.rows, 200);
+        let (name, day) = (&profile.columns[0], &profile.columns[1]);
+        assert!(!name.from_statistics);
+        assert_eq!((name.null_count, name.min.clone(), name.max.clone()), (20, Some(json!("n0")), Some(json!("n2"))));
+        assert_eq!((day.min.clone(), day.max.clone()), (Some(json!("2024-01-01")), Some(json!("2024-07-18"))));
+        assert_eq!(day.histogram.len(), PROFILE_BINS);
+    }
+
+    #[rstest]
+    #[case("unknown", ProfileSpec { columns: Some(vec!["missing".to_string()]), ..Default::default() }, ProfileError::UnknownColumn("missing".to_string()))]
+    #[case("top-k", ProfileSpec { top_k: 0, ..Default::default() }, ProfileError::InvalidTopK(0))]
+    #[case("bins", ProfileSpec { bins: PROFILE_MAX_BINS + 1, ..Default::default() }, ProfileError::InvalidBins(PROFILE_MAX_BINS + 1))]
+    #[tokio::test]
+    async fn profile_table_err_test(#[case] name: &str, #[case] spec: ProfileSpec, #[case] expected: ProfileError) {
+        let err = profile(&format!("datalake-fusion-profile-{name}"), &spec, "page").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<ProfileError>(), Some(&expected));
+    }
+
+    #[rstest]
+    #[case(ScalarValue::Int32(Some(-3)), Some(json!(-3)))]
+    #[case(ScalarValue::Float64(Some(1.5)), Some(json!(1.5)))]
+    #[case(ScalarValue::Boolean(Some(true)), Some(json!(true)))]
+    #[case(ScalarValue::Utf8(Some("a".to_string())), Some(json!("a")))]
+    #[case(ScalarValue::Date32(Some(0)), Some(json!("1970-01-01")))]
+    #[case(ScalarValue::TimestampMillisecond(Some(60_000), None), Some(json!("1970-01-01T00:01:00")))]
+    #[case(ScalarValue::Int32(None), None)]
+    fn to_json_test(#[case] value: ScalarValue, #[case] expected: Option<Value>) {
+        assert_eq!(to_json(&value), expected);
+    }
+}
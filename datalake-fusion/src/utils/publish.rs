@@ -0,0 +1,160 @@
+use std::time::Instant;
+
+use color_eyre::Result;
+use datafusion::parquet::arrow::ParquetRecordBatchStreamBuilder;
+use datafusion::parquet::arrow::async_reader::ParquetObjectReader;
+use datafusion::prelude::SessionContext;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio_stream::StreamExt;
+
+use crate::utils::jobspec::{JobSpec, OutputFormat};
+use crate::utils::manifest::ResultFile;
+use crate::utils::storage::object_store;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PublishError {
+    #[error("{url} has {actual} rows, expected {expected}")]
+    RowCount { url: String, expected: u64, actual: u64 },
+    #[error("{url} has {actual} bytes after publishing, expected {expected}")]
+    Size { url: String, expected: u64, actual: u64 },
+}
+
+/// Rows, size and sha256 of a staged file, the object is streamed and never held in memory
+pub async fn inspect(ctx: &SessionContext, url: &str, format: OutputFormat) -> Result<ResultFile> {
+    let (store, path) = object_store(ctx, url)?;
+    let mut stream = store.get(&path).await?.into_stream();
+    let mut hasher = Sha256::new();
+    let mut bytes = 0;
+    let mut lines = 0;
+    while let Some(chunk) = stream.next().await.transpose()? {
+        hasher.update(&chunk);
+        bytes += chunk.len() as u64;
+        lines += chunk.iter().filter(|b| **b == b'\n').count() as u64;
+    }
+    let rows = match format {
+        // newline delimited json, one row per line
+        OutputFormat::Json => lines,
+        OutputFormat::Parquet => {
+            let reader = ParquetObjectReader::new(store, path);
+            let builder = ParquetRecordBatchStreamBuilder::new(reader).await?;
+            builder.metadata().file_metadata().num_rows() as u64
+        }
+    };
+    Ok(ResultFile {
+        format,
+        rows,
+        bytes,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+/// Verify staged results and copy them to their final keys. Copies are atomic,
+/// readers see a complete file or none, the manifest is written after all of them
+#[tracing::instrument(name = "upload", skip_all, fields(formats = spec.formats.len()))]
+pub async fn publish(ctx: &SessionContext, spec: &JobSpec, rows: u64) -> Result<Vec<ResultFile>> {
+    let start = Instant::now();
+    let mut files = vec![];
+    for format in &spec.formats {
+        let url = spec.staging_url(format.extension());
+        let file = inspect(ctx, &url, *format).await?;
+        if file.rows != rows {
+            return Err(PublishError::RowCount { url, expected: rows, actual: file.rows }.into());
+        }
+        files.push(file);
+    }
+
+    for file in &files {
+        let (store, from) = object_store(ctx, &spec.staging_url(file.format.extension()))?;
+        let url = spec.result_url(file.format.extension());
+        let (_, to) = object_store(ctx, &url)?;
+        store.copy(&from, &to).await?;
+        tracing::info!({ url, rows = file.rows, bytes = file.bytes, sha256 = file.sha256 }, "published");
+        let size = store.head(&to).await?.size;
+        if size != file.bytes {
+            return Err(PublishError::Size { url, expected: file.bytes, actual: size }.into());
+        }
+    }
+    discard_staging(ctx, spec).await;
+    tracing::info!({ elapsed_ms = start.elapsed().as_millis() as u64 }, "results published");
+    Ok(files)
+}
+
+/// Delete the staging prefix, leftovers are removed with the result by retention cleanup
+pub async fn discard_staging(ctx: &SessionContext, spec: &JobSpec) {
+    let res: Result<()> = async {
+        let (store, prefix) = object_store(ctx, &spec.staging_prefix())?;
+        let objects: Vec<_> = store.list(Some(&prefix)).collect().await;
+        for object in objects {
+            store.delete(&object?.location).await?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = res {
+        tracing::error!("failed to delete staged results: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::dataframe::DataFrameWriteOptions;
+    use serde_json::json;
+
+    use super::*;
+
+    fn spec(dir: &std::path::Path) -> JobSpec {
+        serde_json::from_value(json!({
+            "version": 1,
+            "request_id": "id-1",
+            "query": "SELECT 1",
+            "tables": [],
+            "formats": ["parquet", "json"],
+            "output": format!("file://{}/", dir.display()),
+        }))
+        .unwrap()
+    }
+
+    async fn stage(ctx: &SessionContext, spec: &JobSpec) {
+        let df = ctx.sql("SELECT * FROM generate_series(1, 10)").await.unwrap();
+        df.clone()
+            .write_parquet(&spec.staging_url("parquet"), DataFrameWriteOptions::default(), None)
+            .await
+            .unwrap();
+        df.write_json(&spec.staging_url("json"), DataFrameWriteOptions::default(), None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn publish_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-publish-test");
+        let ctx = SessionContext::new();
+        let spec = spec(&dir);
+        stage(&ctx, &spec).await;
+
+        let files = publish(&ctx, &spec, 10).await.unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|f| f.rows == 10 && f.sha256.len() == 64));
+        let parquet = std::fs::read(dir.join("id-1.parquet")).unwrap();
+        assert_eq!(files[0].bytes, parquet.len() as u64);
+        assert_eq!(files[0].sha256, hex::encode(Sha256::digest(&parquet)));
+        assert!(!dir.join("id-1.staging").join("id-1.json").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn publish_row_count_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-publish-rows-test");
+        let ctx = SessionContext::new();
+        let spec = spec(&dir);
+        stage(&ctx, &spec).await;
+
+        let err = publish(&ctx, &spec, 11).await.unwrap_err();
+        assert!(err.is::<PublishError>());
+        // nothing is published when verification fails
+        assert!(!dir.join("id-1.parquet").exists());
+        assert!(!dir.join("id-1.json").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datafusion::execution::disk_manager::{DiskManagerBuilder, DiskManagerMode};
+use datafusion::execution::memory_pool::{FairSpillPool, GreedyMemoryPool, MemoryPool, TrackConsumersPool};
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use serde::Deserialize;
+
+/// Consumers named in the error when the pool is exhausted
+const TOP_MEMORY_CONSUMERS: usize = 5;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryPoolKind {
+    #[default]
+    Fair, // splits memory between spilling operators, sorts and joins spill early
+    Greedy, // first come first served, fine for single heavy operator queries
+}
+
+/// Runtime limits of a job, unlimited memory, os temp dir and no timeout when not set
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ResourceSpec {
+    pub memory_pool: MemoryPoolKind,
+    pub memory_limit: Option<usize>, // bytes, keep below task memory to fail before the oom killer
+    pub spill_dir: Option<String>,
+    pub max_spill_bytes: Option<u64>,
+    pub timeout_secs: Option<u64>,
+}
+
+impl ResourceSpec {
+    pub fn memory_pool(&self) -> Option<Arc<dyn MemoryPool>> {
+        let limit = self.memory_limit?;
+        let top = NonZeroUsize::new(TOP_MEMORY_CONSUMERS).expect("non zero");
+        let pool: Arc<dyn MemoryPool> = match self.memory_pool {
+            MemoryPoolKind::Fair => Arc::new(TrackConsumersPool::new(FairSpillPool::new(limit), top)),
+            MemoryPoolKind::Greedy => Arc::new(TrackConsumersPool::new(GreedyMemoryPool::new(limit), top)),
+        };
+        Some(pool)
+    }
+
+    pub fn disk_manager(&self) -> DiskManagerBuilder {
+        let mut builder = DiskManagerBuilder::default();
+        if let Some(dir) = &self.spill_dir {
+            builder = builder.with_mode(DiskManagerMode::Directories(vec![PathBuf::from(dir)]));
+        }
+        if let Some(max) = self.max_spill_bytes {
+            builder = builder.with_max_temp_directory_size(max);
+        }
+        builder
+    }
+
+    pub fn runtime_builder(&self) -> RuntimeEnvBuilder {
+        let mut builder = RuntimeEnvBuilder::new().with_disk_manager_builder(self.disk_manager());
+        if let Some(pool) = self.memory_pool() {
+            builder = builder.with_memory_pool(pool);
+        }
+        builder
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::execution::SessionStateBuilder;
+    use datafusion::prelude::{SessionConfig, SessionContext};
+
+    use super::*;
+
+    #[test]
+    fn resource_spec_parse_test() {
+        let spec: ResourceSpec =
+            serde_json::from_str(r#"{"memory_pool": "greedy", "memory_limit": 1048576, "timeout_secs": 60}"#).unwrap();
+        assert_eq!(spec.memory_pool, MemoryPoolKind::Greedy);
+        assert_eq!(spec.timeout(), Some(Duration::from_secs(60)));
+        assert!(spec.memory_pool().is_some());
+        assert!(ResourceSpec::default().memory_pool().is_none());
+    }
+
+    fn context(resources: &ResourceSpec) -> SessionContext {
+        let runtime = resources.runtime_builder().build_arc().unwrap();
+        let config = SessionConfig::new().with_target_partitions(1);
+        let state = SessionStateBuilder::new()
+            .with_config(config)
+            .with_runtime_env(runtime)
+            .with_default_features()
+            .build();
+        SessionContext::new_with_state(state)
+    }
+
+    const SORT: &str = "SELECT v FROM (SELECT value AS v, repeat('x', 100) AS s FROM generate_series(1, 200000)) ORDER BY s, v DESC";
+
+    #[tokio::test]
+    async fn memory_limit_spill_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-spill-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let resources = ResourceSpec {
+            memory_limit: Some(8 * 1024 * 1024),
+            spill_dir: Some(dir.display().to_string()),
+            ..Default::default()
+        };
+        let ctx = context(&resources);
+        let df = ctx.sql(&format!("SELECT count(*) FROM ({SORT})")).await.unwrap();
+        let batches = df.collect().await.unwrap();
+        let count = batches[0].column(0).as_any().downcast_ref::<Int64Array>().unwrap().value(0);
+        assert_eq!(count, 200000);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn memory_limit_exhausted_test() {
+        let resources = ResourceSpec {
+            memory_limit: Some(8 * 1024 * 1024),
+            max_spill_bytes: Some(0),
+            ..Default::default()
+        };
+        let ctx = context(&resources);
+        let err = ctx.sql(SORT).await.unwrap().collect().await.unwrap_err();
+        assert!(err.to_string().contains("esources exhausted"), "{err}");
+    }
+}
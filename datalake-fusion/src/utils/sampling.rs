@@ -0,0 +1,458 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ballista_core::serde::BallistaLogicalExtensionCodec;
+use color_eyre::{Result, eyre::eyre};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::common::exec_err;
+use datafusion::datasource::file_format::FileFormatFactory;
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::physical_plan::{FileGroup, FileScanConfigBuilder, ParquetSource};
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::execution::object_store::ObjectStoreUrl;
+use datafusion::logical_expr::{Expr, Extension, LogicalPlan, TableType};
+use datafusion::parquet::arrow::ParquetRecordBatchStreamBuilder;
+use datafusion::parquet::arrow::async_reader::ParquetObjectReader;
+use datafusion::parquet::file::metadata::ParquetMetaData;
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::limit::GlobalLimitExec;
+use datafusion::prelude::SessionContext;
+use datafusion::sql::TableReference;
+use datafusion_proto::logical_plan::LogicalExtensionCodec;
+use object_store::{ObjectMeta, ObjectStore};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use tokio_stream::StreamExt;
+
+use crate::TableRef;
+use crate::utils::constants::*;
+
+/// Size of a TABLESAMPLE clause, {"percent": 10.0} or {"rows": 1000}
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleSize {
+    Percent(f64),
+    Rows(u64),
+}
+
+/// Sample of a table set by the lambda from TABLESAMPLE (n PERCENT | n ROWS) [REPEATABLE (seed)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct TableSample {
+    #[serde(flatten)]
+    pub size: SampleSize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// Sample a table was read with, in the result manifest. The seed is the one used,
+/// running the query again with REPEATABLE (seed) reads the same row groups
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SampleReport {
+    pub table: String,
+    #[serde(flatten)]
+    pub size: SampleSize,
+    pub seed: u64,
+    pub files: usize,      // files with at least one sampled row group
+    pub total_files: usize,
+    pub row_groups: usize, // sampled row groups
+}
+
+/// Row group of a parquet file, parquet scans only read row groups starting in the file range
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct SampledRowGroup {
+    path: String,
+    size: u64, // of the file
+    start: i64,
+    end: i64,
+    rows: i64,
+}
+
+/// Parquet table reading sampled row groups only, in random order. Rows samples are cut to
+/// the requested rows, percent samples keep every row group with that probability
+#[derive(Debug)]
+pub struct SampledTable {
+    schema: SchemaRef,
+    state: SampledTableState,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct SampledTableState {
+    store_url: String,
+    row_groups: Vec<SampledRowGroup>,
+    limit: Option<usize>,
+}
+
+#[async_trait]
+impl TableProvider for SampledTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let partitions = state.config().target_partitions().max(1);
+        let mut groups = vec![vec![]; partitions.min(self.state.row_groups.len()).max(1)];
+        let group_count = groups.len();
+        for (i, rg) in self.state.row_groups.iter().enumerate() {
+            groups[i % group_count].push(PartitionedFile::new_with_range(rg.path.clone(), rg.size, rg.start, rg.end));
+        }
+        let source = Arc::new(ParquetSource::new(state.table_options().parquet.clone()));
+        let config = FileScanConfigBuilder::new(ObjectStoreUrl::parse(&self.state.store_url)?, self.schema.clone(), source)
+            .with_file_groups(groups.into_iter().map(FileGroup::new).collect())
+            .with_projection(projection.cloned())
+            .with_limit(limit.or(self.state.limit))
+            .build();
+        let scan: Arc<dyn ExecutionPlan> = DataSourceExec::from_data_source(config);
+        match self.state.limit {
+            // every partition stops at the limit, the sample only has it in total
+            Some(rows) => Ok(Arc::new(GlobalLimitExec::new(
+                Arc::new(CoalescePartitionsExec::new(scan)),
+                0,
+                Some(rows),
+            ))),
+            None => Ok(scan),
+        }
+    }
+}
+
+/// Ballista logical codec which also ships sampled tables to the scheduler,
+/// everything else is left to the default codec. Stock schedulers and executors can't decode them,
+/// only the standalone cluster built from the session state has this codec
+#[derive(Debug, Default)]
+pub struct SampledTableCodec {
+    inner: BallistaLogicalExtensionCodec,
+}
+
+const SAMPLED_TABLE_TAG: &[u8] = b"datalake.sampled_table:";
+
+impl LogicalExtensionCodec for SampledTableCodec {
+    fn try_decode(
+        &self,
+        buf: &[u8],
+        inputs: &[LogicalPlan],
+        ctx: &SessionContext,
+    ) -> datafusion::error::Result<Extension> {
+        self.inner.try_decode(buf, inputs, ctx)
+    }
+
+    fn try_encode(&self, node: &Extension, buf: &mut Vec<u8>) -> datafusion::error::Result<()> {
+        self.inner.try_encode(node, buf)
+    }
+
+    fn try_decode_table_provider(
+        &self,
+        buf: &[u8],
+        table_ref: &TableReference,
+        schema: SchemaRef,
+        ctx: &SessionContext,
+    ) -> datafusion::error::Result<Arc<dyn TableProvider>> {
+        match buf.strip_prefix(SAMPLED_TABLE_TAG) {
+            Some(state) => match serde_json::from_slice(state) {
+                Ok(state) => Ok(Arc::new(SampledTable { schema, state })),
+                Err(e) => exec_err!("invalid sampled table {table_ref}: {e}"),
+            },
+            None => self.inner.try_decode_table_provider(buf, table_ref, schema, ctx),
+        }
+    }
+
+    fn try_encode_table_provider(
+        &self,
+        table_ref: &TableReference,
+        node: Arc<dyn TableProvider>,
+        buf: &mut Vec<u8>,
+    ) -> datafusion::error::Result<()> {
+        match node.as_any().downcast_ref::<SampledTable>() {
+            Some(table) => {
+                buf.extend(SAMPLED_TABLE_TAG);
+                serde_json::to_writer(buf, &table.state).map_err(|e| datafusion::error::DataFusionError::External(e.into()))
+            }
+            None => self.inner.try_encode_table_provider(table_ref, node, buf),
+        }
+    }
+
+    fn try_decode_file_format(
+        &self,
+        buf: &[u8],
+        ctx: &SessionContext,
+    ) -> datafusion::error::Result<Arc<dyn FileFormatFactory>> {
+        self.inner.try_decode_file_format(buf, ctx)
+    }
+
+    fn try_encode_file_format(
+        &self,
+        buf: &mut Vec<u8>,
+        node: Arc<dyn FileFormatFactory>,
+    ) -> datafusion::error::Result<()> {
+        self.inner.try_encode_file_format(buf, node)
+    }
+}
+
+/// Register a parquet table which reads a random sample of its row groups. Footers are read
+/// until rows samples have enough rows, percent samples read the footers of every file
+pub async fn register_sampled(ctx: &SessionContext, table: &TableRef, sample: &TableSample) -> Result<SampleReport> {
+    if let SampleSize::Percent(percent) = sample.size
+        && !(percent > 0.0 && percent <= 100.0)
+    {
+        return Err(eyre!("sample percent must be in (0, 100], got {percent}"));
+    }
+    // schema and files are the ones of the unsampled table
+    ctx.register_parquet(&table.name, &table.path, Default::default()).await?;
+    let schema = ctx.table_provider(&table.name).await?.schema();
+    ctx.deregister_table(&table.name)?;
+
+    let url = ListingTableUrl::parse(&table.path)?;
+    let (store, mut files) = parquet_files(ctx, &url).await?;
+    let total_files = files.len();
+
+    let seed = sample.seed.unwrap_or_else(|| rand::random::<u32>().into());
+    let mut rng = StdRng::seed_from_u64(seed);
+    files.shuffle(&mut rng);
+
+    let mut selected = vec![];
+    let mut rows = 0;
+    for chunk in files.chunks(FOOTER_READS) {
+        let mut footers = JoinSet::new();
+        for (i, file) in chunk.iter().enumerate() {
+            let store = store.clone();
+            let file = file.clone();
+            footers.spawn(async move { (i, row_groups(store, file).await) });
+        }
+        // footers are handled in file order, the sample only depends on the seed
+        let mut chunk_row_groups = vec![vec![]; chunk.len()];
+        while let Some(footer) = footers.join_next().await {
+            let (i, file_row_groups) = footer?;
+            chunk_row_groups[i] = file_row_groups?;
+        }
+        for mut file_row_groups in chunk_row_groups {
+            file_row_groups.shuffle(&mut rng);
+            for rg in file_row_groups {
+                let keep = match sample.size {
+                    SampleSize::Percent(percent) => rng.random_bool(percent / 100.0),
+                    SampleSize::Rows(limit) => rows < limit as i64,
+                };
+                if keep {
+                    rows += rg.rows;
+                    selected.push(rg);
+                }
+            }
+        }
+        if let SampleSize::Rows(limit) = sample.size
+            && rows >= limit as i64
+        {
+            break;
+        }
+    }
+
+    let mut files_sampled: Vec<_> = selected.iter().map(|rg| rg.path.as_str()).collect();
+    files_sampled.sort();
+    files_sampled.dedup();
+    let report = SampleReport {
+        table: table.name.clone(),
+        size: sample.size,
+        seed,
+        files: files_sampled.len(),
+        total_files,
+        row_groups: selected.len(),
+    };
+    let limit = match sample.size {
+        SampleSize::Rows(limit) => Some(limit as usize),
+        SampleSize::Percent(_) => None,
+    };
+    let state = SampledTableState {
+        store_url: url.object_store().as_str().to_string(),
+        row_groups: selected,
+        limit,
+    };
+    ctx.register_table(&table.name, Arc::new(SampledTable { schema, state }))?;
+    Ok(report)
+}
+
+/// Parquet files of the table sorted by path, with the store they are read from
+pub async fn parquet_files(ctx: &SessionContext, url: &ListingTableUrl) -> Result<(Arc<dyn ObjectStore>, Vec<ObjectMeta>)> {
+    let store = ctx.runtime_env().object_store(url.object_store())?;
+    let state = ctx.state();
+    let mut files: Vec<ObjectMeta> = url
+        .list_all_files(&state, store.as_ref(), ".parquet")
+        .await?
+        .collect::<datafusion::error::Result<_>>()
+        .await?;
+    files.sort_by(|a, b| a.location.cmp(&b.location));
+    Ok((store, files))
+}
+
+pub async fn read_footer(store: Arc<dyn ObjectStore>, file: &ObjectMeta) -> Result<Arc<ParquetMetaData>> {
+    let reader = ParquetObjectReader::new(store, file.location.clone()).with_file_size(file.size);
+    Ok(ParquetRecordBatchStreamBuilder::new(reader).await?.metadata().clone())
+}
+
+async fn row_groups(store: Arc<dyn ObjectStore>, file: ObjectMeta) -> Result<Vec<SampledRowGroup>> {
+    let metadata = read_footer(store, &file).await?;
+    let row_groups = metadata
+        .row_groups()
+        .iter()
+        .filter(|rg| rg.num_columns() > 0)
+        .map(|rg| {
+            // same offset the parquet scan checks against the range
+            let column = rg.column(0);
+            let start = column.dictionary_page_offset().unwrap_or_else(|| column.data_page_offset());
+            SampledRowGroup {
+                path: file.location.to_string(),
+                size: file.size,
+                start,
+                end: start + 1,
+                rows: rg.num_rows(),
+            }
+        })
+        .collect();
+    Ok(row_groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use ballista_core::extension::SessionConfigExt;
+    use datafusion::dataframe::DataFrameWriteOptions;
+    use datafusion::execution::SessionStateBuilder;
+    use datafusion::prelude::SessionConfig;
+    use datafusion_proto::bytes::{logical_plan_from_bytes_with_extension_codec, logical_plan_to_bytes_with_extension_codec};
+
+    use super::*;
+    use crate::utils::standalone::{StandaloneConfig, standalone_context};
+
+    /// 4 files of 5 row groups with 10 rows each
+    async fn write_table(dir: &std::path::Path) -> TableRef {
+        let config = SessionConfig::new().set_usize("datafusion.execution.parquet.max_row_group_size", 10);
+        let ctx = SessionContext::new_with_config(config);
+        for file in 0..4 {
+            ctx.sql(&format!("SELECT v + {} AS v FROM generate_series(0, 49) AS t(v)", file * 50))
+                .await
+                .unwrap()
+                .repartition(datafusion::logical_expr::Partitioning::RoundRobinBatch(1))
+                .unwrap()
+                .write_parquet(
+                    dir.join(format!("{file}.parquet")).to_str().unwrap(),
+                    DataFrameWriteOptions::new().with_single_file_output(true),
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        TableRef {
+            name: "t".to_string(),
+            path: format!("{}/", dir.display()),
+            sample: None,
+        }
+    }
+
+    async fn count(ctx: &SessionContext) -> String {
+        let batches = ctx.sql("SELECT count(*) AS n FROM t").await.unwrap().collect().await.unwrap();
+        pretty_format_batches(&batches).unwrap().to_string()
+    }
+
+    #[test]
+    fn table_sample_parse_test() {
+        let sample: TableSample = serde_json::from_str(r#"{"percent": 10.0, "seed": 42}"#).unwrap();
+        assert_eq!(sample, TableSample { size: SampleSize::Percent(10.0), seed: Some(42) });
+        let sample: TableSample = serde_json::from_str(r#"{"rows": 100}"#).unwrap();
+        assert_eq!(sample, TableSample { size: SampleSize::Rows(100), seed: None });
+        assert!(serde_json::from_str::<TableSample>(r#"{"seed": 1}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn register_sampled_rows_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-sample-rows");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = write_table(&dir).await;
+        let ctx = SessionContext::new();
+
+        let sample = TableSample { size: SampleSize::Rows(25), seed: Some(7) };
+        let report = register_sampled(&ctx, &table, &sample).await.unwrap();
+        assert_eq!((report.seed, report.total_files, report.row_groups), (7, 4, 3));
+        assert!(count(&ctx).await.contains("| 25 |"));
+
+        // the same seed reads the same row groups
+        let first = ctx.sql("SELECT v FROM t ORDER BY v").await.unwrap().collect().await.unwrap();
+        ctx.deregister_table("t").unwrap();
+        let again = register_sampled(&ctx, &table, &sample).await.unwrap();
+        assert_eq!(again, report);
+        let second = ctx.sql("SELECT v FROM t ORDER BY v").await.unwrap().collect().await.unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn register_sampled_percent_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-sample-percent");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = write_table(&dir).await;
+        let ctx = SessionContext::new();
+
+        let sample = TableSample { size: SampleSize::Percent(50.0), seed: None };
+        let report = register_sampled(&ctx, &table, &sample).await.unwrap();
+        assert!(report.row_groups > 0 && report.row_groups < 20, "{report:?}");
+        let expected = format!("| {} |", report.row_groups * 10);
+        assert!(count(&ctx).await.contains(&expected));
+
+        ctx.deregister_table("t").unwrap();
+        let all = TableSample { size: SampleSize::Percent(100.0), seed: None };
+        assert_eq!(register_sampled(&ctx, &table, &all).await.unwrap().row_groups, 20);
+        ctx.deregister_table("t").unwrap();
+        let invalid = TableSample { size: SampleSize::Percent(0.0), seed: None };
+        assert!(register_sampled(&ctx, &table, &invalid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sampled_table_codec_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-sample-codec");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = write_table(&dir).await;
+        let ctx = SessionContext::new();
+        let sample = TableSample { size: SampleSize::Rows(15), seed: Some(1) };
+        register_sampled(&ctx, &table, &sample).await.unwrap();
+
+        let codec = SampledTableCodec::default();
+        let plan = ctx.table("t").await.unwrap().into_unoptimized_plan();
+        let bytes = logical_plan_to_bytes_with_extension_codec(&plan, &codec).unwrap();
+        let decoded = logical_plan_from_bytes_with_extension_codec(&bytes, &ctx, &codec).unwrap();
+        let batches = ctx.execute_logical_plan(decoded).await.unwrap().collect().await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 15);
+    }
+
+    #[tokio::test]
+    async fn sampled_table_on_standalone_cluster_test() {
+        let dir = std::env::temp_dir().join("datalake-fusion-sample-standalone");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = write_table(&dir).await;
+        let config = SessionConfig::new_with_ballista()
+            .with_ballista_logical_extension_codec(Arc::new(SampledTableCodec::default()));
+        let state = SessionStateBuilder::new().with_config(config).with_default_features().build();
+        let config = StandaloneConfig { executors: 1, concurrent_tasks: 2, ..Default::default() };
+        let ctx = standalone_context(state, &config).await.unwrap();
+
+        let sample = TableSample { size: SampleSize::Rows(15), seed: Some(1) };
+        register_sampled(&ctx, &table, &sample).await.unwrap();
+        assert!(count(&ctx).await.contains("| 15 |"));
+    }
+}
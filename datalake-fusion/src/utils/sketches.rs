@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{Array, ArrayRef, Float64Array, Int64Array, StringArray, StructArray, UInt64Array};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Field, Fields};
+use datafusion::common::cast::{as_binary_array, as_float64_array, as_int64_array, as_string_array};
+use datafusion::common::utils::SingleRowListArrayBuilder;
+use datafusion::common::{ScalarValue, exec_err};
+use datafusion::error::Result;
+use datafusion::logical_expr::function::AccumulatorArgs;
+use datafusion::logical_expr::{
+    Accumulator, AggregateUDF, ColumnarValue, ScalarUDF, Signature, SimpleAggregateUDF, SimpleScalarUDF, Volatility,
+};
+use datafusion_functions_aggregate_common::tdigest::{DEFAULT_MAX_SIZE, TDigest};
+use twox_hash::XxHash64;
+
+/// First byte of every serialized sketch, bumped when the layout changes
+const SKETCH_VERSION: u8 = 1;
+/// 4096 registers, 4 KiB per sketch, standard error about 1.6%
+const HLL_P: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_P;
+/// Sketches materialized to s3 are merged with new ones, the seed can never change
+const HLL_SEED: u64 = 0x6461_7461_6c61_6b65;
+/// approx_top_k keeps TOPK_CANDIDATES * k counters, pruned once there are twice as many
+const TOPK_CANDIDATES: usize = 10;
+const TOPK_MAX_K: i64 = 1000;
+
+/// HyperLogLog sketch over the utf8 form of the values, so 42 and '42' are the same value
+#[derive(Debug, Clone, PartialEq)]
+struct Hll {
+    registers: Vec<u8>,
+}
+
+impl Hll {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; HLL_REGISTERS],
+        }
+    }
+
+    fn add(&mut self, value: &[u8]) {
+        let hash = XxHash64::oneshot(HLL_SEED, value);
+        let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+        let rank = ((hash >> HLL_P).trailing_zeros().min(64 - HLL_P) + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    fn merge(&mut self, other: &Hll) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // linear counting is more accurate for small cardinalities
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HLL_REGISTERS + 2);
+        bytes.extend([SKETCH_VERSION, HLL_P as u8]);
+        bytes.extend(&self.registers);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [SKETCH_VERSION, p, registers @ ..]
+                if *p as u32 == HLL_P
+                    && registers.len() == HLL_REGISTERS
+                    && registers.iter().all(|r| *r as u32 <= 64 - HLL_P + 1) =>
+            {
+                Ok(Self {
+                    registers: registers.to_vec(),
+                })
+            }
+            _ => exec_err!("invalid hll sketch of {} bytes", bytes.len()),
+        }
+    }
+}
+
+/// TDigest of approx_percentile_cont, serialized from its scalar state
+fn tdigest_to_bytes(digest: &TDigest) -> Vec<u8> {
+    let state = digest.to_scalar_state();
+    let mut bytes = vec![SKETCH_VERSION];
+    bytes.extend((digest.max_size() as u64).to_le_bytes());
+    for value in &state[1..5] {
+        match value {
+            ScalarValue::UInt64(Some(v)) => bytes.extend(v.to_le_bytes()),
+            ScalarValue::Float64(Some(v)) => bytes.extend(v.to_le_bytes()),
+            _ => unreachable!("tdigest state is not null"),
+        }
+    }
+    if let ScalarValue::List(centroids) = &state[5] {
+        let centroids = centroids.values().as_any().downcast_ref::<Float64Array>().expect("f64 centroids");
+        bytes.extend((centroids.len() as u64 / 2).to_le_bytes());
+        for v in centroids.values() {
+            bytes.extend(v.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+fn tdigest_from_bytes(bytes: &[u8]) -> Result<TDigest> {
+    let invalid = || exec_err!("invalid tdigest sketch of {} bytes", bytes.len());
+    let Some((&SKETCH_VERSION, body)) = bytes.split_first() else {
+        return invalid();
+    };
+    let words: Vec<[u8; 8]> = body.chunks(8).filter_map(|c| c.try_into().ok()).collect();
+    if body.len() % 8 != 0 || words.len() < 6 || words.len() != 6 + 2 * u64::from_le_bytes(words[5]) as usize {
+        return invalid();
+    }
+    let (max, min) = (f64::from_le_bytes(words[3]), f64::from_le_bytes(words[4]));
+    let centroids: Vec<f64> = words[6..].iter().map(|w| f64::from_le_bytes(*w)).collect();
+    // TDigest panics on states it didn't produce, check what it asserts on
+    let sorted = centroids.chunks(2).map(|c| c[0]).is_sorted_by(|a, b| a.total_cmp(b).is_le());
+    if !sorted || (min.is_finite() && max.is_finite() && max < min) {
+        return invalid();
+    }
+    let state = [
+        ScalarValue::UInt64(Some(u64::from_le_bytes(words[0]))),
+        ScalarValue::Float64(Some(f64::from_le_bytes(words[1]))),
+        ScalarValue::UInt64(Some(u64::from_le_bytes(words[2]))),
+        ScalarValue::Float64(Some(max)),
+        ScalarValue::Float64(Some(min)),
+        ScalarValue::List(ScalarValue::new_list_nullable(
+            &centroids.into_iter().map(|v| ScalarValue::Float64(Some(v))).collect::<Vec<_>>(),
+            &DataType::Float64,
+        )),
+    ];
+    Ok(TDigest::from_scalar_state(&state))
+}
+
+fn binary_values(array: &ArrayRef) -> Result<Vec<Vec<u8>>> {
+    let array = cast(array, &DataType::Binary)?;
+    Ok(as_binary_array(&array)?.iter().flatten().map(<[u8]>::to_vec).collect())
+}
+
+/// hll_sketch over values, hll_merge over sketches
+#[derive(Debug)]
+struct HllAccumulator {
+    hll: Hll,
+    sketches: bool,
+}
+
+impl Accumulator for HllAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        if self.sketches {
+            return self.merge_batch(values);
+        }
+        let values = cast(&values[0], &DataType::Utf8)?;
+        for value in as_string_array(&values)?.iter().flatten() {
+            self.hll.add(value.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(ScalarValue::Binary(Some(self.hll.to_bytes())))
+    }
+
+    fn size(&self) -> usize {
+        size_of_val(self) + self.hll.registers.capacity()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![self.evaluate()?])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        for sketch in binary_values(&states[0])? {
+            self.hll.merge(&Hll::from_bytes(&sketch)?);
+        }
+        Ok(())
+    }
+}
+
+/// tdigest_sketch over numeric values, tdigest_merge over sketches
+#[derive(Debug)]
+struct TDigestAccumulator {
+    digest: TDigest,
+    sketches: bool,
+}
+
+impl Accumulator for TDigestAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        if self.sketches {
+            return self.merge_batch(values);
+        }
+        let values = cast(&values[0], &DataType::Float64)?;
+        let values: Vec<f64> = as_float64_array(&values)?.iter().flatten().filter(|v| !v.is_nan()).collect();
+        if !values.is_empty() {
+            self.digest = self.digest.merge_unsorted_f64(values);
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(ScalarValue::Binary(Some(tdigest_to_bytes(&self.digest))))
+    }
+
+    fn size(&self) -> usize {
+        size_of_val(self) + self.digest.size()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![self.evaluate()?])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let mut digests = vec![self.digest.clone()];
+        for sketch in binary_values(&states[0])? {
+            digests.push(tdigest_from_bytes(&sketch)?);
+        }
+        self.digest = TDigest::merge_digests(&digests);
+        Ok(())
+    }
+}
+
+/// Counts of the most frequent values. Counters of rare values are pruned, so counts are lower bounds
+/// and values close to the k-th one may be missed when they are spread over many batches
+#[derive(Debug, Default)]
+struct TopKAccumulator {
+    k: usize,
+    counts: HashMap<String, u64>,
+}
+
+impl TopKAccumulator {
+    fn add(&mut self, value: &str, count: u64) {
+        match self.counts.get_mut(value) {
+            Some(c) => *c += count,
+            None => {
+                self.counts.insert(value.to_string(), count);
+            }
+        }
+    }
+
+    fn top(&self, n: usize) -> Vec<(&String, u64)> {
+        let mut counts: Vec<_> = self.counts.iter().map(|(v, c)| (v, *c)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        counts.truncate(n);
+        counts
+    }
+
+    fn prune(&mut self) {
+        let candidates = self.k * TOPK_CANDIDATES;
+        if self.counts.len() > 2 * candidates {
+            let top: HashMap<_, _> = self.top(candidates).into_iter().map(|(v, c)| (v.clone(), c)).collect();
+            self.counts = top;
+        }
+    }
+}
+
+fn topk_item_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("value", DataType::Utf8, false),
+        Field::new("count", DataType::UInt64, false),
+    ])
+}
+
+impl Accumulator for TopKAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        if self.k == 0 {
+            let k = as_int64_array(&cast(&values[1], &DataType::Int64)?)?.iter().flatten().next();
+            match k {
+                Some(k) if (1..=TOPK_MAX_K).contains(&k) => self.k = k as usize,
+                _ => return exec_err!("approx_top_k needs k between 1 and {TOPK_MAX_K}, got {k:?}"),
+            }
+        }
+        let values = cast(&values[0], &DataType::Utf8)?;
+        for value in as_string_array(&values)?.iter().flatten() {
+            self.add(value, 1);
+        }
+        self.prune();
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let top = self.top(self.k);
+        let values = StringArray::from_iter_values(top.iter().map(|(v, _)| v.as_str()));
+        let counts = UInt64Array::from_iter_values(top.iter().map(|(_, c)| *c));
+        let items = StructArray::try_new(
+            topk_item_fields(),
+            vec![Arc::new(values) as ArrayRef, Arc::new(counts) as ArrayRef],
+            None,
+        )?;
+        Ok(SingleRowListArrayBuilder::new(Arc::new(items)).build_list_scalar())
+    }
+
+    fn size(&self) -> usize {
+        size_of_val(self) + self.counts.keys().map(|v| v.capacity() + size_of::<(String, u64)>()).sum::<usize>()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        let counts: Vec<_> = self.counts.iter().collect();
+        Ok(vec![ScalarValue::Binary(Some(serde_json::to_vec(&(self.k, counts)).expect("serializable")))])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        for state in binary_values(&states[0])? {
+            let Ok((k, counts)) = serde_json::from_slice::<(usize, Vec<(String, u64)>)>(&state) else {
+                return exec_err!("invalid approx_top_k state");
+            };
+            self.k = self.k.max(k);
+            for (value, count) in counts {
+                self.add(&value, count);
+            }
+        }
+        self.prune();
+        Ok(())
+    }
+}
+
+fn udaf(
+    name: &str,
+    arguments: usize,
+    return_type: DataType,
+    accumulator: impl Fn() -> Box<dyn Accumulator> + Send + Sync + 'static,
+) -> AggregateUDF {
+    SimpleAggregateUDF::new_with_signature(
+        name,
+        Signature::any(arguments, Volatility::Immutable),
+        return_type,
+        Arc::new(move |_: AccumulatorArgs| Ok(accumulator())),
+        vec![Arc::new(Field::new("state", DataType::Binary, true))],
+    )
+    .into()
+}
+
+/// Aggregates producing sketches, which can be written to s3 with the results and merged later,
+/// e.g. hll_estimate(hll_merge(sketch)) over sketches of every dt
+pub fn aggregate_udfs() -> Vec<AggregateUDF> {
+    let hll = |sketches| Box::new(HllAccumulator { hll: Hll::new(), sketches }) as Box<dyn Accumulator>;
+    let tdigest = |sketches| {
+        Box::new(TDigestAccumulator {
+            digest: TDigest::new(DEFAULT_MAX_SIZE),
+            sketches,
+        }) as Box<dyn Accumulator>
+    };
+    let items = Field::new_list_field(DataType::Struct(topk_item_fields()), true);
+    vec![
+        udaf("hll_sketch", 1, DataType::Binary, move || hll(false)),
+        udaf("hll_merge", 1, DataType::Binary, move || hll(true)),
+        udaf("tdigest_sketch", 1, DataType::Binary, move || tdigest(false)),
+        udaf("tdigest_merge", 1, DataType::Binary, move || tdigest(true)),
+        udaf("approx_top_k", 2, DataType::List(Arc::new(items)), || Box::<TopKAccumulator>::default()),
+    ]
+}
+
+/// Estimates read from sketches
+pub fn sketch_udfs() -> Vec<ScalarUDF> {
+    let hll_estimate = SimpleScalarUDF::new_with_signature(
+        "hll_estimate",
+        Signature::any(1, Volatility::Immutable),
+        DataType::Int64,
+        Arc::new(|args: &[ColumnarValue]| {
+            let args = ColumnarValue::values_to_arrays(args)?;
+            let sketches = cast(&args[0], &DataType::Binary)?;
+            let estimates = as_binary_array(&sketches)?
+                .iter()
+                .map(|s| s.map(|s| Hll::from_bytes(s).map(|hll| hll.estimate() as i64)).transpose())
+                .collect::<Result<Int64Array>>()?;
+            Ok(ColumnarValue::Array(Arc::new(estimates)))
+        }),
+    );
+    let tdigest_quantile = SimpleScalarUDF::new_with_signature(
+        "tdigest_quantile",
+        Signature::any(2, Volatility::Immutable),
+        DataType::Float64,
+        Arc::new(|args: &[ColumnarValue]| {
+            let args = ColumnarValue::values_to_arrays(args)?;
+            let sketches = cast(&args[0], &DataType::Binary)?;
+            let quantiles = cast(&args[1], &DataType::Float64)?;
+            let values = as_binary_array(&sketches)?
+                .iter()
+                .zip(as_float64_array(&quantiles)?.iter())
+                .map(|(sketch, q)| match (sketch, q) {
+                    (Some(_), Some(q)) if !(0.0..=1.0).contains(&q) => {
+                        exec_err!("tdigest_quantile needs a quantile between 0 and 1, got {q}")
+                    }
+                    (Some(sketch), Some(q)) => {
+                        let digest = tdigest_from_bytes(sketch)?;
+                        Ok((digest.count() > 0).then(|| digest.estimate_quantile(q)))
+                    }
+                    _ => Ok(None),
+                })
+                .collect::<Result<Float64Array>>()?;
+            Ok(ColumnarValue::Array(Arc::new(values)))
+        }),
+    );
+    vec![hll_estimate.into(), tdigest_quantile.into()]
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::prelude::SessionContext;
+
+    use super::*;
+    use crate::utils::udfs::register_udfs;
+
+    async fn query(ctx: &SessionContext, sql: &str) -> String {
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        pretty_format_batches(&batches).unwrap().to_string()
+    }
+
+    #[test]
+    fn hll_estimate_test() {
+        let mut hll = Hll::new();
+        for i in 0..100_000 {
+            hll.add(i.to_string().as_bytes());
+            hll.add(i.to_string().as_bytes());
+        }
+        let error = (hll.estimate() as f64 - 100_000.0).abs() / 100_000.0;
+        assert!(error < 0.05, "estimate {}", hll.estimate());
+
+        let mut small = Hll::new();
+        small.extend_from(["a", "b", "c"]);
+        assert_eq!(small.estimate(), 3);
+        assert_eq!(Hll::new().estimate(), 0);
+    }
+
+    impl Hll {
+        fn extend_from<'a>(&mut self, values: impl IntoIterator<Item = &'a str>) {
+            for value in values {
+                self.add(value.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn sketch_bytes_test() {
+        let mut hll = Hll::new();
+        hll.extend_from(["a", "b"]);
+        assert_eq!(Hll::from_bytes(&hll.to_bytes()).unwrap(), hll);
+        assert!(Hll::from_bytes(&hll.to_bytes()[1..]).is_err());
+        assert!(Hll::from_bytes(b"").is_err());
+
+        let digest = TDigest::new(DEFAULT_MAX_SIZE).merge_unsorted_f64((0..1000).map(f64::from).collect());
+        assert_eq!(tdigest_from_bytes(&tdigest_to_bytes(&digest)).unwrap(), digest);
+        let empty = TDigest::new(DEFAULT_MAX_SIZE);
+        assert_eq!(tdigest_from_bytes(&tdigest_to_bytes(&empty)).unwrap().count(), 0);
+        assert!(tdigest_from_bytes(&tdigest_to_bytes(&digest)[..20]).is_err());
+        assert!(tdigest_from_bytes(&[SKETCH_VERSION + 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn merge_sketches_test() {
+        let mut ctx = SessionContext::new();
+        register_udfs(&mut ctx).unwrap();
+        // sketches of two partitions with overlapping values, merged as with materialized sketches,
+        // 1000 distinct values and median 375 exactly
+        ctx.sql(
+            "CREATE TABLE sketches AS SELECT p, hll_sketch(v) AS ids, tdigest_sketch(v) AS sizes \
+             FROM (SELECT v, v % 2 AS p FROM generate_series(1, 1000) AS t(v) \
+             UNION ALL SELECT v, 0 AS p FROM generate_series(1, 500) AS t(v)) GROUP BY p",
+        )
+        .await
+        .unwrap();
+        let result = query(
+            &ctx,
+            "SELECT hll_estimate(hll_merge(arrow_cast(ids, 'BinaryView'))) AS ids, \
+             round(tdigest_quantile(tdigest_merge(sizes), 0.5)) AS median FROM sketches",
+        )
+        .await;
+        let expected = [
+            "+------+--------+",
+            "| ids  | median |",
+            "+------+--------+",
+            "| 1011 | 376.0  |",
+            "+------+--------+",
+        ];
+        assert_eq!(result, expected.join("\n"));
+    }
+
+    #[tokio::test]
+    async fn approx_top_k_test() {
+        let mut ctx = SessionContext::new();
+        register_udfs(&mut ctx).unwrap();
+        let result = query(
+            &ctx,
+            "SELECT approx_top_k(v, 2) AS top FROM (VALUES ('a'), ('b'), ('b'), ('c'), ('c'), ('c'), (NULL)) AS t(v)",
+        )
+        .await;
+        let expected = [
+            "+----------------------------------------------+",
+            "| top                                          |",
+            "+----------------------------------------------+",
+            "| [{value: c, count: 3}, {value: b, count: 2}] |",
+            "+----------------------------------------------+",
+        ];
+        assert_eq!(result, expected.join("\n"));
+
+        let mut top = TopKAccumulator { k: 1, ..Default::default() };
+        for i in 0..100 {
+            top.add(&i.to_string(), 1);
+        }
+        top.add("hot", 50);
+        top.prune();
+        assert_eq!(top.counts.len(), TOPK_CANDIDATES);
+        assert_eq!(top.top(1), vec![(&"hot".to_string(), 50)]);
+
+        let err = ctx.sql("SELECT approx_top_k(v, 0) FROM (VALUES ('a')) AS t(v)").await.unwrap().collect().await;
+        assert!(err.unwrap_err().to_string().contains("approx_top_k needs k between 1 and 1000"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::ops::ControlFlow;
+
+use datafusion::execution::context::SQLOptions;
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::{
+    Expr, ObjectName, Query, SetExpr, Statement, TableFactor, Visit, Visitor,
+};
+use thiserror::Error;
+
+use crate::utils::constants::DENIED_FUNCTIONS;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SqlPolicyError {
+    #[error("SQL parse error: {0}")]
+    SqlParseError(String),
+
+    #[error("Scripts are not supported: got {0} statements, expected a single query")]
+    MultipleStatements(usize),
+
+    #[error("Only read-only queries are allowed: {0}")]
+    ReadOnlyViolation(String),
+
+    #[error("Function is not allowed: {0}")]
+    DeniedFunction(String),
+}
+
+/// Same check as the lambda does before starting the task,
+/// repeated here because the task may be started with any job spec
+#[derive(Debug, Clone, Default)]
+pub struct SqlPolicy {
+    pub denied_functions: Vec<String>, // lowercase names
+}
+
+impl SqlPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            denied_functions: DENIED_FUNCTIONS.clone(),
+        }
+    }
+
+    /// Options for ctx.sql_with_options, plans with DDL, DML or SET fail to build
+    pub fn sql_options() -> SQLOptions {
+        SQLOptions::new()
+            .with_allow_ddl(false)
+            .with_allow_dml(false)
+            .with_allow_statements(false)
+    }
+
+    /// Parse the query with DataFusion dialect and check that it is a single read-only query,
+    /// COPY TO and CREATE EXTERNAL TABLE extensions are rejected
+    pub fn validate(&self, query: &str) -> Result<(), SqlPolicyError> {
+        let statements =
+            DFParser::parse_sql(query).map_err(|e| SqlPolicyError::SqlParseError(e.to_string()))?;
+        if statements.len() != 1 {
+            return Err(SqlPolicyError::MultipleStatements(statements.len()));
+        }
+        let statement = match &statements[0] {
+            DFStatement::Statement(statement) => statement,
+            DFStatement::CreateExternalTable(_) => {
+                return Err(SqlPolicyError::ReadOnlyViolation("CREATE EXTERNAL TABLE".to_string()));
+            }
+            DFStatement::CopyTo(_) => {
+                return Err(SqlPolicyError::ReadOnlyViolation("COPY".to_string()));
+            }
+            DFStatement::Explain(_) => {
+                return Err(SqlPolicyError::ReadOnlyViolation("EXPLAIN".to_string()));
+            }
+        };
+        let mut visitor = ReadOnlyVisitor { policy: self };
+        match statement.visit(&mut visitor) {
+            ControlFlow::Break(e) => Err(e),
+            ControlFlow::Continue(()) => Ok(()),
+        }
+    }
+
+    fn check_function(&self, name: &ObjectName) -> ControlFlow<SqlPolicyError> {
+        let function = name
+            .0
+            .last()
+            .and_then(|part| part.as_ident())
+            .map(|ident| ident.value.to_lowercase())
+            .unwrap_or_default();
+        if self.denied_functions.contains(&function) {
+            return ControlFlow::Break(SqlPolicyError::DeniedFunction(function));
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct ReadOnlyVisitor<'a> {
+    policy: &'a SqlPolicy,
+}
+
+impl Visitor for ReadOnlyVisitor<'_> {
+    type Break = SqlPolicyError;
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        match statement {
+            Statement::Query(_) => ControlFlow::Continue(()),
+            other => ControlFlow::Break(SqlPolicyError::ReadOnlyViolation(statement_kind(other))),
+        }
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        if !query.locks.is_empty() {
+            return ControlFlow::Break(SqlPolicyError::ReadOnlyViolation("FOR UPDATE".to_string()));
+        }
+        check_set_expr(&query.body)
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        match table_factor {
+            TableFactor::Table { name, args: Some(_), .. } => self.policy.check_function(name),
+            TableFactor::Function { name, .. } => self.policy.check_function(name),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        match expr {
+            Expr::Function(function) => self.policy.check_function(&function.name),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+fn check_set_expr(set_expr: &SetExpr) -> ControlFlow<SqlPolicyError> {
+    match set_expr {
+        SetExpr::Select(select) if select.into.is_some() => {
+            ControlFlow::Break(SqlPolicyError::ReadOnlyViolation("SELECT INTO".to_string()))
+        }
+        SetExpr::SetOperation { left, right, .. } => {
+            check_set_expr(left)?;
+            check_set_expr(right)
+        }
+        SetExpr::Insert(statement) | SetExpr::Update(statement) => {
+            ControlFlow::Break(SqlPolicyError::ReadOnlyViolation(statement_kind(statement)))
+        }
+        SetExpr::Table(_) => {
+            ControlFlow::Break(SqlPolicyError::ReadOnlyViolation("TABLE statement".to_string()))
+        }
+        _ => ControlFlow::Continue(()),
+    }
+}
+
+/// Statement name for error messages, e.g. DROP TABLE x -> "DROP"
+fn statement_kind(statement: &Statement) -> String {
+    statement
+        .to_string()
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn policy() -> SqlPolicy {
+        SqlPolicy {
+            denied_functions: vec!["generate_series".to_string()],
+        }
+    }
+
+    #[rstest]
+    #[case("select * from \"images\" limit 10", Ok(()))]
+    #[case("with x as (select * from foo) select count(*) from x", Ok(()))]
+    #[case("select 1; drop table x", Err(SqlPolicyError::MultipleStatements(2)))]
+    #[case("copy (select 1) to 's3://bucket/out.parquet'", Err(SqlPolicyError::ReadOnlyViolation("COPY".to_string())))]
+    #[case("create external table foo stored as parquet location 's3://bucket/foo/'", Err(SqlPolicyError::ReadOnlyViolation("CREATE EXTERNAL TABLE".to_string())))]
+    #[case("set datafusion.execution.batch_size = 1", Err(SqlPolicyError::ReadOnlyViolation("SET".to_string())))]
+    #[case("insert into foo values (1)", Err(SqlPolicyError::ReadOnlyViolation("INSERT".to_string())))]
+    #[case("select * from generate_series(1, 10)", Err(SqlPolicyError::DeniedFunction("generate_series".to_string())))]
+    fn validate_test(#[case] input: &str, #[case] expected: Result<(), SqlPolicyError>) {
+        assert_eq!(policy().validate(input), expected);
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use ballista_core::extension::SessionStateExt;
+use ballista_core::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
+use color_eyre::{Result, eyre::eyre};
+use datafusion::execution::SessionState;
+use datafusion::prelude::SessionContext;
+use tokio::time::{Instant, sleep, timeout};
+
+use crate::utils::constants::*;
+
+/// In-process Ballista cluster, replaces separate scheduler and executor processes
+#[derive(Debug, Clone)]
+pub struct StandaloneConfig {
+    pub executors: usize,
+    pub concurrent_tasks: usize, // task slots of every executor
+    pub ready_timeout: Duration,
+}
+
+impl Default for StandaloneConfig {
+    fn default() -> Self {
+        Self {
+            executors: STANDALONE_EXECUTORS,
+            concurrent_tasks: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ready_timeout: Duration::from_secs(STANDALONE_READY_TIMEOUT),
+        }
+    }
+}
+
+/// Start scheduler and executors in this process, the context is returned once a probe query ran
+pub async fn standalone_context(state: SessionState, config: &StandaloneConfig) -> Result<SessionContext> {
+    if config.executors == 0 || config.concurrent_tasks == 0 {
+        return Err(eyre!("standalone cluster needs at least one executor and task slot"));
+    }
+    let deadline = Instant::now() + config.ready_timeout;
+
+    let addr = ballista_scheduler::standalone::new_standalone_scheduler_from_state(&state).await?;
+    let scheduler_url = format!("http://localhost:{}", addr.port());
+    let scheduler = loop {
+        match SchedulerGrpcClient::connect(scheduler_url.clone()).await {
+            Ok(scheduler) => break scheduler,
+            Err(e) if Instant::now() >= deadline => {
+                return Err(eyre!("scheduler {scheduler_url} is not ready: {e}"));
+            }
+            Err(_) => sleep(Duration::from_millis(100)).await,
+        }
+    };
+    tracing::info!("scheduler is listening on {scheduler_url}");
+
+    for _ in 0..config.executors {
+        ballista_executor::new_standalone_executor_from_state(scheduler.clone(), config.concurrent_tasks, &state)
+            .await?;
+    }
+
+    let ctx = SessionContext::new_with_state(state.upgrade_for_ballista(scheduler_url)?);
+    // executors pull work from the scheduler, a query only finishes once one of them polled
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    timeout(remaining, async { ctx.sql("SELECT 1").await?.collect().await })
+        .await
+        .map_err(|_| eyre!("executors are not ready after {:?}", config.ready_timeout))??;
+    tracing::info!({ executors = config.executors }, "executors are ready");
+    Ok(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::array::Int64Array;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn standalone_context_test() {
+        let config = StandaloneConfig {
+            executors: 2,
+            concurrent_tasks: 2,
+            ready_timeout: Duration::from_secs(30),
+        };
+        let ctx = standalone_context(SessionContext::new().state(), &config).await.unwrap();
+        let batches = ctx
+            .sql("SELECT count(*) FROM (VALUES (1), (2), (3)) AS t(a)")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let count = batches[0].column(0).as_any().downcast_ref::<Int64Array>().unwrap().value(0);
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    async fn standalone_context_no_executors_test() {
+        let config = StandaloneConfig {
+            executors: 0,
+            ..Default::default()
+        };
+        assert!(standalone_context(SessionContext::new().state(), &config).await.is_err());
+    }
+}
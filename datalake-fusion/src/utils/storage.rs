@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use ballista_core::extension::SessionConfigExt;
+use ballista_core::object_store::session_config_with_s3_support;
+use bytes::Bytes;
+use color_eyre::Result;
+use datafusion::common::exec_err;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::execution::object_store::{DefaultObjectStoreRegistry, ObjectStoreRegistry};
+use datafusion::execution::{SessionState, SessionStateBuilder};
+use datafusion::prelude::{SessionConfig, SessionContext};
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{BackoffConfig, ObjectStore, PutPayload, RetryConfig};
+use url::Url;
+
+use crate::utils::constants::*;
+use crate::utils::credentials::AwsCredentialProvider;
+use crate::utils::resources::ResourceSpec;
+use crate::utils::sampling::SampledTableCodec;
+use crate::utils::udfs::register_udfs;
+use crate::utils::upload::MultipartStore;
+
+/// Object storage settings, s3 (or s3 compatible endpoint) and local file system
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub region: String,
+    pub endpoint: Option<String>,
+}
+
+impl StorageConfig {
+    pub fn from_env() -> Self {
+        Self {
+            region: REGION.to_string(),
+            endpoint: S3_ENDPOINT.clone(),
+        }
+    }
+
+    pub fn session_config(&self) -> Result<SessionConfig> {
+        let mut config = session_config_with_s3_support();
+        let options = config.options_mut();
+        options.set("s3.region", &self.region)?;
+        if let Some(endpoint) = &self.endpoint {
+            // object store uses path-style requests for custom endpoints
+            options.set("s3.endpoint", endpoint)?;
+            options.set("s3.allow_http", &endpoint.starts_with("http://").to_string())?;
+        }
+        // COPY TO uploads results in parts of the writer buffer size
+        options.execution.objectstore_writer_buffer_size = CHUNK_SIZE;
+        // sampled tables are planned by the scheduler too
+        Ok(config.with_ballista_logical_extension_codec(Arc::new(SampledTableCodec::default())))
+    }
+}
+
+/// Resolves s3:// urls with stores signed by the AWS default credential chain,
+/// other urls (file://) with the default registry. Standalone executors are built from the
+/// session state and share it, that is why fusion doesn't run on remote executors
+#[derive(Debug)]
+pub struct StorageRegistry {
+    storage: StorageConfig,
+    credentials: Arc<AwsCredentialProvider>,
+    buckets: RwLock<HashMap<String, Arc<dyn ObjectStore>>>,
+    default: DefaultObjectStoreRegistry,
+}
+
+impl StorageRegistry {
+    pub fn new(storage: StorageConfig, credentials: Arc<AwsCredentialProvider>) -> Self {
+        Self {
+            storage,
+            credentials,
+            buckets: RwLock::new(HashMap::new()),
+            default: DefaultObjectStoreRegistry::new(),
+        }
+    }
+
+    fn s3_store(&self, bucket: &str) -> datafusion::error::Result<Arc<dyn ObjectStore>> {
+        if let Some(store) = self.buckets.read().unwrap().get(bucket) {
+            return Ok(store.clone());
+        }
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(bucket)
+            .with_region(&self.storage.region)
+            .with_credentials(self.credentials.clone())
+            .with_retry(RetryConfig {
+                backoff: BackoffConfig {
+                    init_backoff: Duration::from_millis(CHUNK_RETRY_BACKOFF_MS),
+                    ..Default::default()
+                },
+                max_retries: CHUNKS_MAX_RETRY,
+                ..Default::default()
+            });
+        if let Some(endpoint) = &self.storage.endpoint {
+            // object store uses path-style requests for custom endpoints
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        let store: Arc<dyn ObjectStore> = Arc::new(MultipartStore::new(Arc::new(builder.build()?)));
+        self.buckets.write().unwrap().insert(bucket.to_string(), store.clone());
+        Ok(store)
+    }
+}
+
+impl ObjectStoreRegistry for StorageRegistry {
+    fn register_store(&self, url: &Url, store: Arc<dyn ObjectStore>) -> Option<Arc<dyn ObjectStore>> {
+        self.default.register_store(url, store)
+    }
+
+    fn get_store(&self, url: &Url) -> datafusion::error::Result<Arc<dyn ObjectStore>> {
+        match url.scheme() {
+            "s3" => match url.host_str() {
+                Some(bucket) => self.s3_store(bucket),
+                None => exec_err!("s3 url without bucket: {url}"),
+            },
+            _ => self.default.get_store(url),
+        }
+    }
+}
+
+/// Session state which resolves s3:// and file:// urls, with memory pool and spill dir of the job
+/// and the domain udfs
+pub async fn state_with_storage(storage: &StorageConfig, resources: &ResourceSpec) -> Result<SessionState> {
+    let credentials = Arc::new(AwsCredentialProvider::from_default_chain(&storage.region).await);
+    let registry = StorageRegistry::new(storage.clone(), credentials);
+    let runtime = resources
+        .runtime_builder()
+        .with_object_store_registry(Arc::new(registry))
+        .build_arc()?;
+    let mut state = SessionStateBuilder::new()
+        .with_config(storage.session_config()?)
+        .with_runtime_env(runtime)
+        .with_default_features()
+        .build();
+    register_udfs(&mut state)?;
+    Ok(state)
+}
+
+/// Object store the session resolves url with and the path of url in it
+pub fn object_store(ctx: &SessionContext, url: &str) -> Result<(Arc<dyn ObjectStore>, Path)> {
+    let url = ListingTableUrl::parse(url)?;
+    let store = ctx.runtime_env().object_store(url.object_store())?;
+    Ok((store, url.prefix().clone()))
+}
+
+/// Put small object (manifest, job record) with the object store the session resolves url with
+pub async fn put_object(ctx: &SessionContext, url: &str, body: Vec<u8>) -> Result<()> {
+    let (store, path) = object_store(ctx, url)?;
+    store.put(&path, PutPayload::from(body)).await?;
+    Ok(())
+}
+
+/// Get small object, None when it doesn't exist
+pub async fn get_object(ctx: &SessionContext, url: &str) -> Result<Option<Bytes>> {
+    let (store, path) = object_store(ctx, url)?;
+    match store.get(&path).await {
+        Ok(object) => Ok(Some(object.bytes().await?)),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_credential_types::Credentials;
+    use aws_credential_types::provider::SharedCredentialsProvider;
+
+    use super::*;
+
+    fn registry() -> StorageRegistry {
+        let credentials = Credentials::new("key", "secret", None, None, "test");
+        let provider = AwsCredentialProvider::new(SharedCredentialsProvider::new(credentials));
+        let storage = StorageConfig {
+            region: REGION.to_string(),
+            endpoint: Some("http://localhost:9000".to_string()),
+        };
+        StorageRegistry::new(storage, Arc::new(provider))
+    }
+
+    #[test]
+    fn storage_registry_test() {
+        let registry = registry();
+        let a = registry.get_store(&Url::parse("s3://bucket-a/x").unwrap()).unwrap();
+        let a2 = registry.get_store(&Url::parse("s3://bucket-a/y").unwrap()).unwrap();
+        let b = registry.get_store(&Url::parse("s3://bucket-b/x").unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&a, &a2));
+        assert!(!Arc::ptr_eq(&a, &b));
+        assert!(registry.get_store(&Url::parse("file:///tmp").unwrap()).is_ok());
+        assert!(registry.get_store(&Url::parse("gs://bucket/x").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use color_eyre::Result;
+use sha2::{Digest, Sha256};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::utils::telemetry::Telemetry;
+
+/// JSON logs in the lambda format. Events carry the fields of all their spans (request_id,
+/// query_hash, table_name, ...), phases log elapsed_ms when they finish.
+/// Logs go to stderr, stdout is left to cli output. Spans are exported too when telemetry is enabled
+pub fn init_tracing() -> Result<Option<Telemetry>> {
+    let telemetry = Telemetry::from_env()?;
+    let logs = tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .with_writer(std::io::stderr);
+    let registry = tracing_subscriber::registry().with(LevelFilter::INFO).with(logs);
+    #[cfg(feature = "otel")]
+    let registry = registry.with(telemetry.as_ref().map(Telemetry::layer));
+    registry.init();
+    Ok(telemetry)
+}
+
+/// Short id of the query text, runs of the same query can be grouped without logging it
+pub fn query_hash(query: &str) -> String {
+    hex::encode(&Sha256::digest(query.as_bytes())[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_hash_test() {
+        assert_eq!(query_hash("SELECT 1"), query_hash("SELECT 1"));
+        assert_ne!(query_hash("SELECT 1"), query_hash("SELECT 2"));
+        assert_eq!(query_hash("SELECT 1").len(), 16);
+    }
+}
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate};
+use datafusion::arrow::array::{ArrayRef, Date32Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Date32Type};
+use datafusion::common::cast::{as_int64_array, as_string_array};
+use datafusion::error::Result;
+use datafusion::execution::FunctionRegistry;
+use datafusion::logical_expr::{ColumnarValue, ScalarUDF, Volatility, create_udf};
+use serde::Serialize;
+
+use crate::utils::sketches::{aggregate_udfs, sketch_udfs};
+
+/// Documentation of a domain function, served to the ui by the lambda GET /functions route
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FunctionDoc {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+    pub example: &'static str,
+}
+
+pub const FUNCTIONS: [FunctionDoc; 12] = [
+    FunctionDoc {
+        name: "file_extension",
+        signature: "file_extension(file_name VARCHAR) -> VARCHAR",
+        description: "Lowercase extension of the last path segment, NULL when there is none",
+        example: "file_extension('images/IMG_01.JPG') = 'jpg'",
+    },
+    FunctionDoc {
+        name: "extract_or_id",
+        signature: "extract_or_id(key VARCHAR) -> VARCHAR",
+        description: "Value of the or_id=... segment of an object key, NULL when the key has none",
+        example: "extract_or_id('images/or_id=42/dt=2021-01-01/a.jpg') = '42'",
+    },
+    FunctionDoc {
+        name: "human_size",
+        signature: "human_size(file_size BIGINT) -> VARCHAR",
+        description: "Size in bytes formatted with binary units and one decimal",
+        example: "human_size(1536) = '1.5 KiB'",
+    },
+    FunctionDoc {
+        name: "size_bucket",
+        signature: "size_bucket(file_size BIGINT) -> VARCHAR",
+        description: "Binary unit of the size magnitude (B, KiB, MiB, GiB, TiB), for GROUP BY",
+        example: "size_bucket(3145728) = 'MiB'",
+    },
+    FunctionDoc {
+        name: "normalize_dt",
+        signature: "normalize_dt(dt VARCHAR) -> DATE",
+        description: "Date of YYYY-MM-DD, YYYYMMDD, YYYY/MM/DD, dt=... or RFC 3339 strings, NULL otherwise",
+        example: "normalize_dt('dt=20210101') = DATE '2021-01-01'",
+    },
+    FunctionDoc {
+        name: "hll_estimate",
+        signature: "hll_estimate(sketch BYTEA) -> BIGINT",
+        description: "Distinct count estimated from a hll_sketch or hll_merge sketch",
+        example: "hll_estimate(hll_sketch(or_id))",
+    },
+    FunctionDoc {
+        name: "tdigest_quantile",
+        signature: "tdigest_quantile(sketch BYTEA, q DOUBLE) -> DOUBLE",
+        description: "Quantile q between 0 and 1 estimated from a tdigest_sketch or tdigest_merge sketch",
+        example: "tdigest_quantile(tdigest_sketch(file_size), 0.95)",
+    },
+    FunctionDoc {
+        name: "hll_sketch",
+        signature: "hll_sketch(value ANY) -> BYTEA",
+        description: "Aggregate, HyperLogLog sketch of distinct values (about 1.6% error), mergeable with hll_merge",
+        example: "SELECT dt, hll_sketch(or_id) AS or_ids FROM t GROUP BY dt",
+    },
+    FunctionDoc {
+        name: "hll_merge",
+        signature: "hll_merge(sketch BYTEA) -> BYTEA",
+        description: "Aggregate, union of hll sketches, e.g. of materialized daily sketches",
+        example: "hll_estimate(hll_merge(or_ids))",
+    },
+    FunctionDoc {
+        name: "tdigest_sketch",
+        signature: "tdigest_sketch(value DOUBLE) -> BYTEA",
+        description: "Aggregate, t-digest sketch of numeric values for approximate percentiles, mergeable with tdigest_merge",
+        example: "SELECT dt, tdigest_sketch(file_size) AS sizes FROM t GROUP BY dt",
+    },
+    FunctionDoc {
+        name: "tdigest_merge",
+        signature: "tdigest_merge(sketch BYTEA) -> BYTEA",
+        description: "Aggregate, union of t-digest sketches",
+        example: "tdigest_quantile(tdigest_merge(sizes), 0.5)",
+    },
+    FunctionDoc {
+        name: "approx_top_k",
+        signature: "approx_top_k(value ANY, k BIGINT) -> ARRAY<STRUCT<value VARCHAR, count BIGINT UNSIGNED>>",
+        description: "Aggregate, k most frequent values with their counts, counts are lower bounds (k up to 1000)",
+        example: "approx_top_k(file_extension(file_name), 10)",
+    },
+];
+
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Domain scalar functions and sketch estimates, in the order of FUNCTIONS
+pub fn udfs() -> Vec<ScalarUDF> {
+    let mut udfs = vec![
+        string_udf("file_extension", file_extension),
+        string_udf("extract_or_id", extract_or_id),
+        size_udf("human_size", human_size),
+        size_udf("size_bucket", |size| UNITS[unit_index(size)].to_string()),
+        create_udf(
+            "normalize_dt",
+            vec![DataType::Utf8],
+            DataType::Date32,
+            Volatility::Immutable,
+            Arc::new(|args: &[ColumnarValue]| {
+                let args = ColumnarValue::values_to_arrays(args)?;
+                let dates: Date32Array = as_string_array(&args[0])?
+                    .iter()
+                    .map(|dt| dt.and_then(normalize_dt).map(Date32Type::from_naive_date))
+                    .collect();
+                Ok(ColumnarValue::Array(Arc::new(dates) as ArrayRef))
+            }),
+        ),
+    ];
+    udfs.extend(sketch_udfs());
+    udfs
+}
+
+/// Register the domain functions and sketch aggregates on a session state or context. Ballista
+/// standalone scheduler and executors are built from the state and get them too, stock executors
+/// don't know them, one more reason fusion runs standalone only
+pub fn register_udfs(registry: &mut dyn FunctionRegistry) -> Result<()> {
+    for udf in udfs() {
+        registry.register_udf(Arc::new(udf))?;
+    }
+    for udaf in aggregate_udfs() {
+        registry.register_udaf(Arc::new(udaf))?;
+    }
+    Ok(())
+}
+
+fn string_udf(name: &str, f: fn(&str) -> Option<String>) -> ScalarUDF {
+    create_udf(
+        name,
+        vec![DataType::Utf8],
+        DataType::Utf8,
+        Volatility::Immutable,
+        Arc::new(move |args: &[ColumnarValue]| {
+            let args = ColumnarValue::values_to_arrays(args)?;
+            let values: StringArray = as_string_array(&args[0])?.iter().map(|s| s.and_then(f)).collect();
+            Ok(ColumnarValue::Array(Arc::new(values) as ArrayRef))
+        }),
+    )
+}
+
+fn size_udf(name: &str, f: fn(u64) -> String) -> ScalarUDF {
+    create_udf(
+        name,
+        vec![DataType::Int64],
+        DataType::Utf8,
+        Volatility::Immutable,
+        Arc::new(move |args: &[ColumnarValue]| {
+            let args = ColumnarValue::values_to_arrays(args)?;
+            // negative sizes are invalid, they map to NULL like missing ones
+            let values: StringArray = as_int64_array(&args[0])?
+                .iter()
+                .map(|size| size.and_then(|s| u64::try_from(s).ok()).map(f))
+                .collect();
+            Ok(ColumnarValue::Array(Arc::new(values) as ArrayRef))
+        }),
+    )
+}
+
+fn file_extension(file_name: &str) -> Option<String> {
+    let name = file_name.rsplit('/').next()?;
+    match name.rsplit_once('.') {
+        // dot files like .env have no extension
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => Some(ext.to_lowercase()),
+        _ => None,
+    }
+}
+
+fn extract_or_id(key: &str) -> Option<String> {
+    key.split('/')
+        .find_map(|segment| segment.strip_prefix("or_id="))
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
+fn unit_index(size: u64) -> usize {
+    let mut index = 0;
+    let mut value = size;
+    while value >= 1024 && index < UNITS.len() - 1 {
+        value /= 1024;
+        index += 1;
+    }
+    index
+}
+
+fn human_size(size: u64) -> String {
+    let index = unit_index(size);
+    if index == 0 {
+        return format!("{size} B");
+    }
+    let value = size as f64 / 1024f64.powi(index as i32);
+    format!("{value:.1} {}", UNITS[index])
+}
+
+fn normalize_dt(dt: &str) -> Option<NaiveDate> {
+    let dt = dt.trim();
+    let dt = dt.strip_prefix("dt=").unwrap_or(dt);
+    ["%Y-%m-%d", "%Y%m%d", "%Y/%m/%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(dt, format).ok())
+        .or_else(|| DateTime::parse_from_rfc3339(dt).ok().map(|t| t.date_naive()))
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::prelude::SessionContext;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::utils::standalone::{StandaloneConfig, standalone_context};
+
+    #[rstest]
+    #[case("images/IMG_01.JPG", Some("jpg"))]
+    #[case("archive.tar.gz", Some("gz"))]
+    #[case("dir.d/README", None)]
+    #[case(".env", None)]
+    #[case("trailing.", None)]
+    fn file_extension_test(#[case] input: &str, #[case] expected: Option<&str>) {
+        assert_eq!(file_extension(input).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("images/or_id=42/dt=2021-01-01/a.jpg", Some("42"))]
+    #[case("or_id=abc-1/a.jpg", Some("abc-1"))]
+    #[case("images/or_id=/a.jpg", None)]
+    #[case("images/partner_or_id=42/a.jpg", None)]
+    fn extract_or_id_test(#[case] input: &str, #[case] expected: Option<&str>) {
+        assert_eq!(extract_or_id(input).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case(0, "0 B", "B")]
+    #[case(1023, "1023 B", "B")]
+    #[case(1536, "1.5 KiB", "KiB")]
+    #[case(3 * 1024 * 1024, "3.0 MiB", "MiB")]
+    #[case(5 * 1024u64.pow(5), "5120.0 TiB", "TiB")]
+    fn size_test(#[case] size: u64, #[case] human: &str, #[case] bucket: &str) {
+        assert_eq!(human_size(size), human);
+        assert_eq!(UNITS[unit_index(size)], bucket);
+    }
+
+    #[rstest]
+    #[case("2021-01-01", Some((2021, 1, 1)))]
+    #[case("dt=20210102", Some((2021, 1, 2)))]
+    #[case(" 2021/01/03 ", Some((2021, 1, 3)))]
+    #[case("2021-01-04T23:30:00+02:00", Some((2021, 1, 4)))]
+    #[case("2021-02-30", None)]
+    #[case("yesterday", None)]
+    fn normalize_dt_test(#[case] input: &str, #[case] expected: Option<(i32, u32, u32)>) {
+        let expected = expected.map(|(y, m, d)| NaiveDate::from_ymd_opt(y, m, d).unwrap());
+        assert_eq!(normalize_dt(input), expected);
+    }
+
+    #[test]
+    fn functions_documented_test() {
+        let mut names: Vec<_> = udfs().iter().map(|udf| udf.name().to_string()).collect();
+        names.extend(aggregate_udfs().iter().map(|udaf| udaf.name().to_string()));
+        let documented: Vec<_> = FUNCTIONS.iter().map(|f| f.name.to_string()).collect();
+        assert_eq!(names, documented);
+    }
+
+    #[tokio::test]
+    async fn register_udfs_test() {
+        let mut ctx = SessionContext::new();
+        register_udfs(&mut ctx).unwrap();
+        let batches = ctx
+            .sql(
+                "SELECT file_extension(arrow_cast(k, 'Utf8View')) AS ext, extract_or_id(k) AS or_id, \
+                 human_size(s) AS size, size_bucket(s) AS bucket, normalize_dt('dt=20210101') AS dt \
+                 FROM (VALUES ('or_id=7/a.PNG', 2048), (NULL, NULL)) AS t(k, s)",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let expected = [
+            "+-----+-------+---------+--------+------------+",
+            "| ext | or_id | size    | bucket | dt         |",
+            "+-----+-------+---------+--------+------------+",
+            "| png | 7     | 2.0 KiB | KiB    | 2021-01-01 |",
+            "|     |       |         |        | 2021-01-01 |",
+            "+-----+-------+---------+--------+------------+",
+        ];
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected.join("\n"));
+    }
+
+    #[tokio::test]
+    async fn udfs_on_standalone_executors_test() {
+        // memory tables can't be shipped to executors, the query reads a parquet file
+        let dir = std::env::temp_dir().join("datalake-fusion-udfs-standalone");
+        let _ = std::fs::remove_dir_all(&dir);
+        SessionContext::new()
+            .sql("SELECT * FROM (VALUES ('a.PNG', 2048), ('b.png', 2048)) AS t(k, s)")
+            .await
+            .unwrap()
+            .write_parquet(dir.to_str().unwrap(), Default::default(), None)
+            .await
+            .unwrap();
+
+        let mut state = SessionContext::new().state();
+        register_udfs(&mut state).unwrap();
+        let config = StandaloneConfig { executors: 1, concurrent_tasks: 2, ..Default::default() };
+        let ctx = standalone_context(state, &config).await.unwrap();
+        ctx.register_parquet("t", dir.to_str().unwrap(), Default::default()).await.unwrap();
+        let batches = ctx
+            .sql(
+                "SELECT file_extension(k) AS ext, human_size(s) AS size, hll_estimate(hll_sketch(k)) AS n \
+                 FROM t GROUP BY 1, 2",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let expected = [
+            "+-----+---------+---+",
+            "| ext | size    | n |",
+            "+-----+---------+---+",
+            "| png | 2.0 KiB | 2 |",
+            "+-----+---------+---+",
+        ];
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected.join("\n"));
+    }
+}
@@ -0,0 +1,323 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOptions, PutOptions,
+    PutPayload, PutResult, UploadPart,
+};
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+use crate::utils::constants::*;
+
+const STORE: &str = "MultipartStore";
+
+/// Object store whose multipart uploads (COPY TO results) send at most CHUNKS_WORKERS parts at once
+/// and are aborted when they fail or are dropped unfinished. DataFusion drops the writer of a failed
+/// query without aborting, S3 would keep and bill the uploaded parts
+#[derive(Debug)]
+pub struct MultipartStore {
+    inner: Arc<dyn ObjectStore>,
+}
+
+impl MultipartStore {
+    pub fn new(inner: Arc<dyn ObjectStore>) -> Self {
+        Self { inner }
+    }
+}
+
+impl fmt::Display for MultipartStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MultipartStore({})", self.inner)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MultipartStore {
+    async fn put_opts(&self, location: &Path, payload: PutPayload, opts: PutOptions) -> object_store::Result<PutResult> {
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOptions,
+    ) -> object_store::Result<Box<dyn MultipartUpload>> {
+        let upload = self.inner.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(ChunkedUpload {
+            inner: Some(upload),
+            workers: Arc::new(Semaphore::new(CHUNKS_WORKERS)),
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> object_store::Result<GetResult> {
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<u64>]) -> object_store::Result<Vec<Bytes>> {
+        self.inner.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        self.inner.delete(location).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, object_store::Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'static, object_store::Result<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+/// Upload of MultipartStore, None once completed or aborted
+#[derive(Debug)]
+struct ChunkedUpload {
+    inner: Option<Box<dyn MultipartUpload>>,
+    workers: Arc<Semaphore>,
+}
+
+fn finished() -> object_store::Error {
+    object_store::Error::Generic { store: STORE, source: "upload already completed or aborted".into() }
+}
+
+#[async_trait]
+impl MultipartUpload for ChunkedUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let Some(inner) = self.inner.as_mut() else {
+            return Box::pin(async { Err(finished()) });
+        };
+        // the part number is taken now, the request waits for a worker
+        let part = inner.put_part(data);
+        let workers = self.workers.clone();
+        Box::pin(async move {
+            let _worker = workers
+                .acquire_owned()
+                .await
+                .map_err(|e| object_store::Error::Generic { store: STORE, source: Box::new(e) })?;
+            part.await
+        })
+    }
+
+    async fn complete(&mut self) -> object_store::Result<PutResult> {
+        let inner = self.inner.as_mut().ok_or_else(finished)?;
+        match inner.complete().await {
+            Ok(result) => {
+                self.inner = None;
+                Ok(result)
+            }
+            Err(e) => {
+                if let Err(abort) = self.abort().await {
+                    tracing::error!("failed to abort upload: {abort}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn abort(&mut self) -> object_store::Result<()> {
+        match self.inner.take() {
+            Some(mut inner) => inner.abort().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ChunkedUpload {
+    fn drop(&mut self) {
+        if let Some(mut inner) = self.inner.take()
+            && let Ok(handle) = Handle::try_current()
+        {
+            handle.spawn(async move {
+                if let Err(e) = inner.abort().await {
+                    tracing::error!("failed to abort upload: {e}");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use datafusion::dataframe::DataFrameWriteOptions;
+    use datafusion::prelude::{SessionConfig, SessionContext};
+    use object_store::buffered::BufWriter;
+    use object_store::memory::InMemory;
+    use tokio::io::AsyncWriteExt;
+    use url::Url;
+
+    use super::*;
+
+    /// In memory store which counts aborted uploads and parts in flight, parts fail when fail is set
+    #[derive(Debug, Default)]
+    struct TrackingStore {
+        inner: InMemory,
+        fail: bool,
+        aborted: Arc<AtomicUsize>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl fmt::Display for TrackingStore {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "TrackingStore")
+        }
+    }
+
+    #[derive(Debug)]
+    struct TrackingUpload {
+        inner: Box<dyn MultipartUpload>,
+        fail: bool,
+        aborted: Arc<AtomicUsize>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MultipartUpload for TrackingUpload {
+        fn put_part(&mut self, data: PutPayload) -> UploadPart {
+            let part = self.inner.put_part(data);
+            let (fail, in_flight, max_in_flight) = (self.fail, self.in_flight.clone(), self.max_in_flight.clone());
+            Box::pin(async move {
+                let running = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                if fail {
+                    return Err(object_store::Error::Generic { store: "TrackingStore", source: "part failed".into() });
+                }
+                part.await
+            })
+        }
+
+        async fn complete(&mut self) -> object_store::Result<PutResult> {
+            self.inner.complete().await
+        }
+
+        async fn abort(&mut self) -> object_store::Result<()> {
+            self.aborted.fetch_add(1, Ordering::SeqCst);
+            self.inner.abort().await
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStore for TrackingStore {
+        async fn put_opts(&self, location: &Path, payload: PutPayload, opts: PutOptions) -> object_store::Result<PutResult> {
+            self.inner.put_opts(location, payload, opts).await
+        }
+
+        async fn put_multipart_opts(
+            &self,
+            location: &Path,
+            opts: PutMultipartOptions,
+        ) -> object_store::Result<Box<dyn MultipartUpload>> {
+            Ok(Box::new(TrackingUpload {
+                inner: self.inner.put_multipart_opts(location, opts).await?,
+                fail: self.fail,
+                aborted: self.aborted.clone(),
+                in_flight: self.in_flight.clone(),
+                max_in_flight: self.max_in_flight.clone(),
+            }))
+        }
+
+        async fn get_opts(&self, location: &Path, options: GetOptions) -> object_store::Result<GetResult> {
+            self.inner.get_opts(location, options).await
+        }
+
+        async fn delete(&self, location: &Path) -> object_store::Result<()> {
+            self.inner.delete(location).await
+        }
+
+        fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, object_store::Result<ObjectMeta>> {
+            self.inner.list(prefix)
+        }
+
+        async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+            self.inner.list_with_delimiter(prefix).await
+        }
+
+        async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy(from, to).await
+        }
+
+        async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy_if_not_exists(from, to).await
+        }
+    }
+
+    #[tokio::test]
+    async fn multipart_store_workers_test() {
+        let tracking = Arc::new(TrackingStore::default());
+        let store = Arc::new(MultipartStore::new(tracking.clone()));
+        let path = Path::from("result.json");
+        let mut writer = BufWriter::with_capacity(store.clone(), path.clone(), 1024).with_max_concurrency(4 * CHUNKS_WORKERS);
+        writer.write_all(&[b'x'; 64 * 1024]).await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        let max = tracking.max_in_flight.load(Ordering::SeqCst);
+        assert!(max > 1 && max <= CHUNKS_WORKERS, "{max}");
+        assert_eq!(store.head(&path).await.unwrap().size, 64 * 1024);
+        assert_eq!(tracking.aborted.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn multipart_store_abort_test() {
+        let tracking = Arc::new(TrackingStore { fail: true, ..Default::default() });
+        // small writer buffer, the result is uploaded in parts
+        let config = SessionConfig::new().set_usize("datafusion.execution.objectstore_writer_buffer_size", 1024);
+        let ctx = SessionContext::new_with_config(config);
+        let url = Url::parse("tracking://bucket").unwrap();
+        ctx.register_object_store(&url, Arc::new(MultipartStore::new(tracking.clone())));
+
+        let res = ctx
+            .sql("SELECT v FROM generate_series(1, 100000) AS t(v)")
+            .await
+            .unwrap()
+            .write_json(
+                "tracking://bucket/result.json",
+                DataFrameWriteOptions::new().with_single_file_output(true),
+                None,
+            )
+            .await;
+        assert!(res.is_err());
+
+        // DataFusion drops the writer of the failed query, the upload is aborted in the background
+        for _ in 0..100 {
+            if tracking.aborted.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(tracking.aborted.load(Ordering::SeqCst), 1);
+        assert!(tracking.head(&Path::from("result.json")).await.is_err());
+    }
+}
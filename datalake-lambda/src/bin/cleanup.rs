@@ -0,0 +1,30 @@
+//! Delete results by retention policy from the command line,
+//! `cleanup --dry-run` only prints what would be deleted
+use chrono::Utc;
+use color_eyre::Result;
+
+use datalake_lambda::{
+    error::init_error_handler,
+    utils::{
+        constants::{REGION, RETENTION_POLICY},
+        retention::run_cleanup,
+        storage::Storage,
+        tracing::init_tracing,
+    },
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_error_handler()?;
+    let telemetry = init_tracing()?;
+
+    let dry_run = std::env::args().any(|arg| arg == "--dry-run");
+    let storage = Storage::new(REGION.to_string()).await;
+    let report = run_cleanup(&storage, &RETENTION_POLICY, dry_run, Utc::now()).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if let Some(telemetry) = telemetry {
+        telemetry.flush();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum EventError {
+    #[error("Unsupported event payload: {0}")]
+    UnsupportedPayload(String),
+
+    #[error("Invalid base64 body: {0}")]
+    InvalidBase64(String),
+
+    #[error("Invalid gzip body: {0}")]
+    InvalidGzip(String),
+
+    #[error("Body is not valid utf-8")]
+    InvalidUtf8,
+}
+
+/// Payload format of the incoming event, responses are shaped accordingly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadVersion {
+    /// API Gateway REST API (and HTTP API with payload 1.0)
+    V1,
+    /// API Gateway HTTP API with payload 2.0 and Lambda Function URL
+    V2,
+}
+
+/// REST API event, payload version 1.0
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RestApiEvent {
+    pub http_method: String,
+    pub path: String,
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub query_string_parameters: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+    pub request_context: RestApiRequestContext,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RestApiRequestContext {
+    pub identity: RestApiIdentity,
+    #[serde(default)]
+    pub authorizer: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RestApiIdentity {
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// HTTP API and Function URL event, payload version 2.0
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpApiEvent {
+    pub raw_path: String,
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub query_string_parameters: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+    pub request_context: HttpApiRequestContext,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HttpApiRequestContext {
+    pub http: HttpApiHttp,
+    #[serde(default)]
+    pub authorizer: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpApiHttp {
+    pub method: String,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Retention cleanup, either an EventBridge scheduled event
+/// or a direct invocation with {"action": "cleanup", "dry_run": true}
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(try_from = "Value")]
+pub struct CleanupEvent {
+    pub dry_run: bool,
+}
+
+impl TryFrom<Value> for CleanupEvent {
+    type Error = EventError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let is_scheduled = value.get("detail-type").and_then(Value::as_str) == Some("Scheduled Event");
+        let is_cleanup = value.get("action").and_then(Value::as_str) == Some("cleanup");
+        if !is_scheduled && !is_cleanup {
+            return Err(EventError::UnsupportedPayload("not a cleanup event".to_string()));
+        }
+        let dry_run = value
+            .pointer("/dry_run")
+            .or_else(|| value.pointer("/detail/dry_run"))
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        Ok(Self { dry_run })
+    }
+}
+
+/// Request normalized from any supported payload version
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "Value")]
+pub struct ApiRequest {
+    pub version: PayloadVersion,
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>, // header names are lowercase
+    pub query_params: HashMap<String, String>,
+    pub body: Option<String>,
+    pub is_base64_encoded: bool,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub principal: Option<String>, // caller identity set by the api gateway authorizer
+}
+
+impl TryFrom<Value> for ApiRequest {
+    type Error = EventError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let version = match value.get("version").and_then(Value::as_str) {
+            Some("2.0") => PayloadVersion::V2,
+            _ => PayloadVersion::V1,
+        };
+        let request = match version {
+            PayloadVersion::V1 => {
+                let event: RestApiEvent = serde_json::from_value(value)
+                    .map_err(|e| EventError::UnsupportedPayload(e.to_string()))?;
+                let user_agent = event.request_context.identity.user_agent;
+                let principal = event.request_context.authorizer.as_ref().and_then(principal);
+                Self {
+                    version,
+                    method: event.http_method,
+                    path: event.path,
+                    headers: lowercase_keys(event.headers.unwrap_or_default()),
+                    query_params: event.query_string_parameters.unwrap_or_default(),
+                    body: event.body,
+                    is_base64_encoded: event.is_base64_encoded,
+                    source_ip: event.request_context.identity.source_ip,
+                    user_agent,
+                    principal,
+                }
+            }
+            PayloadVersion::V2 => {
+                let event: HttpApiEvent = serde_json::from_value(value)
+                    .map_err(|e| EventError::UnsupportedPayload(e.to_string()))?;
+                let principal = event.request_context.authorizer.as_ref().and_then(principal);
+                let http = event.request_context.http;
+                Self {
+                    version,
+                    method: http.method,
+                    path: event.raw_path,
+                    headers: lowercase_keys(event.headers.unwrap_or_default()),
+                    query_params: event.query_string_parameters.unwrap_or_default(),
+                    body: event.body,
+                    is_base64_encoded: event.is_base64_encoded,
+                    source_ip: http.source_ip,
+                    user_agent: http.user_agent,
+                    principal,
+                }
+            }
+        };
+        Ok(request)
+    }
+}
+
+impl ApiRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query_params.get(name).map(String::as_str)
+    }
+
+    /// Body as text, base64 and gzip encodings are decoded
+    pub fn decoded_body(&self) -> Result<String, EventError> {
+        let Some(body) = &self.body else {
+            return Ok(String::new());
+        };
+        let bytes = if self.is_base64_encoded {
+            STANDARD
+                .decode(body)
+                .map_err(|e| EventError::InvalidBase64(e.to_string()))?
+        } else {
+            body.as_bytes().to_vec()
+        };
+        let bytes = match self.header("content-encoding") {
+            Some(encoding) if encoding.eq_ignore_ascii_case("gzip") => {
+                let mut decoded = vec![];
+                GzDecoder::new(bytes.as_slice())
+                    .read_to_end(&mut decoded)
+                    .map_err(|e| EventError::InvalidGzip(e.to_string()))?;
+                decoded
+            }
+            _ => bytes,
+        };
+        String::from_utf8(bytes).map_err(|_| EventError::InvalidUtf8)
+    }
+}
+
+/// Principal from lambda authorizer (principalId), jwt authorizer (sub claim) or iam auth
+fn principal(authorizer: &Value) -> Option<String> {
+    [
+        "/principalId",
+        "/claims/sub",
+        "/lambda/principalId",
+        "/jwt/claims/sub",
+        "/iam/userArn",
+    ]
+    .iter()
+    .find_map(|pointer| authorizer.pointer(pointer).and_then(Value::as_str))
+    .map(str::to_string)
+}
+
+fn lowercase_keys(map: HashMap<String, String>) -> HashMap<String, String> {
+    map.into_iter().map(|(k, v)| (k.to_lowercase(), v)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{Compression, write::GzEncoder};
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+
+    fn rest_api_event(body: Value, is_base64_encoded: bool, headers: Value) -> Value {
+        json!({
+            "httpMethod": "POST",
+            "path": "/query",
+            "headers": headers,
+            "queryStringParameters": null,
+            "body": body,
+            "isBase64Encoded": is_base64_encoded,
+            "requestContext": { "identity": { "sourceIp": "127.0.0.1", "userAgent": "curl" } }
+        })
+    }
+
+    fn http_api_event(body: Value, is_base64_encoded: bool, headers: Value) -> Value {
+        json!({
+            "version": "2.0",
+            "routeKey": "$default",
+            "rawPath": "/query",
+            "rawQueryString": "offset=10",
+            "headers": headers,
+            "queryStringParameters": { "offset": "10" },
+            "body": body,
+            "isBase64Encoded": is_base64_encoded,
+            "requestContext": { "http": { "method": "POST", "path": "/query", "sourceIp": "127.0.0.1", "userAgent": "curl" } }
+        })
+    }
+
+    fn gzip(input: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[rstest]
+    #[case(rest_api_event(json!("{}"), false, json!({"Content-Type": "application/json"})), PayloadVersion::V1)]
+    #[case(rest_api_event(json!(null), false, json!(null)), PayloadVersion::V1)]
+    #[case(http_api_event(json!("{}"), false, json!({"content-type": "application/json"})), PayloadVersion::V2)]
+    fn api_request_version_test(#[case] input: Value, #[case] expected: PayloadVersion) {
+        let request: ApiRequest = serde_json::from_value(input).unwrap();
+        assert_eq!(request.version, expected);
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/query");
+        assert_eq!(request.source_ip.as_deref(), Some("127.0.0.1"));
+    }
+
+    #[test]
+    fn api_request_headers_and_params_test() {
+        let input = http_api_event(json!("{}"), false, json!({"X-Custom": "foo"}));
+        let request: ApiRequest = serde_json::from_value(input).unwrap();
+        assert_eq!(request.header("x-custom"), Some("foo"));
+        assert_eq!(request.header("X-Custom"), Some("foo"));
+        assert_eq!(request.query_param("offset"), Some("10"));
+    }
+
+    #[rstest]
+    #[case(rest_api_event(json!("{\"query\":\"select 1\"}"), false, json!(null)), Ok("{\"query\":\"select 1\"}".to_string()))]
+    #[case(rest_api_event(json!(STANDARD.encode("{\"query\":\"select 1\"}")), true, json!(null)), Ok("{\"query\":\"select 1\"}".to_string()))]
+    #[case(http_api_event(json!(STANDARD.encode(gzip("{\"query\":\"select 1\"}"))), true, json!({"content-encoding": "gzip"})), Ok("{\"query\":\"select 1\"}".to_string()))]
+    #[case(http_api_event(json!(null), false, json!({})), Ok("".to_string()))]
+    #[case(http_api_event(json!("not base64!"), true, json!({})), Err(EventError::InvalidBase64("Invalid symbol 32, offset 3.".to_string())))]
+    #[case(http_api_event(json!(STANDARD.encode("plain")), true, json!({"content-encoding": "gzip"})), Err(EventError::InvalidGzip("unexpected end of file".to_string())))]
+    fn decoded_body_test(#[case] input: Value, #[case] expected: Result<String, EventError>) {
+        let request: ApiRequest = serde_json::from_value(input).unwrap();
+        assert_eq!(request.decoded_body(), expected);
+    }
+
+    #[rstest]
+    #[case(rest_api_event(json!(null), false, json!(null)), None)]
+    #[case({ let mut e = rest_api_event(json!(null), false, json!(null)); e["requestContext"]["authorizer"] = json!({"principalId": "etl"}); e }, Some("etl"))]
+    #[case({ let mut e = http_api_event(json!(null), false, json!({})); e["requestContext"]["authorizer"] = json!({"jwt": {"claims": {"sub": "alice"}}}); e }, Some("alice"))]
+    #[case({ let mut e = http_api_event(json!(null), false, json!({})); e["requestContext"]["authorizer"] = json!({"lambda": {"principalId": "etl"}}); e }, Some("etl"))]
+    fn principal_test(#[case] input: Value, #[case] expected: Option<&str>) {
+        let request: ApiRequest = serde_json::from_value(input).unwrap();
+        assert_eq!(request.principal.as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"action": "cleanup"}), Some(CleanupEvent { dry_run: false }))]
+    #[case(json!({"action": "cleanup", "dry_run": true}), Some(CleanupEvent { dry_run: true }))]
+    #[case(json!({"source": "aws.events", "detail-type": "Scheduled Event", "detail": {}}), Some(CleanupEvent { dry_run: false }))]
+    #[case(json!({"source": "aws.events", "detail-type": "Scheduled Event", "detail": {"dry_run": true}}), Some(CleanupEvent { dry_run: true }))]
+    #[case(rest_api_event(json!(null), false, json!(null)), None)]
+    fn cleanup_event_test(#[case] input: Value, #[case] expected: Option<CleanupEvent>) {
+        assert_eq!(serde_json::from_value::<CleanupEvent>(input).ok(), expected);
+    }
+
+    #[test]
+    fn unsupported_payload_test() {
+        let res = serde_json::from_value::<ApiRequest>(json!({"foo": "bar"}));
+        assert!(res.is_err());
+    }
+}
@@ -1,50 +1,41 @@
 pub mod error;
+pub mod event;
 pub mod routes;
 pub mod utils;
 
 use std::time::Instant;
 use std::{collections::HashMap, sync::Arc};
 
-use aws_sdk_s3::Client;
+use chrono::Utc;
 use http::Response;
 use lambda_runtime::LambdaEvent;
 use serde::{Deserialize, Serialize};
 
 use crate::error::ApiError;
-use crate::routes::query;
+use crate::event::{CleanupEvent, PayloadVersion};
+use crate::routes::profile::ProfileRequest;
+use crate::routes::{functions, profile, query, rows, status, urls};
 use crate::routes::route::ApiRoute;
+use crate::utils::callback::{Callback, redact_secret};
 use crate::utils::pathparser::ParseredTablePath;
 use crate::utils::pathvalidator::path_validator;
-use crate::utils::queryparser::{prepare_query, replace_table_name};
+use crate::utils::queryparams::QueryParam;
+use crate::utils::queryparser::{TableRef, prepare_query};
+use crate::utils::retention::{CleanupReport, RetentionPolicy, run_cleanup};
+use crate::utils::rowlimit::RowLimitPolicy;
+use crate::utils::sqlpolicy::SqlPolicy;
+use crate::utils::storage::Storage;
+use crate::utils::telemetry::record_request;
 
 pub enum ApiResponseKind {
     Ok(Option<String>),
     NotFound,
     BadRequest,
+    Gone,
+    ServiceUnavailable,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ApiRequest {
-    #[serde(rename = "httpMethod")]
-    pub method: String,
-    pub path: String,
-    pub body: String,
-    #[serde(rename = "requestContext")]
-    pub request_context: RequestContext,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct RequestContext {
-    pub identity: Identity,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct Identity {
-    #[serde(rename = "sourceIp")]
-    pub source_ip: Option<String>,
-    #[serde(rename = "userAgent")]
-    pub user_agent: Option<String>,
-}
+pub use crate::event::ApiRequest;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiResponse {
@@ -52,6 +43,10 @@ pub struct ApiResponse {
     pub status: u16,
     pub headers: HashMap<String, String>,
     pub body: Option<String>,
+    #[serde(rename = "isBase64Encoded", default)]
+    pub is_base64_encoded: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub cookies: Option<Vec<String>>, // payload 2.0 only
 }
 
 impl ApiResponse {
@@ -70,8 +65,19 @@ impl ApiResponse {
             status,
             headers,
             body,
+            is_base64_encoded: false,
+            cookies: None,
         }
     }
+
+    /// Shape response for the payload version of the request
+    pub fn with_version(mut self, version: PayloadVersion) -> Self {
+        self.cookies = match version {
+            PayloadVersion::V1 => None,
+            PayloadVersion::V2 => Some(vec![]),
+        };
+        self
+    }
 }
 
 impl TryFrom<ApiResponseKind> for ApiResponse {
@@ -81,6 +87,8 @@ impl TryFrom<ApiResponseKind> for ApiResponse {
         let response = match kind {
             ApiResponseKind::NotFound => Response::builder().status(404).body(None)?,
             ApiResponseKind::BadRequest => Response::builder().status(400).body(None)?,
+            ApiResponseKind::Gone => Response::builder().status(410).body(None)?,
+            ApiResponseKind::ServiceUnavailable => Response::builder().status(503).body(None)?,
             ApiResponseKind::Ok(body) => Response::builder().status(200).body(body)?,
         };
         Ok(ApiResponse::new(response))
@@ -90,91 +98,212 @@ impl TryFrom<ApiResponseKind> for ApiResponse {
 #[derive(Deserialize, Debug)]
 struct Query {
     pub query: String,
+    #[serde(default)]
+    pub params: Vec<QueryParam>,
+    #[serde(default)]
+    pub max_rows: Option<u64>, // ask for more rows than default, up to the caller's cap
+    #[serde(default)]
+    pub callback_url: Option<String>, // notified when the query finishes
+    #[serde(default)]
+    pub callback_secret: Option<String>, // signs the callback payload, never logged
 }
 
 pub struct AppState {
-    pub client: Client,
+    pub storage: Storage,
+    pub policy: SqlPolicy,
+    pub row_limits: RowLimitPolicy,
+    pub retention: RetentionPolicy,
+}
+
+/// Event the function is invoked with, api gateway request or scheduled cleanup
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum LambdaRequest {
+    Cleanup(CleanupEvent),
+    Api(Box<ApiRequest>),
 }
 
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum LambdaResponse {
+    Api(ApiResponse),
+    Cleanup(CleanupReport),
+}
+
+#[tracing::instrument(level = "info", name = "handler", skip_all)]
 pub async fn handler(
-    event: LambdaEvent<ApiRequest>,
+    event: LambdaEvent<LambdaRequest>,
     state: Arc<AppState>,
-) -> Result<ApiResponse, ApiError> {
+) -> Result<LambdaResponse, ApiError> {
     let start = Instant::now();
     let (request, context) = event.into_parts();
-    let method = request.method;
-    let path = request.path;
-    let body = request.body;
-    let request_id = context.request_id;
-    let user_ip = request.request_context.identity.source_ip;
-    let user_agent = request.request_context.identity.user_agent;
-    tracing::info!({ user_ip, user_agent, path, method, query = %body }, "starting handler");
+    let response = match request {
+        LambdaRequest::Api(request) => {
+            let version = request.version;
+            let route = ApiRoute::try_from((request.method.as_str(), request.path.as_str()))
+                .map_or("unknown", |route| route.key());
+            let response = Box::pin(handle_request(*request, context.request_id, state)).await;
+            let status = response.as_ref().map_or(500, |response| response.status);
+            record_request(route, status, start.elapsed());
+            LambdaResponse::Api(response?.with_version(version))
+        }
+        LambdaRequest::Cleanup(event) => {
+            tracing::info!({ dry_run = event.dry_run }, "starting cleanup");
+            let report = run_cleanup(&state.storage, &state.retention, event.dry_run, Utc::now())
+                .await
+                .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+            LambdaResponse::Cleanup(report)
+        }
+    };
+    let exec_time = start.elapsed().as_secs();
+    tracing::info!({ duration = %exec_time }, "finishing handler");
+    Ok(response)
+}
+
+async fn handle_request(
+    request: ApiRequest,
+    request_id: String,
+    state: Arc<AppState>,
+) -> Result<ApiResponse, ApiError> {
+    let method = &request.method;
+    let path = &request.path;
+    let user_ip = &request.source_ip;
+    let user_agent = &request.user_agent;
+    let body = match request.decoded_body() {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("{e}, path: {path}");
+            return ApiResponseKind::BadRequest.try_into();
+        }
+    };
+    let logged_body = redact_secret(&body);
+    tracing::info!({ user_ip, user_agent, path, method, version = ?request.version, query = %logged_body }, "starting handler");
 
     let route: ApiRoute = match (method.as_str(), path.as_str()).try_into() {
         Ok(route) => route,
         Err(e) => {
-            tracing::error!("{e}, query: {body}");
+            tracing::error!("{e}, query: {logged_body}");
             return ApiResponseKind::BadRequest.try_into();
         }
     };
 
-    let (query, table_path) = match serde_json::from_str::<Query>(&body) {
+    // route futures are boxed, inlined they make the handler future too deep to lay out
+    let response = match route {
+        ApiRoute::QueryPost => Box::pin(handle_query_post(&request, &body, &request_id, &state)).await?,
+        ApiRoute::QueryGet { id } => status::get_status(&state.storage, &id).await?,
+        ApiRoute::QueryRowsGet { id } => {
+            rows::get_rows(&state.storage, &id, &request.query_params).await?
+        }
+        ApiRoute::QueryUrlsPost { id } => urls::post_urls(&state.storage, &id, &body).await?,
+        ApiRoute::FunctionsGet => functions::get_functions()?,
+        ApiRoute::ProfilePost => Box::pin(handle_profile_post(&request, &body, &request_id, &state)).await?,
+    };
+
+    Ok(response)
+}
+
+async fn handle_query_post(
+    request: &ApiRequest,
+    body: &str,
+    request_id: &str,
+    state: &AppState,
+) -> Result<ApiResponse, ApiError> {
+    let logged_body = redact_secret(body);
+    let (query, tables, params, limit, callback) = match serde_json::from_str::<Query>(body) {
         Ok(query) => {
-            match prepare_query(&query.query) {
-                Ok(query) => (query.query, query.table_name),
+            let callback = match Callback::new(query.callback_url, query.callback_secret).await {
+                Ok(callback) => callback,
                 Err(e) => {
-                    tracing::error!("{e}, query: {body}");
+                    tracing::error!("{e}, query: {logged_body}");
+                    return ApiResponseKind::BadRequest.try_into();
+                }
+            };
+            let principal = request.principal.as_deref();
+            let row_limit = state.row_limits.resolve(query.max_rows, principal, ApiRoute::QueryPost.key());
+            match prepare_query(&query.query, &query.params, &state.policy, row_limit) {
+                Ok(parsered) => (parsered.query, parsered.tables, query.params, parsered.limit, callback),
+                Err(e) => {
+                    tracing::error!("{e}, query: {logged_body}");
                     return ApiResponseKind::BadRequest.try_into();
                 }
             }
         }
         Err(e) => {
-            tracing::error!("{e}, query: {body}");
+            tracing::error!("{e}, query: {logged_body}");
             return ApiResponseKind::BadRequest.try_into();
         }
     };
 
-    let table_path = match ParseredTablePath::new(&table_path) {
-        Ok(v) => v,
-        Err(e) => {
-            tracing::error!("{e}, query: {body}");
+    for table in &tables {
+        if table_path(&table.path, state, &logged_body).await.is_none() {
             return ApiResponseKind::BadRequest.try_into();
         }
-    };
+    }
 
-    let is_valid = match path_validator(&table_path, &state.client).await {
-        Ok(v) => v,
+    tracing::info!({ query, tables = ?tables, limit = ?limit }, "processing query");
+
+    let principal = request.principal.clone();
+    query::post_query(&state.storage, request_id, principal, &query, &params, &tables, limit, callback).await
+}
+
+async fn handle_profile_post(
+    request: &ApiRequest,
+    body: &str,
+    request_id: &str,
+    state: &AppState,
+) -> Result<ApiResponse, ApiError> {
+    let (path, profile) = match serde_json::from_str::<ProfileRequest>(body) {
+        Ok(request) => match request.profile_spec() {
+            Ok(profile) => (request.path, profile),
+            Err(e) => {
+                tracing::error!("{e}, body: {body}");
+                return ApiResponseKind::BadRequest.try_into();
+            }
+        },
         Err(e) => {
-            tracing::error!("{e}, query: {body}");
+            tracing::error!("{e}, body: {body}");
             return ApiResponseKind::BadRequest.try_into();
         }
     };
-
-    if !is_valid {
-        tracing::error!("invalid path: {}, query: {body}", table_path.as_ref());
+    let Some(table_path) = table_path(&path, state, body).await else {
         return ApiResponseKind::BadRequest.try_into();
-    }
-
-    let table_name = match &table_path.extract_table_name() {
-        Ok(name) => name.to_string(),
+    };
+    let name = match table_path.extract_table_name() {
+        Ok(name) => name,
         Err(e) => {
-            tracing::error!("{e}, query: {body}");
+            tracing::error!("{e}, body: {body}");
             return ApiResponseKind::BadRequest.try_into();
         }
     };
+    let table = TableRef { name, path: table_path.as_ref().to_string(), sample: None };
 
-    // replace s3 path in query with table name
-    let query = replace_table_name(&query, &table_name);
+    tracing::info!({ table = ?table, profile = ?profile }, "processing profile");
 
-    tracing::info!({ query, table_name, table_path = %table_path.as_ref() }, "processing query");
+    let principal = request.principal.clone();
+    profile::post_profile(&state.storage, request_id, principal, table, profile).await
+}
 
-    let response = match route {
-        ApiRoute::QueryPost => {
-            query::post_query(&state.client, &request_id, &query, table_path.as_ref(), &table_name).await?
+/// Parsed table path when it is valid and exists in storage, errors are logged
+async fn table_path(path: &str, state: &AppState, logged_body: &str) -> Option<ParseredTablePath> {
+    let table_path = match ParseredTablePath::new(path) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("{e}, body: {logged_body}");
+            return None;
         }
     };
 
-    let exec_time = start.elapsed().as_secs();
-    tracing::info!({ duration = %exec_time }, "finishing handler");
-    Ok(response)
+    let is_valid = match path_validator(&table_path, &state.storage).await {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("{e}, body: {logged_body}");
+            return None;
+        }
+    };
+
+    if !is_valid {
+        tracing::error!("invalid path: {}, body: {logged_body}", table_path.as_ref());
+        return None;
+    }
+    Some(table_path)
 }
@@ -6,22 +6,37 @@ use datalake_lambda::{
     AppState,
     error::init_error_handler,
     handler,
-    utils::{aws::get_aws_client, constants::REGION, tracing::init_tracing},
+    utils::{
+        constants::{REGION, RETENTION_POLICY, ROW_LIMIT_POLICY},
+        sqlpolicy::SqlPolicy,
+        storage::Storage,
+        tracing::init_tracing,
+    },
 };
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     init_error_handler()?;
-    init_tracing();
+    let telemetry = init_tracing()?;
 
-    let client = get_aws_client(REGION.to_string()).await;
-    let app_state = Arc::new(AppState { client });
+    let storage = Storage::new(REGION.to_string()).await;
+    let app_state = Arc::new(AppState {
+        storage,
+        policy: SqlPolicy::from_env(),
+        row_limits: ROW_LIMIT_POLICY.clone(),
+        retention: RETENTION_POLICY.clone(),
+    });
 
     run(service_fn(|event| async {
-        handler(event, app_state.clone()).await.map_err(|err| {
+        let response = handler(event, app_state.clone()).await.map_err(|err| {
             tracing::error!(?err, "lambda handler failed");
             err
-        })
+        });
+        // the lambda is frozen after the response, nothing is exported until the next event
+        if let Some(telemetry) = &telemetry {
+            telemetry.flush();
+        }
+        response
     }))
     .await?;
 
@@ -0,0 +1,118 @@
+use serde::Serialize;
+
+use crate::{ApiResponse, ApiResponseKind, error::ApiError};
+
+/// Domain udf or sketch aggregate registered in every fusion session,
+/// same list as datalake-fusion utils/udfs.rs
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FunctionDoc {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+    pub example: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FunctionsResponse {
+    pub functions: &'static [FunctionDoc],
+}
+
+pub const FUNCTIONS: [FunctionDoc; 12] = [
+    FunctionDoc {
+        name: "file_extension",
+        signature: "file_extension(file_name VARCHAR) -> VARCHAR",
+        description: "Lowercase extension of the last path segment, NULL when there is none",
+        example: "file_extension('images/IMG_01.JPG') = 'jpg'",
+    },
+    FunctionDoc {
+        name: "extract_or_id",
+        signature: "extract_or_id(key VARCHAR) -> VARCHAR",
+        description: "Value of the or_id=... segment of an object key, NULL when the key has none",
+        example: "extract_or_id('images/or_id=42/dt=2021-01-01/a.jpg') = '42'",
+    },
+    FunctionDoc {
+        name: "human_size",
+        signature: "human_size(file_size BIGINT) -> VARCHAR",
+        description: "Size in bytes formatted with binary units and one decimal",
+        example: "human_size(1536) = '1.5 KiB'",
+    },
+    FunctionDoc {
+        name: "size_bucket",
+        signature: "size_bucket(file_size BIGINT) -> VARCHAR",
+        description: "Binary unit of the size magnitude (B, KiB, MiB, GiB, TiB), for GROUP BY",
+        example: "size_bucket(3145728) = 'MiB'",
+    },
+    FunctionDoc {
+        name: "normalize_dt",
+        signature: "normalize_dt(dt VARCHAR) -> DATE",
+        description: "Date of YYYY-MM-DD, YYYYMMDD, YYYY/MM/DD, dt=... or RFC 3339 strings, NULL otherwise",
+        example: "normalize_dt('dt=20210101') = DATE '2021-01-01'",
+    },
+    FunctionDoc {
+        name: "hll_estimate",
+        signature: "hll_estimate(sketch BYTEA) -> BIGINT",
+        description: "Distinct count estimated from a hll_sketch or hll_merge sketch",
+        example: "hll_estimate(hll_sketch(or_id))",
+    },
+    FunctionDoc {
+        name: "tdigest_quantile",
+        signature: "tdigest_quantile(sketch BYTEA, q DOUBLE) -> DOUBLE",
+        description: "Quantile q between 0 and 1 estimated from a tdigest_sketch or tdigest_merge sketch",
+        example: "tdigest_quantile(tdigest_sketch(file_size), 0.95)",
+    },
+    FunctionDoc {
+        name: "hll_sketch",
+        signature: "hll_sketch(value ANY) -> BYTEA",
+        description: "Aggregate, HyperLogLog sketch of distinct values (about 1.6% error), mergeable with hll_merge",
+        example: "SELECT dt, hll_sketch(or_id) AS or_ids FROM t GROUP BY dt",
+    },
+    FunctionDoc {
+        name: "hll_merge",
+        signature: "hll_merge(sketch BYTEA) -> BYTEA",
+        description: "Aggregate, union of hll sketches, e.g. of materialized daily sketches",
+        example: "hll_estimate(hll_merge(or_ids))",
+    },
+    FunctionDoc {
+        name: "tdigest_sketch",
+        signature: "tdigest_sketch(value DOUBLE) -> BYTEA",
+        description: "Aggregate, t-digest sketch of numeric values for approximate percentiles, mergeable with tdigest_merge",
+        example: "SELECT dt, tdigest_sketch(file_size) AS sizes FROM t GROUP BY dt",
+    },
+    FunctionDoc {
+        name: "tdigest_merge",
+        signature: "tdigest_merge(sketch BYTEA) -> BYTEA",
+        description: "Aggregate, union of t-digest sketches",
+        example: "tdigest_quantile(tdigest_merge(sizes), 0.5)",
+    },
+    FunctionDoc {
+        name: "approx_top_k",
+        signature: "approx_top_k(value ANY, k BIGINT) -> ARRAY<STRUCT<value VARCHAR, count BIGINT UNSIGNED>>",
+        description: "Aggregate, k most frequent values with their counts, counts are lower bounds (k up to 1000)",
+        example: "approx_top_k(file_extension(file_name), 10)",
+    },
+];
+
+/// Functions the ui offers for autocompletion, static so it doesn't touch storage
+pub fn get_functions() -> Result<ApiResponse, ApiError> {
+    let body = serde_json::to_string(&FunctionsResponse { functions: &FUNCTIONS })?;
+    ApiResponseKind::Ok(Some(body)).try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions_response_serialize_test() {
+        let body = serde_json::to_value(FunctionsResponse { functions: &FUNCTIONS[..1] }).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"functions": [{
+                "name": "file_extension",
+                "signature": "file_extension(file_name VARCHAR) -> VARCHAR",
+                "description": "Lowercase extension of the last path segment, NULL when there is none",
+                "example": "file_extension('images/IMG_01.JPG') = 'jpg'"
+            }]})
+        );
+    }
+}
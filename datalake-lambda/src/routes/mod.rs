@@ -1,2 +1,7 @@
+pub mod functions;
+pub mod profile;
 pub mod query;
 pub mod route;
+pub mod rows;
+pub mod status;
+pub mod urls;
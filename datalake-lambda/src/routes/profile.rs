@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ApiResponse, ApiResponseKind,
+    error::ApiError,
+    routes::query::launch_job,
+    utils::{
+        constants::*,
+        jobrecord::JobRecord,
+        jobspec::{JobSpec, ProfileSpec},
+        queryparser::TableRef,
+        results::{DEFAULT_FILENAME, ResultFormat, presign_result},
+        storage::Storage,
+    },
+};
+
+/// Dataset to profile, {"path": "s3://bucket/table/", "columns": ["a"], "top_k": 10, "bins": 20}
+#[derive(Deserialize, Debug)]
+pub struct ProfileRequest {
+    pub path: String,
+    #[serde(default)]
+    pub columns: Option<Vec<String>>, // all columns when not given
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    #[serde(default)]
+    pub bins: Option<usize>, // histogram bins of numeric and date columns
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ProfileResponse {
+    pub result_profile: String, // profile url, null counts, min/max, distinct estimates, top values and histograms per column
+    pub result_manifest: String, // manifest url, written after the profile
+}
+
+impl ProfileRequest {
+    pub fn profile_spec(&self) -> Result<ProfileSpec, String> {
+        let top_k = self.top_k.unwrap_or(PROFILE_TOP_K);
+        if top_k == 0 || top_k > PROFILE_MAX_TOP_K {
+            return Err(format!("top_k must be between 1 and {PROFILE_MAX_TOP_K}"));
+        }
+        let bins = self.bins.unwrap_or(PROFILE_BINS);
+        if bins == 0 || bins > PROFILE_MAX_BINS {
+            return Err(format!("bins must be between 1 and {PROFILE_MAX_BINS}"));
+        }
+        if self.columns.as_ref().is_some_and(|c| c.is_empty() || c.iter().any(|c| c.trim().is_empty())) {
+            return Err("columns must be non-empty names".to_string());
+        }
+        Ok(ProfileSpec {
+            columns: self.columns.clone(),
+            top_k,
+            bins,
+        })
+    }
+}
+
+#[tracing::instrument(level = "info", name = "profile", skip(storage))]
+pub async fn post_profile(
+    storage: &Storage,
+    request_id: &str,
+    principal: Option<String>,
+    table: TableRef,
+    profile: ProfileSpec,
+) -> Result<ApiResponse, ApiError> {
+    let expires_in = Duration::from_secs(PRESIGNED_TIMEOUT);
+    let presign = |format| presign_result(storage, request_id, format, expires_in, DEFAULT_FILENAME);
+    let result_profile = presign(ResultFormat::Profile)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    let result_manifest = presign(ResultFormat::Manifest)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    let resp = ProfileResponse { result_profile, result_manifest };
+    let body = serde_json::to_string(&resp)?;
+
+    let mut record = JobRecord::new(request_id, principal, None);
+    let spec = JobSpec::profile(request_id, table, profile);
+    if !launch_job(storage, &mut record, &spec).await? {
+        return ApiResponseKind::ServiceUnavailable.try_into();
+    }
+
+    let response = ApiResponseKind::Ok(Some(body)).try_into()?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(r#"{"path": "s3://bucket/t/"}"#, Ok(ProfileSpec { columns: None, top_k: PROFILE_TOP_K, bins: PROFILE_BINS }))]
+    #[case(r#"{"path": "s3://bucket/t/", "columns": ["a"], "top_k": 5, "bins": 8}"#, Ok(ProfileSpec { columns: Some(vec!["a".to_string()]), top_k: 5, bins: 8 }))]
+    #[case(r#"{"path": "s3://bucket/t/", "top_k": 0}"#, Err(format!("top_k must be between 1 and {PROFILE_MAX_TOP_K}")))]
+    #[case(r#"{"path": "s3://bucket/t/", "bins": 1000}"#, Err(format!("bins must be between 1 and {PROFILE_MAX_BINS}")))]
+    #[case(r#"{"path": "s3://bucket/t/", "columns": []}"#, Err("columns must be non-empty names".to_string()))]
+    fn profile_spec_test(#[case] body: &str, #[case] expected: Result<ProfileSpec, String>) {
+        let request: ProfileRequest = serde_json::from_str(body).unwrap();
+        assert_eq!(request.profile_spec(), expected);
+    }
+}
@@ -1,14 +1,22 @@
 use std::time::Duration;
 
-use aws_sdk_s3::{Client, presigning::PresigningConfig};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     ApiResponse, ApiResponseKind,
     error::ApiError,
     utils::{
-        aws::{get_ecs_client, run_ecs_task},
+        aws::{EcsTarget, ecs_task_env, get_ecs_client},
+        callback::Callback,
         constants::*,
+        queryparams::QueryParam,
+        jobrecord::{ErrorKind, JobRecord, JobStatus},
+        jobspec::JobSpec,
+        launcher::launch_task,
+        queryparser::{AppliedLimit, TableRef},
+        results::{DEFAULT_FILENAME, ResultFormat, presign_result},
+        storage::Storage,
+        telemetry::record_failure,
     },
 };
 
@@ -16,83 +24,96 @@ use crate::{
 pub struct QueryResponse {
     pub result_parquet: String, // parqet url 
     pub result_json: String, // json url (for visualization for web ui) 
+    pub result_manifest: String, // manifest url, states row count and whether the result was truncated
+    pub row_limit: u64,
+    pub limit_clamped: bool, // query limit was lowered to row_limit
 }
 
-#[tracing::instrument(level = "info", name = "query", skip(client))]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(level = "info", name = "query", skip(storage, callback))]
 pub async fn post_query(
-    client: &Client,
+    storage: &Storage,
     request_id: &str,
+    principal: Option<String>,
     query: &str,
-    table_path: &str,
-    table_name: &str,
+    params: &[QueryParam],
+    tables: &[TableRef],
+    limit: AppliedLimit,
+    callback: Option<Callback>,
 ) -> Result<ApiResponse, ApiError> {
-    // prepare parquet file
-    let key_parquet = format!("{DATA_PREFIX}{request_id}.parquet"); 
-    tracing::info!("creating presigned object for key: {}", key_parquet);
-    let get_object_request1 = client
-        .get_object()
-        .bucket(DATA_BUCKET)
-        .key(&key_parquet)
-        .response_content_type("application/parquet") // for browser
-        .response_content_disposition("attachment; filename=\"download.parquet\""); // for browser
-
-    let presigning_config1 = PresigningConfig::builder()
-        .expires_in(Duration::from_secs(PRESIGNED_TIMEOUT))
-        .build()
-        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
-
-    let presigned_url1 = get_object_request1
-        .presigned(presigning_config1.clone())
+    let expires_in = Duration::from_secs(PRESIGNED_TIMEOUT);
+    let presign = |format| presign_result(storage, request_id, format, expires_in, DEFAULT_FILENAME);
+    let presigned_url1 = presign(ResultFormat::Parquet)
         .await
         .map_err(|e| ApiError::UnexpectedError(e.into()))?;
-
-    // prepare json file
-    let key_json = format!("{DATA_PREFIX}{request_id}.json"); 
-    tracing::info!("creating presigned object for key: {}", key_json);
-    let get_object_request2 = client
-        .get_object()
-        .bucket(DATA_BUCKET)
-        .key(&key_json)
-        .response_content_type("application/json") // for browser
-        .response_content_disposition("attachment; filename=\"download.json\""); // for browser
-
-    let presigning_config2 = PresigningConfig::builder()
-        .expires_in(Duration::from_secs(PRESIGNED_TIMEOUT))
-        .build()
+    let presigned_url2 = presign(ResultFormat::Json)
+        .await
         .map_err(|e| ApiError::UnexpectedError(e.into()))?;
-
-    let presigned_url2 = get_object_request2
-        .presigned(presigning_config2.clone())
+    // manifest is written by fusion after results
+    let presigned_url3 = presign(ResultFormat::Manifest)
         .await
         .map_err(|e| ApiError::UnexpectedError(e.into()))?;
 
     let resp = QueryResponse {
-        result_parquet: presigned_url1.uri().to_string(),
-        result_json: presigned_url2.uri().to_string(),
+        result_parquet: presigned_url1,
+        result_json: presigned_url2,
+        result_manifest: presigned_url3,
+        row_limit: limit.row_limit,
+        limit_clamped: limit.clamped,
     };
     let body = serde_json::to_string(&resp)?;
+    // callback payload carries the same urls as the response
+    let result_urls = serde_json::json!({
+        "parquet": resp.result_parquet,
+        "json": resp.result_json,
+        "manifest": resp.result_manifest,
+    });
 
-    // pass request_id & query to ecs task and start the task
-    let ecs_client = get_ecs_client(REGION.to_string()).await;
-    let subnets = SUBNETS.iter().map(|x| x.to_string()).collect();
-    let security_groups = SECURITY_GROUPS.iter().map(|x| x.to_string()).collect();
-    let _output = run_ecs_task(
-        &ecs_client,
-        CLUSTER,
-        TASK_NAME,
-        CONTAINER_NAME,
-        Some(subnets),
-        Some(security_groups),
+    let mut record = JobRecord::new(request_id, principal, Some(limit.row_limit));
+    record.callback_url = callback.as_ref().map(|c| c.url.clone());
+    let spec = JobSpec::new(
         request_id,
         query,
-        table_path,
-        table_name,
-    )
-    .await
-    .map_err(|e| ApiError::UnexpectedError(e.into()))?; //#TODO process output of ecs
-    tracing::info!("starting ecs task");
+        params,
+        tables,
+        limit.row_limit,
+        callback.map(|c| (c, result_urls)),
+    );
+    if !launch_job(storage, &mut record, &spec).await? {
+        return ApiResponseKind::ServiceUnavailable.try_into();
+    }
 
     let response = ApiResponseKind::Ok(Some(body)).try_into()?;
 
     Ok(response)
 }
+
+/// Save the record and spec, then start the fusion task. The job is failed
+/// and false returned when the task can't be launched
+pub async fn launch_job(storage: &Storage, record: &mut JobRecord, spec: &JobSpec) -> Result<bool, ApiError> {
+    record
+        .save(storage)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+
+    // the task only gets the spec uri, query & params may not fit into ecs overrides
+    let spec_location = spec
+        .save(storage)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    let env_vars = ecs_task_env(&spec_location.to_string());
+    let ecs_client = get_ecs_client(REGION.to_string()).await;
+    let target = EcsTarget::from_constants();
+    if let Err(e) = launch_task(&ecs_client, &target, env_vars, *ECS_USE_SPOT, &record.request_id).await {
+        // urls would never resolve, the job is failed instead
+        tracing::error!("{e}");
+        record.finish(JobStatus::Failed, Some((ErrorKind::Launch, e.to_string())));
+        record_failure(ErrorKind::Launch);
+        record
+            .save(storage)
+            .await
+            .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+        return Ok(false);
+    }
+    Ok(true)
+}
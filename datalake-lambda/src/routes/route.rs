@@ -1,6 +1,25 @@
 #[derive(Debug, PartialEq)]
 pub enum ApiRoute {
     QueryPost,
+    QueryGet { id: String },
+    QueryRowsGet { id: String },
+    QueryUrlsPost { id: String },
+    FunctionsGet,
+    ProfilePost,
+}
+
+impl ApiRoute {
+    /// Route key as in row limit policy, e.g. "POST /query"
+    pub fn key(&self) -> &'static str {
+        match self {
+            ApiRoute::QueryPost => "POST /query",
+            ApiRoute::QueryGet { .. } => "GET /query/{id}",
+            ApiRoute::QueryRowsGet { .. } => "GET /query/{id}/rows",
+            ApiRoute::QueryUrlsPost { .. } => "POST /query/{id}/urls",
+            ApiRoute::FunctionsGet => "GET /functions",
+            ApiRoute::ProfilePost => "POST /profile",
+        }
+    }
 }
 
 impl TryFrom<(&str, &str)> for ApiRoute {
@@ -9,6 +28,15 @@ impl TryFrom<(&str, &str)> for ApiRoute {
     fn try_from((method, path): (&str, &str)) -> Result<Self, Self::Error> {
         match (method, path) {
             ("POST", "/query") => Ok(ApiRoute::QueryPost),
+            ("GET", "/functions") => Ok(ApiRoute::FunctionsGet),
+            ("POST", "/profile") => Ok(ApiRoute::ProfilePost),
+            ("POST", path) if let Some(id) = query_id(path, "/urls") => {
+                Ok(ApiRoute::QueryUrlsPost { id })
+            }
+            ("GET", path) if let Some(id) = query_id(path, "") => Ok(ApiRoute::QueryGet { id }),
+            ("GET", path) if let Some(id) = query_id(path, "/rows") => {
+                Ok(ApiRoute::QueryRowsGet { id })
+            }
             _ => Err(format!(
                 "unsupported resource method: {method}, path: {path}"
             )),
@@ -16,6 +44,14 @@ impl TryFrom<(&str, &str)> for ApiRoute {
     }
 }
 
+/// Id from /query/{id}{suffix}, ids are request ids and can't contain path separators
+fn query_id(path: &str, suffix: &str) -> Option<String> {
+    let id = path.strip_prefix("/query/")?.strip_suffix(suffix)?;
+    let is_valid = !id.is_empty()
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_valid.then(|| id.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -25,8 +61,19 @@ mod tests {
     #[rstest]
     #[test]
     #[case(("POST", "/query"), Ok(ApiRoute::QueryPost))]
+    #[case(("GET", "/query/8a1f-42_b/rows"), Ok(ApiRoute::QueryRowsGet { id: "8a1f-42_b".to_string() }))]
+    #[case(("GET", "/query/8a1f-42_b"), Ok(ApiRoute::QueryGet { id: "8a1f-42_b".to_string() }))]
+    #[case(("POST", "/query/8a1f-42_b/urls"), Ok(ApiRoute::QueryUrlsPost { id: "8a1f-42_b".to_string() }))]
+    #[case(("GET", "/functions"), Ok(ApiRoute::FunctionsGet))]
+    #[case(("POST", "/functions"), Err("unsupported resource method: POST, path: /functions".to_string()))]
+    #[case(("POST", "/profile"), Ok(ApiRoute::ProfilePost))]
+    #[case(("GET", "/profile"), Err("unsupported resource method: GET, path: /profile".to_string()))]
+    #[case(("GET", "/query//rows"), Err("unsupported resource method: GET, path: /query//rows".to_string()))]
+    #[case(("GET", "/query/../x/rows"), Err("unsupported resource method: GET, path: /query/../x/rows".to_string()))]
+    #[case(("POST", "/query/foo/rows"), Err("unsupported resource method: POST, path: /query/foo/rows".to_string()))]
     #[case(("foo", "/foo"), Err(format!("unsupported resource method: foo, path: /foo")))]
     #[case(("", "/"), Err(format!("unsupported resource method: , path: /")))]
+    #[allow(clippy::useless_format)]
     fn test_api_route(#[case] input: (&str, &str), #[case] expected: Result<ApiRoute, String>) {
         let res = input.try_into();
         assert_eq!(res, expected);
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::{
+    ApiResponse, ApiResponseKind,
+    error::ApiError,
+    utils::{
+        constants::*,
+        jobrecord::{JobRecord, JobStatus},
+        resultreader::{ResultReaderError, RowsRequest, SortSpec, read_rows},
+        storage::{Storage, StorageLocation},
+    },
+};
+
+/// Build page request from ?offset=0&limit=100&sort=-name&columns=id,name
+pub fn rows_request(query_params: &HashMap<String, String>) -> Result<RowsRequest, String> {
+    let number = |name: &str, default: u64| match query_params.get(name) {
+        Some(v) => v.parse::<u64>().map_err(|_| format!("invalid {name}: {v}")),
+        None => Ok(default),
+    };
+    let offset = number("offset", 0)?;
+    let limit = number("limit", ROWS_PAGE_SIZE)?;
+    if limit == 0 || limit > ROWS_PAGE_MAX {
+        return Err(format!("limit must be between 1 and {ROWS_PAGE_MAX}"));
+    }
+    let sort = query_params
+        .get("sort")
+        .filter(|v| !v.is_empty())
+        .map(|v| SortSpec::parse(v));
+    let columns = query_params.get("columns").filter(|v| !v.is_empty()).map(|v| {
+        v.split(',')
+            .map(|c| c.trim().to_string())
+            .collect::<Vec<_>>()
+    });
+    Ok(RowsRequest { offset, limit, sort, columns })
+}
+
+#[tracing::instrument(level = "info", name = "rows", skip(storage))]
+pub async fn get_rows(
+    storage: &Storage,
+    id: &str,
+    query_params: &HashMap<String, String>,
+) -> Result<ApiResponse, ApiError> {
+    let request = match rows_request(query_params) {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!("{e}");
+            return ApiResponseKind::BadRequest.try_into();
+        }
+    };
+
+    // cleanup deletes the files of expired results, the record tells them apart from unknown ids
+    let record = JobRecord::load(storage, id)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    if let Some(record) = record.filter(|r| r.status == JobStatus::Expired) {
+        tracing::info!("results expired: {:?}", record.expired_reason);
+        return ApiResponseKind::Gone.try_into();
+    }
+
+    let location = StorageLocation::parse(&RESULTS_URL)
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?
+        .join(&format!("{id}.parquet"));
+    let exists = storage
+        .exists(&location)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    if !exists {
+        tracing::info!("result not found: {location}");
+        return ApiResponseKind::NotFound.try_into();
+    }
+
+    let reader = storage
+        .open_parquet(&location)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    let page = match read_rows(reader, &request, ROWS_SORT_MAX_ROWS).await {
+        Ok(page) => page,
+        Err(ResultReaderError::UnknownColumn(column)) => {
+            tracing::error!("unknown column: {column}");
+            return ApiResponseKind::BadRequest.try_into();
+        }
+        Err(e @ ResultReaderError::SortTooLarge { .. }) => {
+            tracing::error!("{e}");
+            return ApiResponseKind::BadRequest.try_into();
+        }
+        Err(e) => return Err(ApiError::UnexpectedError(e.into())),
+    };
+    let body = serde_json::to_string(&page)?;
+
+    let response = ApiResponseKind::Ok(Some(body)).try_into()?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn params(input: &[(&str, &str)]) -> HashMap<String, String> {
+        input.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[rstest]
+    #[case(&[], Ok(RowsRequest { offset: 0, limit: ROWS_PAGE_SIZE, sort: None, columns: None }))]
+    #[case(&[("offset", "200"), ("limit", "50"), ("sort", "-name"), ("columns", "id, name")], Ok(RowsRequest { offset: 200, limit: 50, sort: Some(SortSpec { column: "name".to_string(), descending: true }), columns: Some(vec!["id".to_string(), "name".to_string()]) }))]
+    #[case(&[("limit", "0")], Err(format!("limit must be between 1 and {ROWS_PAGE_MAX}")))]
+    #[case(&[("limit", "100000")], Err(format!("limit must be between 1 and {ROWS_PAGE_MAX}")))]
+    #[case(&[("offset", "-1")], Err("invalid offset: -1".to_string()))]
+    fn rows_request_test(#[case] input: &[(&str, &str)], #[case] expected: Result<RowsRequest, String>) {
+        assert_eq!(rows_request(&params(input)), expected);
+    }
+}
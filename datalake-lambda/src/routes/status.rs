@@ -0,0 +1,88 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    ApiResponse, ApiResponseKind,
+    error::ApiError,
+    utils::{
+        constants::*,
+        jobrecord::{CallbackAttempt, ErrorKind, JobRecord, JobStatus},
+        storage::{Storage, StorageLocation},
+    },
+};
+
+#[derive(Serialize, Debug)]
+pub struct StatusResponse {
+    pub request_id: String,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub row_limit: Option<u64>,
+    pub manifest: Option<Value>, // written by fusion when results are complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Value>, // operator metrics of the query, written by fusion before the manifest
+    pub error: Option<String>,
+    pub error_kind: Option<ErrorKind>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub callback_attempts: Vec<CallbackAttempt>,
+}
+
+#[tracing::instrument(level = "info", name = "status", skip(storage))]
+pub async fn get_status(storage: &Storage, id: &str) -> Result<ApiResponse, ApiError> {
+    let record = JobRecord::load(storage, id)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    let Some(record) = record else {
+        return ApiResponseKind::NotFound.try_into();
+    };
+    if record.status == JobStatus::Expired {
+        tracing::info!("results expired: {:?}", record.expired_reason);
+        return ApiResponseKind::Gone.try_into();
+    }
+
+    let manifest = read_result_json(storage, &format!("{id}.manifest.json")).await?;
+    let metrics = read_result_json(storage, &format!("{id}.metrics.json")).await?;
+    // manifest is the last file fusion writes
+    let status = match (record.status, &manifest) {
+        (JobStatus::Submitted, Some(_)) => JobStatus::Succeeded,
+        (status, _) => status,
+    };
+
+    let resp = StatusResponse {
+        request_id: record.request_id,
+        status,
+        created_at: record.created_at,
+        updated_at: record.updated_at,
+        row_limit: record.row_limit,
+        manifest,
+        metrics,
+        error: record.error,
+        error_kind: record.error_kind,
+        callback_attempts: record.callback_attempts,
+    };
+    let body = serde_json::to_string(&resp)?;
+
+    let response = ApiResponseKind::Ok(Some(body)).try_into()?;
+
+    Ok(response)
+}
+
+/// Json file fusion wrote next to the results, None until it exists
+async fn read_result_json(storage: &Storage, name: &str) -> Result<Option<Value>, ApiError> {
+    let location = StorageLocation::parse(&RESULTS_URL)
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?
+        .join(name);
+    let exists = storage
+        .exists(&location)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    if !exists {
+        return Ok(None);
+    }
+    let data = storage
+        .get(&location)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    Ok(Some(serde_json::from_slice(&data)?))
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ApiResponse, ApiResponseKind,
+    error::ApiError,
+    utils::{
+        constants::*,
+        jobrecord::{JobRecord, JobStatus},
+        results::{DEFAULT_FILENAME, ResultFormat, presign_result, sanitize_filename},
+        storage::Storage,
+    },
+};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct UrlsRequest {
+    #[serde(default)]
+    pub expires_in: Option<u64>, // seconds, up to PRESIGNED_MAX_TIMEOUT
+    #[serde(default)]
+    pub filename: Option<String>, // download name without extension
+}
+
+#[derive(Serialize, Debug)]
+pub struct UrlsResponse {
+    pub urls: BTreeMap<ResultFormat, String>, // only formats fusion has produced
+    pub expires_in: u64,
+}
+
+#[tracing::instrument(level = "info", name = "urls", skip(storage))]
+pub async fn post_urls(storage: &Storage, id: &str, body: &str) -> Result<ApiResponse, ApiError> {
+    let request: UrlsRequest = if body.trim().is_empty() {
+        UrlsRequest::default()
+    } else {
+        match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::error!("{e}, body: {body}");
+                return ApiResponseKind::BadRequest.try_into();
+            }
+        }
+    };
+    let expires_in = request.expires_in.unwrap_or(PRESIGNED_TIMEOUT);
+    if expires_in == 0 || expires_in > PRESIGNED_MAX_TIMEOUT {
+        tracing::error!("expires_in must be between 1 and {PRESIGNED_MAX_TIMEOUT}, got {expires_in}");
+        return ApiResponseKind::BadRequest.try_into();
+    }
+    let filename = match request.filename.as_deref().map(sanitize_filename) {
+        None => DEFAULT_FILENAME.to_string(),
+        Some(Some(filename)) => filename,
+        Some(None) => {
+            tracing::error!("invalid filename: {:?}", request.filename);
+            return ApiResponseKind::BadRequest.try_into();
+        }
+    };
+
+    let record = JobRecord::load(storage, id)
+        .await
+        .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+    if record.as_ref().is_some_and(|r| r.status == JobStatus::Expired) {
+        return ApiResponseKind::Gone.try_into();
+    }
+
+    let mut urls = BTreeMap::new();
+    for format in ResultFormat::ALL {
+        let location = format
+            .location(id)
+            .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+        let exists = storage
+            .exists(&location)
+            .await
+            .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+        if !exists {
+            continue;
+        }
+        let url = presign_result(storage, id, format, Duration::from_secs(expires_in), &filename)
+            .await
+            .map_err(|e| ApiError::UnexpectedError(e.into()))?;
+        urls.insert(format, url);
+    }
+    if urls.is_empty() && record.is_none() {
+        return ApiResponseKind::NotFound.try_into();
+    }
+
+    let resp = UrlsResponse { urls, expires_in };
+    let body = serde_json::to_string(&resp)?;
+
+    let response = ApiResponseKind::Ok(Some(body)).try_into()?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_response_serialize_test() {
+        let resp = UrlsResponse {
+            urls: BTreeMap::from([
+                (ResultFormat::Json, "file:///tmp/id.json".to_string()),
+                (ResultFormat::Parquet, "file:///tmp/id.parquet".to_string()),
+            ]),
+            expires_in: 60,
+        };
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"urls":{"parquet":"file:///tmp/id.parquet","json":"file:///tmp/id.json"},"expires_in":60}"#
+        );
+    }
+}
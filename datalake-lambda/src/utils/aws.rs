@@ -4,13 +4,15 @@ use aws_config::{BehaviorVersion, Region, retry::RetryConfig, timeout::TimeoutCo
 use aws_sdk_ecs::Client as ECSClient;
 use aws_sdk_ecs::operation::run_task::RunTaskOutput;
 use aws_sdk_ecs::types::{
-    AssignPublicIp, AwsVpcConfiguration, ContainerOverride, KeyValuePair, LaunchType,
-    NetworkConfiguration, TaskOverride,
+    AssignPublicIp, AwsVpcConfiguration, CapacityProviderStrategyItem, ContainerOverride,
+    KeyValuePair, LaunchType, NetworkConfiguration, TaskOverride,
 };
+use aws_sdk_s3::Client;
 use aws_sdk_s3::config::Builder;
-use aws_sdk_s3::{Client, operation::get_object::GetObjectOutput};
 
+use crate::utils::constants::*;
 use crate::utils::error::UtilsError;
+use crate::utils::launcher::Capacity;
 
 pub async fn get_aws_client(region: String) -> Client {
     let region = Region::new(region);
@@ -23,9 +25,15 @@ pub async fn get_aws_client(region: String) -> Client {
         .operation_attempt_timeout(Duration::from_secs(60 * 5))
         .connect_timeout(Duration::from_secs(60 * 5))
         .build();
-    let config_builder = Builder::from(&sdk_config)
+    let mut config_builder = Builder::from(&sdk_config)
         .timeout_config(timeout)
         .retry_config(RetryConfig::standard().with_max_attempts(10));
+    // s3 compatible storage (MinIO, localstack) doesn't support virtual hosted buckets
+    if let Some(endpoint) = S3_ENDPOINT.as_deref() {
+        config_builder = config_builder
+            .endpoint_url(endpoint)
+            .force_path_style(true);
+    }
     let config = config_builder.build();
     Client::from_conf(config)
 }
@@ -39,52 +47,51 @@ pub async fn get_ecs_client(region: String) -> ECSClient {
     ECSClient::new(&sdk_config)
 }
 
-pub async fn get_aws_object(
-    client: Client,
-    bucket: &str,
-    key: &str,
-) -> Result<GetObjectOutput, UtilsError> {
-    let req = client.get_object().bucket(bucket).key(key);
+/// Cluster, task definition and network the fusion task is started with
+#[derive(Debug, Clone)]
+pub struct EcsTarget {
+    pub cluster: String,
+    pub task_definition: String,
+    pub container: String,
+    pub subnets: Vec<String>,
+    pub security_groups: Vec<String>,
+}
 
-    let res = req.send().await?;
+impl EcsTarget {
+    pub fn from_constants() -> Self {
+        Self {
+            cluster: CLUSTER.to_string(),
+            task_definition: TASK_NAME.to_string(),
+            container: CONTAINER_NAME.to_string(),
+            subnets: SUBNETS.iter().map(|x| x.to_string()).collect(),
+            security_groups: SECURITY_GROUPS.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+}
 
-    Ok(res)
+/// Environment of the fusion container, the job itself is read from the spec
+pub fn ecs_task_env(job_spec_uri: &str) -> Vec<KeyValuePair> {
+    vec![
+        KeyValuePair::builder()
+            .name("JOB_SPEC_URI")
+            .value(job_spec_uri)
+            .build(),
+    ]
 }
 
+/// Single RunTask call, failures in the output are checked by the launcher
+#[tracing::instrument(level = "info", name = "run_ecs_task", skip(client, target, env_vars), fields(cluster = target.cluster))]
 pub async fn run_ecs_task(
     client: &ECSClient,
-    cluster: &str,
-    task_definition: &str,
-    container: &str,
-    subnets: Option<Vec<String>>,
-    security_groups: Option<Vec<String>>,
-    request_id: &str,
-    query: &str,
-    table_path: &str,
-    table_name: &str,
+    target: &EcsTarget,
+    env_vars: Vec<KeyValuePair>,
+    capacity: Capacity,
+    client_token: &str,
 ) -> Result<RunTaskOutput, UtilsError> {
-    let env_vars = vec![
-        KeyValuePair::builder()
-            .name("REQUEST_ID")
-            .value(request_id)
-            .build(),
-        KeyValuePair::builder()
-            .name("QUERY")
-            .value(query)
-            .build(),
-        KeyValuePair::builder()
-            .name("TABLE_PATH")
-            .value(table_path)
-            .build(),
-        KeyValuePair::builder()
-            .name("TABLE_NAME")
-            .value(table_name)
-            .build(),
-    ];
     let overrides = TaskOverride::builder()
         .container_overrides(
             ContainerOverride::builder()
-                .name(container)
+                .name(&target.container)
                 .set_environment(Some(env_vars))
                 .build(),
         )
@@ -93,8 +100,8 @@ pub async fn run_ecs_task(
     let network_configuration = NetworkConfiguration::builder()
         .awsvpc_configuration(
             AwsVpcConfiguration::builder()
-                .set_subnets(subnets)
-                .set_security_groups(security_groups)
+                .set_subnets(Some(target.subnets.clone()))
+                .set_security_groups(Some(target.security_groups.clone()))
                 .assign_public_ip(AssignPublicIp::Disabled)
                 .build()?,
         )
@@ -102,11 +109,21 @@ pub async fn run_ecs_task(
 
     let run_task_builder = client.run_task();
     let run_task_builder = run_task_builder
-        .cluster(cluster)
-        .task_definition(task_definition)
-        .launch_type(LaunchType::Fargate)
+        .cluster(&target.cluster)
+        .task_definition(&target.task_definition)
+        .client_token(client_token)
         .network_configuration(network_configuration)
         .overrides(overrides);
+    // launch type and capacity provider strategy are mutually exclusive
+    let run_task_builder = match capacity {
+        Capacity::Fargate => run_task_builder.launch_type(LaunchType::Fargate),
+        Capacity::FargateSpot => run_task_builder.capacity_provider_strategy(
+            CapacityProviderStrategyItem::builder()
+                .capacity_provider("FARGATE_SPOT")
+                .weight(1)
+                .build()?,
+        ),
+    };
 
     let output = run_task_builder.send().await?;
     Ok(output)
@@ -0,0 +1,231 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use aes_gcm::{
+    Aes256Gcm, Key,
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::net::lookup_host;
+use url::{Host, Url};
+
+use crate::utils::constants::{ALLOW_PRIVATE_CALLBACKS, CALLBACK_SECRET_KEY};
+
+const REDACTED: &str = "***";
+
+#[derive(Error, Debug)]
+pub enum CallbackError {
+    #[error("invalid callback url: {0}")]
+    InvalidUrl(String),
+    #[error("callback secret is set without callback url")]
+    MissingUrl,
+    #[error("callback url resolves to a non public address: {0}")]
+    PrivateAddress(String),
+    #[error("callback secrets are disabled, CALLBACK_SECRET_KEY is not set")]
+    SecretsDisabled,
+    #[error("failed to encrypt callback secret")]
+    Encryption,
+}
+
+/// Webhook fusion notifies when the query finishes, fails or is cancelled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Callback {
+    pub url: String,
+    pub encrypted_secret: Option<String>, // payload is signed with hmac-sha256 when set, fusion decrypts it
+}
+
+impl Callback {
+    /// Callback from the query request, None when callback_url is not given
+    pub async fn new(url: Option<String>, secret: Option<String>) -> Result<Option<Self>, CallbackError> {
+        Self::with_policy(url, secret, *ALLOW_PRIVATE_CALLBACKS, CALLBACK_SECRET_KEY.as_ref()).await
+    }
+
+    /// Private addresses are rejected unless allow_private is set, secrets need a key
+    pub async fn with_policy(
+        url: Option<String>,
+        secret: Option<String>,
+        allow_private: bool,
+        key: Option<&[u8; 32]>,
+    ) -> Result<Option<Self>, CallbackError> {
+        let secret = secret.filter(|s| !s.is_empty());
+        let Some(url) = url else {
+            return match secret {
+                Some(_) => Err(CallbackError::MissingUrl),
+                None => Ok(None),
+            };
+        };
+        let parsed = Url::parse(&url).map_err(|e| CallbackError::InvalidUrl(format!("{url}: {e}")))?;
+        if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+            return Err(CallbackError::InvalidUrl(url));
+        }
+        if !allow_private {
+            check_public(&parsed).await?;
+        }
+        let encrypted_secret = match secret {
+            Some(secret) => Some(encrypt_secret(key.ok_or(CallbackError::SecretsDisabled)?, &secret)?),
+            None => None,
+        };
+        Ok(Some(Self { url, encrypted_secret }))
+    }
+}
+
+/// Every address the host resolves to must be public, metadata endpoints and the vpc are not reachable
+async fn check_public(url: &Url) -> Result<(), CallbackError> {
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs: Vec<IpAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => lookup_host((domain, port))
+            .await
+            .map_err(|e| CallbackError::InvalidUrl(format!("{url}: {e}")))?
+            .map(|addr| addr.ip())
+            .collect(),
+        None => return Err(CallbackError::InvalidUrl(url.to_string())),
+    };
+    match addrs.iter().find(|ip| !is_public(ip)) {
+        Some(ip) => Err(CallbackError::PrivateAddress(format!("{url}: {ip}"))),
+        None if addrs.is_empty() => Err(CallbackError::InvalidUrl(url.to_string())),
+        None => Ok(()),
+    }
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(&v4),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local() // 169.254.0.0/16, ec2 and ecs metadata endpoints
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (b & 0xc0) == 64) // 100.64.0.0/10, carrier-grade nat
+        || (a == 198 && (b & 0xfe) == 18) // 198.18.0.0/15, benchmarking
+        || a >= 240)
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local() // fc00::/7, includes the ecs metadata fd00:ec2::254
+        || ip.is_unicast_link_local()
+        || ip.segments()[0] == 0x2001 && ip.segments()[1] == 0xdb8) // documentation
+}
+
+/// base64(nonce || aes-256-gcm ciphertext), the spec never holds the secret in plaintext
+fn encrypt_secret(key: &[u8; 32], secret: &str) -> Result<String, CallbackError> {
+    let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(*key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, secret.as_bytes())
+        .map_err(|_| CallbackError::Encryption)?;
+    Ok(STANDARD.encode([&nonce[..], &ciphertext].concat()))
+}
+
+/// Request body for logs, callback secret is replaced
+pub fn redact_secret(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(Value::Object(mut map)) if map.contains_key("callback_secret") => {
+            map.insert("callback_secret".to_string(), Value::String(REDACTED.to_string()));
+            Value::Object(map).to_string()
+        }
+        _ => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[rstest]
+    #[case(None, None, true)]
+    #[case(Some("https://example.com/hook"), None, true)]
+    #[case(Some("http://localhost:8080/hook"), Some("secret"), true)]
+    #[case(None, Some("secret"), false)]
+    #[case(Some("ftp://example.com/hook"), None, false)]
+    #[case(Some("not a url"), None, false)]
+    #[case(Some("file:///etc/passwd"), None, false)]
+    #[tokio::test]
+    async fn callback_new_test(#[case] url: Option<&str>, #[case] secret: Option<&str>, #[case] is_ok: bool) {
+        let callback = Callback::with_policy(url.map(str::to_string), secret.map(str::to_string), true, Some(&KEY)).await;
+        assert_eq!(callback.is_ok(), is_ok);
+    }
+
+    #[rstest]
+    #[case("http://localhost:8080/hook")]
+    #[case("http://127.0.0.1/hook")]
+    #[case("http://169.254.169.254/latest/meta-data/")]
+    #[case("http://169.254.170.2/v2/credentials")]
+    #[case("http://10.0.0.1/hook")]
+    #[case("http://172.16.3.4/hook")]
+    #[case("http://192.168.1.1/hook")]
+    #[case("http://100.64.0.1/hook")]
+    #[case("http://0.0.0.0/hook")]
+    #[case("http://[::1]/hook")]
+    #[case("http://[fd00:ec2::254]/hook")]
+    #[case("http://[fe80::1]/hook")]
+    #[case("http://[::ffff:169.254.169.254]/hook")]
+    #[tokio::test]
+    async fn callback_private_address_test(#[case] url: &str) {
+        let callback = Callback::with_policy(Some(url.to_string()), None, false, None).await;
+        assert!(matches!(callback, Err(CallbackError::PrivateAddress(_))), "{url}: {callback:?}");
+        let allowed = Callback::with_policy(Some(url.to_string()), None, true, None).await;
+        assert!(allowed.is_ok());
+    }
+
+    #[rstest]
+    #[case("8.8.8.8", true)]
+    #[case("2606:4700:4700::1111", true)]
+    #[case("172.32.0.1", true)]
+    #[case("198.18.0.1", false)]
+    #[case("255.255.255.255", false)]
+    #[case("2001:db8::1", false)]
+    fn is_public_test(#[case] ip: &str, #[case] expected: bool) {
+        assert_eq!(is_public(&ip.parse().unwrap()), expected);
+    }
+
+    #[tokio::test]
+    async fn callback_secret_encrypted_test() {
+        let url = Some("http://localhost/hook".to_string());
+        let secret = Some("s3cr3t".to_string());
+        let disabled = Callback::with_policy(url.clone(), secret.clone(), true, None).await;
+        assert!(matches!(disabled, Err(CallbackError::SecretsDisabled)));
+
+        let callback = Callback::with_policy(url, secret, true, Some(&KEY)).await.unwrap().unwrap();
+        let encrypted = STANDARD.decode(callback.encrypted_secret.unwrap()).unwrap();
+        assert!(!String::from_utf8_lossy(&encrypted).contains("s3cr3t"));
+        let (nonce, ciphertext) = encrypted.split_at(12);
+        let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(KEY));
+        let plaintext = cipher.decrypt(&<[u8; 12]>::try_from(nonce).unwrap().into(), ciphertext).unwrap();
+        assert_eq!(plaintext, b"s3cr3t");
+    }
+
+    #[rstest]
+    #[case(r#"{"query":"select 1","callback_secret":"s3cr3t"}"#, false)]
+    #[case(r#"{"query":"select 1"}"#, true)]
+    #[case("not json s3cr3t", true)]
+    fn redact_secret_test(#[case] body: &str, #[case] unchanged: bool) {
+        let redacted = redact_secret(body);
+        assert_eq!(redacted == body, unchanged);
+        if !unchanged {
+            assert!(!redacted.contains("s3cr3t"));
+            assert!(redacted.contains(REDACTED));
+        }
+    }
+}
@@ -1,10 +1,142 @@
+use std::{env as std_env, sync::LazyLock};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use dotenvy::dotenv;
+
+use crate::utils::{jobspec::ResourceSpec, retention::RetentionPolicy, rowlimit::RowLimitPolicy};
+
+pub mod env {
+    pub const S3_ENDPOINT_ENV_VAR: &str = "S3_ENDPOINT";
+    pub const RESULTS_URL_ENV_VAR: &str = "RESULTS_URL";
+    pub const ALLOW_LOCAL_PATHS_ENV_VAR: &str = "ALLOW_LOCAL_PATHS";
+    pub const ALLOW_PRIVATE_CALLBACKS_ENV_VAR: &str = "ALLOW_PRIVATE_CALLBACKS";
+    pub const CALLBACK_SECRET_KEY_ENV_VAR: &str = "CALLBACK_SECRET_KEY";
+    pub const DENIED_FUNCTIONS_ENV_VAR: &str = "DENIED_FUNCTIONS";
+    pub const ROW_LIMIT_POLICY_ENV_VAR: &str = "ROW_LIMIT_POLICY";
+    pub const RETENTION_POLICY_ENV_VAR: &str = "RETENTION_POLICY";
+    pub const ECS_USE_SPOT_ENV_VAR: &str = "ECS_USE_SPOT";
+    pub const JOB_RESOURCES_ENV_VAR: &str = "JOB_RESOURCES";
+    pub const OTEL_ENDPOINT_ENV_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+}
+
 pub const REGION: &str = "eu-central-1";
 pub const DATA_BUCKET: &str = "bucket";
 pub const DATA_PREFIX: &str = "prefix"; // prefix for parquet
 pub const PRESIGNED_TIMEOUT: u64 = 3600; // url is available for 1 hour
+pub const PRESIGNED_MAX_TIMEOUT: u64 = 7 * 24 * 3600; // max for sigv4, urls made with role credentials expire with the session
 pub const MAX_ROWS: u64 = 1000;
+pub const ROWS_PAGE_SIZE: u64 = 100; // default page size for GET /query/{id}/rows
+pub const ROWS_PAGE_MAX: u64 = 1000;
+pub const ROWS_SORT_MAX_ROWS: u64 = 200_000; // larger results are not sorted, the whole projected file is read into memory
+pub const PROFILE_TOP_K: usize = 10; // most frequent values per column in POST /profile
+pub const PROFILE_MAX_TOP_K: usize = 100;
+pub const PROFILE_BINS: usize = 20; // histogram bins of numeric and date columns
+pub const PROFILE_MAX_BINS: usize = 100;
 pub const CLUSTER: &str = "cluster";
 pub const SUBNETS: [&str; 2] = ["foo", "bar"];
 pub const SECURITY_GROUPS: [&str; 1] = ["sg-foo"];
 pub const CONTAINER_NAME: &str = "foo";
 pub const TASK_NAME: &str = "bar";
+pub const ECS_LAUNCH_MAX_ATTEMPTS: u32 = 3;
+pub const ECS_LAUNCH_BACKOFF_MS: u64 = 1000; // doubled after every failed launch
+
+/// Custom S3 endpoint (MinIO, localstack), requests use path-style addressing when set
+pub static S3_ENDPOINT: LazyLock<Option<String>> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::S3_ENDPOINT_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+});
+
+/// Location of query results, either s3://bucket/prefix or file:///path/
+pub static RESULTS_URL: LazyLock<String> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::RESULTS_URL_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| format!("s3://{DATA_BUCKET}/{DATA_PREFIX}"))
+});
+
+/// Accept file:// table paths, only for local runs where results are local too or the flag is set
+pub static ALLOW_LOCAL_PATHS: LazyLock<bool> = LazyLock::new(|| {
+    dotenv().ok();
+    RESULTS_URL.starts_with("file://")
+        || std_env::var(env::ALLOW_LOCAL_PATHS_ENV_VAR)
+            .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+});
+
+/// Accept callback urls on loopback, link-local and private addresses, for tests and local runs
+pub static ALLOW_PRIVATE_CALLBACKS: LazyLock<bool> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::ALLOW_PRIVATE_CALLBACKS_ENV_VAR)
+        .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+});
+
+/// Base64 aes-256 key shared with fusion, callback secrets are encrypted with it in the job spec
+pub static CALLBACK_SECRET_KEY: LazyLock<Option<[u8; 32]>> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::CALLBACK_SECRET_KEY_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            STANDARD.decode(v.trim()).ok()
+                .and_then(|key| key.try_into().ok())
+                .expect("CALLBACK_SECRET_KEY must be a base64 32 byte key.")
+        })
+});
+
+/// Functions user queries may not call, comma separated list, case insensitive
+pub static DENIED_FUNCTIONS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::DENIED_FUNCTIONS_ENV_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+});
+
+/// Row limits per principal and route as json, MAX_ROWS for everyone when not set
+pub static ROW_LIMIT_POLICY: LazyLock<RowLimitPolicy> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::ROW_LIMIT_POLICY_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| serde_json::from_str(&v).expect("ROW_LIMIT_POLICY must be valid json."))
+        .unwrap_or_default()
+});
+
+/// Result ttl and per principal storage quotas as json, results are kept for a week when not set
+pub static RETENTION_POLICY: LazyLock<RetentionPolicy> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::RETENTION_POLICY_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| serde_json::from_str(&v).expect("RETENTION_POLICY must be valid json."))
+        .unwrap_or_default()
+});
+
+/// Start fusion on Fargate Spot first, on-demand Fargate is used when spot has no capacity
+pub static ECS_USE_SPOT: LazyLock<bool> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::ECS_USE_SPOT_ENV_VAR)
+        .is_ok_and(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+});
+
+/// Memory pool, spill and timeout limits passed to fusion in the job spec as json, unlimited when not set
+pub static JOB_RESOURCES: LazyLock<ResourceSpec> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::JOB_RESOURCES_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(|v| serde_json::from_str(&v).expect("JOB_RESOURCES must be valid json."))
+        .unwrap_or_default()
+});
+
+/// OTLP/HTTP collector, http://localhost:4318, traces and metrics are exported when set and built with the otel feature
+pub static OTEL_ENDPOINT: LazyLock<Option<String>> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::OTEL_ENDPOINT_ENV_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+});
@@ -1,6 +1,8 @@
 use aws_sdk_ecs::operation::run_task::RunTaskError;
 use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::delete_object::DeleteObjectError;
 use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
 use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
 use aws_sdk_s3::operation::put_object::PutObjectError;
 use aws_smithy_types::byte_stream::error::Error as AWSSmithyError;
@@ -32,6 +34,12 @@ pub enum UtilsError {
     #[error("AWS PutObjectError error")]
     PutObjectError(#[from] SdkError<PutObjectError>),
 
+    #[error("AWS HeadObjectError error")]
+    HeadObjectError(#[from] SdkError<HeadObjectError>),
+
+    #[error("AWS DeleteObjectError error")]
+    DeleteObjectError(#[from] SdkError<DeleteObjectError>),
+
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
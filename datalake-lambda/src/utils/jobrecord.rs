@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    constants::RESULTS_URL,
+    error::UtilsError,
+    pathparser::PathParserError,
+    storage::{Storage, StorageLocation},
+};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Submitted,
+    Succeeded,
+    Failed,
+    Cancelled,
+    Expired, // results were deleted by retention cleanup
+}
+
+/// Why the job failed, set together with the error message
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    ResourcesExhausted, // memory pool or spill space limit of the job
+    Timeout,
+    Query, // invalid query, plan or job spec
+    Storage,
+    Launch, // the task could not be started
+    Internal,
+}
+
+/// Callback delivery made by fusion when the job finished
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CallbackAttempt {
+    pub attempt: u32,
+    pub at: DateTime<Utc>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// State of a query job, stored next to the results as {request_id}.job.json
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct JobRecord {
+    pub request_id: String,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub principal: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub row_limit: Option<u64>,
+    #[serde(default)]
+    pub expired_reason: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>, // set by fusion when the query failed or was cancelled
+    #[serde(default)]
+    pub error_kind: Option<ErrorKind>,
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    #[serde(default)]
+    pub callback_attempts: Vec<CallbackAttempt>,
+}
+
+impl JobRecord {
+    pub fn new(request_id: &str, principal: Option<String>, row_limit: Option<u64>) -> Self {
+        let now = Utc::now();
+        Self {
+            request_id: request_id.to_string(),
+            status: JobStatus::Submitted,
+            principal,
+            created_at: now,
+            updated_at: now,
+            row_limit,
+            expired_reason: None,
+            error: None,
+            error_kind: None,
+            callback_url: None,
+            callback_attempts: vec![],
+        }
+    }
+
+    pub fn location(request_id: &str) -> Result<StorageLocation, PathParserError> {
+        let results = StorageLocation::parse(&RESULTS_URL)?;
+        Ok(results.join(&format!("{request_id}.job.json")))
+    }
+
+    /// Record of the job, None when the job is unknown
+    pub async fn load(storage: &Storage, request_id: &str) -> Result<Option<Self>, UtilsError> {
+        let location = Self::location(request_id).map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+        if !storage.exists(&location).await? {
+            return Ok(None);
+        }
+        let data = storage.get(&location).await?;
+        let record = serde_json::from_slice(&data).map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+        Ok(Some(record))
+    }
+
+    pub async fn save(&self, storage: &Storage) -> Result<(), UtilsError> {
+        let location =
+            Self::location(&self.request_id).map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+        let body = serde_json::to_vec(self).map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+        storage.put(&location, body).await
+    }
+
+    pub fn finish(&mut self, status: JobStatus, error: Option<(ErrorKind, String)>) {
+        self.status = status;
+        self.error_kind = error.as_ref().map(|(kind, _)| *kind);
+        self.error = error.map(|(_, message)| message);
+        self.updated_at = Utc::now();
+    }
+
+    pub fn expire(&mut self, reason: &str) {
+        self.status = JobStatus::Expired;
+        self.expired_reason = Some(reason.to_string());
+        self.updated_at = Utc::now();
+    }
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::{
+    callback::Callback,
+    constants::{JOB_RESOURCES, RESULTS_URL},
+    error::UtilsError,
+    pathparser::PathParserError,
+    queryparams::QueryParam,
+    queryparser::TableRef,
+    results::ResultFormat,
+    storage::{Storage, StorageLocation},
+    telemetry::traceparent,
+    tracing::xray_trace_id,
+};
+
+/// Bumped when fusion can't read specs of the previous version
+pub const JOB_SPEC_VERSION: u32 = 1;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CallbackSpec {
+    pub url: String,
+    pub encrypted_secret: Option<String>, // decrypted by fusion with CALLBACK_SECRET_KEY
+    pub result_urls: Value, // presigned urls returned to the caller, sent back in the payload
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryPoolKind {
+    #[default]
+    Fair,
+    Greedy,
+}
+
+/// Runtime limits of the fusion task, configured as json:
+/// {"memory_pool": "fair", "memory_limit": 6442450944, "spill_dir": "/tmp/spill", "timeout_secs": 900}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ResourceSpec {
+    pub memory_pool: MemoryPoolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<usize>, // bytes, below the task memory so fusion fails before the oom killer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spill_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_spill_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Profile of the only table instead of a query, fusion writes {request_id}.profile.json
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ProfileSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<String>>, // all columns when not given
+    pub top_k: usize,
+    pub bins: usize,
+}
+
+/// Trace of the request which started the job, fusion logs it with every event of the job
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct TraceContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xray_trace_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traceparent: Option<String>, // fusion spans continue the trace of the lambda span
+}
+
+impl TraceContext {
+    pub fn current() -> Self {
+        Self {
+            xray_trace_id: xray_trace_id(),
+            traceparent: traceparent(),
+        }
+    }
+}
+
+/// Everything fusion needs to run the query, stored as {request_id}.spec.json,
+/// the task only gets its uri, ecs overrides are limited to 8 KiB
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct JobSpec {
+    pub version: u32,
+    pub request_id: String,
+    pub query: String,
+    pub params: Vec<QueryParam>,
+    pub tables: Vec<TableRef>,
+    pub formats: Vec<ResultFormat>, // result files, the manifest is always written
+    pub row_limit: Option<u64>, // None for profiles
+    pub output: String, // results location, files are {output}{request_id}.{extension}
+    pub callback: Option<CallbackSpec>,
+    pub resources: ResourceSpec,
+    pub trace: TraceContext,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileSpec>,
+}
+
+impl JobSpec {
+    pub fn new(
+        request_id: &str,
+        query: &str,
+        params: &[QueryParam],
+        tables: &[TableRef],
+        row_limit: u64,
+        callback: Option<(Callback, Value)>,
+    ) -> Self {
+        Self {
+            version: JOB_SPEC_VERSION,
+            request_id: request_id.to_string(),
+            query: query.to_string(),
+            params: params.to_vec(),
+            tables: tables.to_vec(),
+            formats: vec![ResultFormat::Parquet, ResultFormat::Json],
+            row_limit: Some(row_limit),
+            output: RESULTS_URL.to_string(),
+            callback: callback.map(|(callback, result_urls)| CallbackSpec {
+                url: callback.url,
+                encrypted_secret: callback.encrypted_secret,
+                result_urls,
+            }),
+            resources: JOB_RESOURCES.clone(),
+            trace: TraceContext::current(),
+            profile: None,
+        }
+    }
+
+    /// Spec profiling one table, there is no query and no result files besides the profile
+    pub fn profile(request_id: &str, table: TableRef, profile: ProfileSpec) -> Self {
+        Self {
+            query: String::new(),
+            tables: vec![table],
+            formats: vec![],
+            row_limit: None,
+            profile: Some(profile),
+            ..Self::new(request_id, "", &[], &[], 0, None)
+        }
+    }
+
+    pub fn location(request_id: &str) -> Result<StorageLocation, PathParserError> {
+        let results = StorageLocation::parse(&RESULTS_URL)?;
+        Ok(results.join(&format!("{request_id}.spec.json")))
+    }
+
+    /// Write the spec next to the results, location is passed to the task
+    pub async fn save(&self, storage: &Storage) -> Result<StorageLocation, UtilsError> {
+        let location =
+            Self::location(&self.request_id).map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+        let body = serde_json::to_vec(self).map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+        storage.put(&location, body).await?;
+        Ok(location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::queryparser::{SampleSize, TableSample};
+
+    #[test]
+    fn job_spec_serialize_test() {
+        let tables = vec![
+            TableRef {
+                name: "t0".to_string(),
+                path: "s3://bucket/table/".to_string(),
+                sample: None,
+            },
+            TableRef {
+                name: "t1".to_string(),
+                path: "s3://bucket/other/".to_string(),
+                sample: Some(TableSample { size: SampleSize::Rows(500), seed: Some(7) }),
+            },
+        ];
+        let callback = Callback {
+            url: "https://example.com/hook".to_string(),
+            encrypted_secret: None,
+        };
+        let spec = JobSpec::new(
+            "id",
+            "SELECT * FROM t0 WHERE a = $1 LIMIT 1001",
+            &[QueryParam::Integer(1)],
+            &tables,
+            1000,
+            Some((callback, json!({"parquet": "https://example.com/id.parquet"}))),
+        );
+        let value = serde_json::to_value(&spec).unwrap();
+        assert_eq!(value["version"], JOB_SPEC_VERSION);
+        assert_eq!(value["formats"], json!(["parquet", "json"]));
+        assert_eq!(value["params"], json!([{"type": "integer", "value": 1}]));
+        assert_eq!(
+            value["tables"],
+            json!([
+                {"name": "t0", "path": "s3://bucket/table/"},
+                {"name": "t1", "path": "s3://bucket/other/", "sample": {"rows": 500, "seed": 7}}
+            ])
+        );
+        assert_eq!(value["callback"]["url"], "https://example.com/hook");
+        assert_eq!(value["callback"]["encrypted_secret"], Value::Null);
+        assert_eq!(value["resources"], json!({"memory_pool": "fair"}));
+    }
+
+    #[test]
+    fn job_spec_profile_test() {
+        let table = TableRef {
+            name: "table".to_string(),
+            path: "s3://bucket/table/".to_string(),
+            sample: None,
+        };
+        let profile = ProfileSpec { columns: None, top_k: 10, bins: 20 };
+        let value = serde_json::to_value(JobSpec::profile("id", table, profile)).unwrap();
+        assert_eq!(value["query"], "");
+        assert_eq!(value["formats"], json!([]));
+        assert_eq!(value["tables"], json!([{"name": "table", "path": "s3://bucket/table/"}]));
+        assert_eq!(value["profile"], json!({"top_k": 10, "bins": 20}));
+        assert_eq!(value["callback"], Value::Null);
+    }
+
+    #[test]
+    fn resource_spec_parse_test() {
+        let resources: ResourceSpec =
+            serde_json::from_str(r#"{"memory_pool": "greedy", "memory_limit": 1073741824, "timeout_secs": 600}"#)
+                .unwrap();
+        assert_eq!(resources.memory_pool, MemoryPoolKind::Greedy);
+        assert_eq!(resources.memory_limit, Some(1073741824));
+        assert_eq!(resources.spill_dir, None);
+        assert!(serde_json::from_str::<ResourceSpec>(r#"{"memory_pool": "lru"}"#).is_err());
+    }
+}
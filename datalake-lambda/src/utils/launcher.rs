@@ -0,0 +1,276 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use aws_sdk_ecs::Client as ECSClient;
+use aws_sdk_ecs::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_ecs::operation::run_task::{RunTaskError, RunTaskOutput};
+use aws_sdk_ecs::types::KeyValuePair;
+use thiserror::Error;
+
+use crate::utils::{
+    aws::{EcsTarget, run_ecs_task},
+    constants::*,
+    error::UtilsError,
+    telemetry::record_launch,
+};
+
+/// Capacity the fusion task runs on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capacity {
+    Fargate,
+    FargateSpot, // cheaper, may have no capacity, on-demand is used then
+}
+
+#[derive(Error, Debug)]
+#[error("ecs task was not started after {attempts} attempts: {reason}")]
+pub struct LaunchError {
+    pub attempts: u32,
+    pub reason: String,
+}
+
+/// Why a single RunTask call did not start the task
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttemptError {
+    Transient(String), // capacity, throttling, server errors, worth retrying
+    Permanent(String), // missing task definition, bad parameters, access denied
+}
+
+impl AttemptError {
+    fn reason(&self) -> &str {
+        match self {
+            AttemptError::Transient(reason) | AttemptError::Permanent(reason) => reason,
+        }
+    }
+}
+
+/// Failure reasons ECS reports for tasks it could not place, RESOURCE:MEMORY, AGENT, ...
+pub fn is_transient_failure(reason: &str) -> bool {
+    let reason = reason.to_lowercase();
+    reason.starts_with("resource:")
+        || reason == "agent"
+        || ["capacity", "throttl", "unavailable", "timeout"]
+            .iter()
+            .any(|v| reason.contains(v))
+}
+
+/// Arn of the started task, RunTask succeeds even when no task was placed
+pub fn check_output(output: &RunTaskOutput) -> Result<String, AttemptError> {
+    if let Some(failure) = output.failures().first() {
+        let reason = failure.reason().unwrap_or("unknown");
+        let message = match failure.detail() {
+            Some(detail) => format!("{reason} ({detail})"),
+            None => reason.to_string(),
+        };
+        return Err(if is_transient_failure(reason) {
+            AttemptError::Transient(message)
+        } else {
+            AttemptError::Permanent(message)
+        });
+    }
+    output
+        .tasks()
+        .first()
+        .and_then(|t| t.task_arn())
+        .map(str::to_string)
+        .ok_or_else(|| AttemptError::Transient("no task was started".to_string()))
+}
+
+pub fn check_sdk_error(error: &SdkError<RunTaskError>) -> AttemptError {
+    let transient = match error {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => true,
+        SdkError::ServiceError(e) => match e.err() {
+            RunTaskError::ServerException(_) => true,
+            e => e.code().is_some_and(|c| c.contains("Throttl")),
+        },
+        _ => false,
+    };
+    let message = match error.as_service_error().and_then(|e| e.message()) {
+        Some(message) => message.to_string(),
+        None => error.to_string(),
+    };
+    if transient {
+        AttemptError::Transient(message)
+    } else {
+        AttemptError::Permanent(message)
+    }
+}
+
+/// RunTask idempotency token of the job. It only changes once ECS answered that no task was placed,
+/// a call which timed out may have started the task and is retried with the same token
+pub fn client_token(request_id: &str, generation: u32) -> String {
+    format!("{request_id}-{generation}")
+}
+
+/// Start the fusion task, transient failures are retried with backoff,
+/// spot capacity falls back to on-demand after the first transient failure
+#[tracing::instrument(level = "info", name = "launch_task", skip(client, target, env_vars))]
+pub async fn launch_task(
+    client: &ECSClient,
+    target: &EcsTarget,
+    env_vars: Vec<KeyValuePair>,
+    use_spot: bool,
+    request_id: &str,
+) -> Result<String, LaunchError> {
+    launch_with(use_spot, request_id, |capacity, token| {
+        let env_vars = env_vars.clone();
+        async move { run_ecs_task(client, target, env_vars, capacity, &token).await }
+    })
+    .await
+}
+
+/// Launch loop, run makes one RunTask call with the capacity and client token
+async fn launch_with<F, Fut>(use_spot: bool, request_id: &str, mut run: F) -> Result<String, LaunchError>
+where
+    F: FnMut(Capacity, String) -> Fut,
+    Fut: Future<Output = Result<RunTaskOutput, UtilsError>>,
+{
+    let start = Instant::now();
+    let mut capacity = if use_spot { Capacity::FargateSpot } else { Capacity::Fargate };
+    let mut backoff = Duration::from_millis(ECS_LAUNCH_BACKOFF_MS);
+    let mut attempt = 0;
+    let mut generation = 0;
+    loop {
+        attempt += 1;
+        let (result, answered) = match run(capacity, client_token(request_id, generation)).await {
+            Ok(output) => (check_output(&output), true),
+            Err(UtilsError::EcsRunTaskError(e)) => (Err(check_sdk_error(&e)), false),
+            Err(e) => (Err(AttemptError::Permanent(e.to_string())), false),
+        };
+        let error = match result {
+            Ok(task_arn) => {
+                tracing::info!({ task_arn, attempt, capacity = ?capacity }, "ecs task started");
+                record_launch(capacity, true, start.elapsed());
+                return Ok(task_arn);
+            }
+            Err(e) => e,
+        };
+        tracing::warn!({ attempt, capacity = ?capacity, error = ?error }, "ecs task launch failed");
+        if matches!(error, AttemptError::Permanent(_)) || attempt >= ECS_LAUNCH_MAX_ATTEMPTS {
+            record_launch(capacity, false, start.elapsed());
+            return Err(LaunchError {
+                attempts: attempt,
+                reason: error.reason().to_string(),
+            });
+        }
+        if answered {
+            // ecs reported the placement failure, a retry with the same token would get it again
+            generation += 1;
+        }
+        if capacity == Capacity::FargateSpot {
+            // spot is short of capacity, on-demand is tried right away
+            capacity = Capacity::Fargate;
+            continue;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_ecs::types::{Failure, Task};
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("RESOURCE:MEMORY", true)]
+    #[case("RESOURCE:ENI", true)]
+    #[case("AGENT", true)]
+    #[case("Capacity is unavailable at this time. Please try again later or in a different availability zone", true)]
+    #[case("MISSING", false)]
+    #[case("ATTRIBUTE", false)]
+    #[case("INACTIVE", false)]
+    fn is_transient_failure_test(#[case] reason: &str, #[case] expected: bool) {
+        assert_eq!(is_transient_failure(reason), expected);
+    }
+
+    #[test]
+    fn check_output_started_test() {
+        let output = RunTaskOutput::builder()
+            .tasks(Task::builder().task_arn("arn:aws:ecs:task/1").build())
+            .build();
+        assert_eq!(check_output(&output), Ok("arn:aws:ecs:task/1".to_string()));
+    }
+
+    #[rstest]
+    #[case(Some("RESOURCE:CPU"), None, AttemptError::Transient("RESOURCE:CPU".to_string()))]
+    #[case(Some("MISSING"), Some("task definition"), AttemptError::Permanent("MISSING (task definition)".to_string()))]
+    #[case(None, None, AttemptError::Permanent("unknown".to_string()))]
+    fn check_output_failure_test(
+        #[case] reason: Option<&str>,
+        #[case] detail: Option<&str>,
+        #[case] expected: AttemptError,
+    ) {
+        let failure = Failure::builder()
+            .set_reason(reason.map(str::to_string))
+            .set_detail(detail.map(str::to_string))
+            .build();
+        let output = RunTaskOutput::builder().failures(failure).build();
+        assert_eq!(check_output(&output), Err(expected));
+    }
+
+    #[test]
+    fn check_output_empty_test() {
+        let output = RunTaskOutput::builder().build();
+        assert!(matches!(check_output(&output), Err(AttemptError::Transient(_))));
+    }
+
+    /// Fake RunTask answering with given results in order, calls are recorded
+    async fn launch_fake(
+        use_spot: bool,
+        results: Vec<Result<RunTaskOutput, UtilsError>>,
+    ) -> (Result<String, LaunchError>, Vec<(Capacity, String)>) {
+        let mut results = results.into_iter();
+        let mut calls = vec![];
+        let res = launch_with(use_spot, "id", |capacity, token| {
+            calls.push((capacity, token));
+            let result = results.next().expect("unexpected RunTask call");
+            async move { result }
+        })
+        .await;
+        (res, calls)
+    }
+
+    fn started() -> RunTaskOutput {
+        RunTaskOutput::builder()
+            .tasks(Task::builder().task_arn("arn:aws:ecs:task/1").build())
+            .build()
+    }
+
+    fn timed_out() -> UtilsError {
+        UtilsError::EcsRunTaskError(SdkError::timeout_error("timed out"))
+    }
+
+    #[tokio::test]
+    async fn launch_reuses_token_after_timeout_test() {
+        let (res, calls) = launch_fake(true, vec![Err(timed_out()), Err(timed_out()), Ok(started())]).await;
+        assert_eq!(res.unwrap(), "arn:aws:ecs:task/1");
+        let expected = vec![
+            (Capacity::FargateSpot, "id-0".to_string()),
+            (Capacity::Fargate, "id-0".to_string()),
+            (Capacity::Fargate, "id-0".to_string()),
+        ];
+        assert_eq!(calls, expected);
+    }
+
+    #[tokio::test]
+    async fn launch_new_token_after_placement_failure_test() {
+        let failed = Ok(RunTaskOutput::builder()
+            .failures(Failure::builder().reason("RESOURCE:MEMORY").build())
+            .build());
+        let (res, calls) = launch_fake(true, vec![failed, Ok(started())]).await;
+        assert!(res.is_ok());
+        let expected = vec![
+            (Capacity::FargateSpot, "id-0".to_string()),
+            (Capacity::Fargate, "id-1".to_string()),
+        ];
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn check_sdk_error_timeout_test() {
+        let error: SdkError<RunTaskError> = SdkError::timeout_error("timed out");
+        assert!(matches!(check_sdk_error(&error), AttemptError::Transient(_)));
+    }
+}
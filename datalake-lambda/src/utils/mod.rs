@@ -1,7 +1,19 @@
 pub mod aws;
+pub mod callback;
 pub mod constants;
 pub mod error;
+pub mod jobrecord;
+pub mod jobspec;
+pub mod launcher;
 pub mod pathparser;
 pub mod pathvalidator;
+pub mod queryparams;
 pub mod queryparser;
+pub mod resultreader;
+pub mod results;
+pub mod retention;
+pub mod rowlimit;
+pub mod sqlpolicy;
+pub mod storage;
+pub mod telemetry;
 pub mod tracing;
@@ -1,11 +1,17 @@
+use percent_encoding::percent_decode_str;
 use thiserror::Error;
 use url::Url;
 
+use crate::utils::{constants::ALLOW_LOCAL_PATHS, storage::StorageLocation};
+
 #[derive(Debug, Error, PartialEq)]
 pub enum PathParserError {
-    #[error("Invalid S3 path: must start with s3://")]
+    #[error("Invalid S3 path: must start with s3:// or file://")]
     InvalidScheme,
 
+    #[error("Invalid S3 path: file:// paths are not enabled")]
+    LocalPathNotAllowed,
+
     #[error("Invalid S3 path: missing bucket name")]
     MissingBucket,
 
@@ -16,27 +22,58 @@ pub enum PathParserError {
     ParseError(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TablePathScheme {
+    S3,
+    File,
+}
+
 #[derive(Debug)]
 pub struct ParseredTablePath {
     pub url: Url,
-    pub bucket: String,
+    pub scheme: TablePathScheme,
+    pub bucket: String, // empty for local paths
     pub prefix: Option<String>,
 }
 
 impl ParseredTablePath {
     pub fn new(path: &str) -> Result<Self, PathParserError> {
+        Self::parse(path, *ALLOW_LOCAL_PATHS)
+    }
+
+    /// file:// paths are rejected unless allow_local is set
+    pub fn parse(path: &str, allow_local: bool) -> Result<Self, PathParserError> {
         let cleaned = path.trim_matches(&['\'', '"'][..]).trim();
         let url = Url::parse(cleaned)
             .map_err(|e| PathParserError::ParseError(e.to_string()))?;
-        if url.scheme() != "s3" {
-            return Err(PathParserError::InvalidScheme);
-        }
-        let bucket = url.host_str().ok_or(PathParserError::MissingBucket)?.to_string();
+        let scheme = match url.scheme() {
+            "s3" => TablePathScheme::S3,
+            "file" if allow_local => TablePathScheme::File,
+            "file" => return Err(PathParserError::LocalPathNotAllowed),
+            _ => return Err(PathParserError::InvalidScheme),
+        };
+        let bucket = match scheme {
+            TablePathScheme::S3 => url.host_str().ok_or(PathParserError::MissingBucket)?.to_string(),
+            TablePathScheme::File => String::new(),
+        };
         let prefix = url.path()
             .strip_prefix('/')
             .filter(|s| !s.is_empty())
-            .map(String::from);
-        Ok(Self{ url, bucket, prefix })
+            .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned());
+        if scheme == TablePathScheme::File && prefix.is_none() {
+            return Err(PathParserError::MissingTableName);
+        }
+        Ok(Self{ url, scheme, bucket, prefix })
+    }
+
+    pub fn location(&self) -> StorageLocation {
+        match self.scheme {
+            TablePathScheme::S3 => StorageLocation::S3 {
+                bucket: self.bucket.clone(),
+                key: self.prefix.clone().unwrap_or_default(),
+            },
+            TablePathScheme::File => StorageLocation::Local(self.url.to_file_path().unwrap_or_default()),
+        }
     }
 }
 
@@ -47,6 +84,7 @@ impl AsRef<str> for ParseredTablePath {
 }
 
 impl ParseredTablePath {
+    /// Table name from the last path segment, safe to use as sql identifier
     pub fn extract_table_name(&self) -> Result<String, PathParserError> {
         let bucket = &self.bucket;
         let table_name = match &self.prefix {
@@ -56,7 +94,25 @@ impl ParseredTablePath {
             },
             None => bucket,
         };
-        Ok(table_name.to_string())
+        Ok(to_identifier(table_name))
+    }
+}
+
+/// lowercase ascii letters, digits and underscores, never starts with a digit
+fn to_identifier(name: &str) -> String {
+    let mut ident = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            ident.push(c.to_ascii_lowercase());
+        } else if !ident.ends_with('_') {
+            ident.push('_');
+        }
+    }
+    let ident = ident.trim_matches('_');
+    match ident.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => ident.to_string(),
+        Some(_) => format!("t_{ident}"),
+        None => "t".to_string(),
     }
 }
 
@@ -73,9 +129,13 @@ mod tests {
     #[case("s3://bucket/path-to-data", Ok("s3://bucket/path-to-data"))]
     #[case("s3://path-to-data/", Ok("s3://path-to-data/"))]
     #[case("s3://path-to-data", Ok("s3://path-to-data"))]
+    #[case("'file:///data/path-to-data/'", Ok("file:///data/path-to-data/"))]
+    #[case("file:///path-to-data", Ok("file:///path-to-data"))]
     #[case("s3://", Err(PathParserError::MissingBucket))]
     #[case("s3:/", Err(PathParserError::MissingBucket))]
     #[case("s3:", Err(PathParserError::MissingBucket))]
+    #[case("file:///", Err(PathParserError::MissingTableName))]
+    #[case("http://path-to-data", Err(PathParserError::InvalidScheme))]
     #[case("s3", Err(PathParserError::ParseError("relative URL without a base".to_string())))]
     #[case("", Err(PathParserError::ParseError("relative URL without a base".to_string())))]
     #[case("foo", Err(PathParserError::ParseError("relative URL without a base".to_string())))]
@@ -83,7 +143,7 @@ mod tests {
         #[case] input: &str,
         #[case] expected: Result<&str, PathParserError>,
     ) {
-        let result = ParseredTablePath::new(input);
+        let result = ParseredTablePath::parse(input, true);
 
         match (result, expected) {
             (Ok(parsed), Ok(expected_url)) => {
@@ -99,12 +159,17 @@ mod tests {
     }
 
     #[rstest]
-    #[case("'s3://bucket/path-to-data/'", Ok("path-to-data".to_string()))]
-    #[case("'s3://bucket/path-to-data'", Ok("path-to-data".to_string()))]
-    #[case("s3://bucket/path-to-data/", Ok("path-to-data".to_string()))]
-    #[case("s3://bucket/path-to-data", Ok("path-to-data".to_string()))]
-    #[case("s3://path-to-data/", Ok("path-to-data".to_string()))]
-    #[case("s3://path-to-data", Ok("path-to-data".to_string()))]
+    #[case("'s3://bucket/path-to-data/'", Ok("path_to_data".to_string()))]
+    #[case("'s3://bucket/path-to-data'", Ok("path_to_data".to_string()))]
+    #[case("s3://bucket/path-to-data/", Ok("path_to_data".to_string()))]
+    #[case("s3://bucket/path-to-data", Ok("path_to_data".to_string()))]
+    #[case("s3://path-to-data/", Ok("path_to_data".to_string()))]
+    #[case("s3://path-to-data", Ok("path_to_data".to_string()))]
+    #[case("file:///data/path-to-data/", Ok("path_to_data".to_string()))]
+    #[case("s3://bucket/images/dt=2021-01-01/", Ok("dt_2021_01_01".to_string()))]
+    #[case("s3://bucket/images/2021/", Ok("t_2021".to_string()))]
+    #[case("s3://bucket/Images.Parquet", Ok("images_parquet".to_string()))]
+    #[case("s3://bucket/---/", Ok("t".to_string()))]
     #[case("s3://", Err(PathParserError::MissingBucket))]
     #[case("s3:/", Err(PathParserError::MissingBucket))]
     #[case("s3:", Err(PathParserError::MissingBucket))]
@@ -115,11 +180,23 @@ mod tests {
         #[case] input: &str,
         #[case] expected: Result<String, PathParserError>,
     ) {
-        let validated = ParseredTablePath::new(input);
+        let validated = ParseredTablePath::parse(input, true);
         let result = match validated {
             Ok(valid) => valid.extract_table_name(),
             Err(e) => Err(e),
         };
         assert_eq!(result, expected);
     }
+
+    #[rstest]
+    #[case("file:///data/path-to-data/", Err(PathParserError::LocalPathNotAllowed))]
+    #[case("'file:///etc/'", Err(PathParserError::LocalPathNotAllowed))]
+    #[case("s3://bucket/path-to-data/", Ok("s3://bucket/path-to-data/"))]
+    fn production_rejects_local_paths_test(
+        #[case] input: &str,
+        #[case] expected: Result<&str, PathParserError>,
+    ) {
+        let result = ParseredTablePath::parse(input, false).map(|p| p.as_ref().to_string());
+        assert_eq!(result, expected.map(String::from));
+    }
 }
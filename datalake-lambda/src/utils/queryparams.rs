@@ -0,0 +1,94 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum QueryParamError {
+    #[error("Invalid date param ${0}: expected YYYY-MM-DD")]
+    InvalidDate(usize),
+
+    #[error("Invalid timestamp param ${0}: expected RFC 3339 or YYYY-MM-DD HH:MM:SS")]
+    InvalidTimestamp(usize),
+
+    #[error("Invalid float param ${0}: must be finite")]
+    InvalidFloat(usize),
+}
+
+/// Typed bind value for $1, $2, ... placeholders,
+/// serialized as {"type": "integer", "value": 42}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum QueryParam {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Date(String),
+    Timestamp(String),
+}
+
+impl QueryParam {
+    /// Check that value has a valid format, position is 1-based as in placeholder
+    pub fn validate(&self, position: usize) -> Result<(), QueryParamError> {
+        match self {
+            QueryParam::Date(v) => {
+                NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                    .map_err(|_| QueryParamError::InvalidDate(position))?;
+            }
+            QueryParam::Timestamp(v) => {
+                let is_valid = DateTime::parse_from_rfc3339(v).is_ok()
+                    || NaiveDateTime::parse_from_str(v, "%Y-%m-%d %H:%M:%S").is_ok()
+                    || NaiveDateTime::parse_from_str(v, "%Y-%m-%dT%H:%M:%S").is_ok();
+                if !is_valid {
+                    return Err(QueryParamError::InvalidTimestamp(position));
+                }
+            }
+            QueryParam::Float(v) if !v.is_finite() => {
+                return Err(QueryParamError::InvalidFloat(position));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(r#"{"type": "string", "value": "foo"}"#, QueryParam::String("foo".to_string()))]
+    #[case(r#"{"type": "integer", "value": 42}"#, QueryParam::Integer(42))]
+    #[case(r#"{"type": "float", "value": 4.2}"#, QueryParam::Float(4.2))]
+    #[case(r#"{"type": "bool", "value": true}"#, QueryParam::Bool(true))]
+    #[case(r#"{"type": "date", "value": "2021-01-01"}"#, QueryParam::Date("2021-01-01".to_string()))]
+    #[case(r#"{"type": "timestamp", "value": "2021-01-01 10:00:00"}"#, QueryParam::Timestamp("2021-01-01 10:00:00".to_string()))]
+    fn query_param_deserialize_test(#[case] input: &str, #[case] expected: QueryParam) {
+        let param: QueryParam = serde_json::from_str(input).unwrap();
+        assert_eq!(param, expected);
+    }
+
+    #[rstest]
+    #[case(r#"{"type": "integer", "value": "42"}"#)]
+    #[case(r#"{"type": "uuid", "value": "foo"}"#)]
+    #[case(r#"{"value": "foo"}"#)]
+    fn query_param_deserialize_err_test(#[case] input: &str) {
+        assert!(serde_json::from_str::<QueryParam>(input).is_err());
+    }
+
+    #[rstest]
+    #[case(QueryParam::Date("2021-01-01".to_string()), Ok(()))]
+    #[case(QueryParam::Date("2021-13-01".to_string()), Err(QueryParamError::InvalidDate(1)))]
+    #[case(QueryParam::Date("01.01.2021".to_string()), Err(QueryParamError::InvalidDate(1)))]
+    #[case(QueryParam::Timestamp("2021-01-01T10:00:00Z".to_string()), Ok(()))]
+    #[case(QueryParam::Timestamp("2021-01-01T10:00:00".to_string()), Ok(()))]
+    #[case(QueryParam::Timestamp("2021-01-01 10:00:00".to_string()), Ok(()))]
+    #[case(QueryParam::Timestamp("2021-01-01".to_string()), Err(QueryParamError::InvalidTimestamp(1)))]
+    #[case(QueryParam::Float(f64::NAN), Err(QueryParamError::InvalidFloat(1)))]
+    #[case(QueryParam::String("'; drop table foo; --".to_string()), Ok(()))]
+    fn query_param_validate_test(#[case] input: QueryParam, #[case] expected: Result<(), QueryParamError>) {
+        assert_eq!(input.validate(1), expected);
+    }
+}
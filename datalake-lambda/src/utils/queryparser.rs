@@ -1,10 +1,20 @@
-use sqlparser::ast::{Expr, LimitClause, SetExpr, Statement, TableFactor, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{
+    Expr, Ident, LimitClause, ObjectName, ObjectNamePart, Offset, OffsetRows, Query, SetExpr,
+    Statement, TableFactor, TableSampleKind, TableSampleMethod, TableSampleUnit, Value, Visit,
+    VisitMut, Visitor, VisitorMut, visit_expressions, visit_relations_mut,
+};
+use serde::Serialize;
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
 use sqlparser::parser::ParserError;
 use thiserror::Error;
 
-use crate::utils::constants::MAX_ROWS;
+use crate::utils::pathparser::{ParseredTablePath, PathParserError};
+use crate::utils::queryparams::{QueryParam, QueryParamError};
+use crate::utils::sqlpolicy::{SqlPolicy, SqlPolicyError};
 
 #[derive(Debug, Error, PartialEq)]
 pub enum QueryParserError {
@@ -14,21 +24,87 @@ pub enum QueryParserError {
     #[error("Invalid query: doesn't contain table name")]
     InvalidTableName,
 
+    #[error("Invalid table path")]
+    InvalidTablePath(#[from] PathParserError),
+
     #[error("Select query type not found")]
     SelectQueryNotFound,
 
     #[error("Unsupported query type")]
     UnsupportedQueryType,
+
+    #[error("Invalid placeholder: {0}, expected $1, $2, ...")]
+    InvalidPlaceholder(String),
+
+    #[error("Query params mismatch: query uses {placeholders} placeholders, got {params} params")]
+    ParamsMismatch { placeholders: usize, params: usize },
+
+    #[error("Query param ${0} is not used in the query")]
+    UnusedParam(usize),
+
+    #[error("Invalid query param")]
+    InvalidParam(#[from] QueryParamError),
+
+    #[error("Invalid limit: {0}, expected a non-negative number")]
+    InvalidLimit(String),
+
+    #[error("Unsupported limit clause: {0}")]
+    UnsupportedLimit(String),
+
+    #[error("Query rejected by policy: {0}")]
+    PolicyViolation(#[from] SqlPolicyError),
+
+    #[error("Unsupported table sample: {0}")]
+    UnsupportedSample(String),
+}
+
+/// Size of a TABLESAMPLE clause as fusion reads it, {"percent": 10.0} or {"rows": 1000}
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleSize {
+    Percent(f64),
+    Rows(u64),
+}
+
+/// TABLESAMPLE (n PERCENT | n ROWS) [REPEATABLE (seed)], fusion samples files and row groups
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct TableSample {
+    #[serde(flatten)]
+    pub size: SampleSize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct TableRef {
+    pub name: String, // table name used in the query
+    pub path: String, // table url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<TableSample>,
+}
+
+/// Limit the query is executed with
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct AppliedLimit {
+    pub row_limit: u64, // max rows in the result
+    pub clamped: bool,  // user limit was larger than allowed
+    pub probe: bool,    // query fetches one extra row, so fusion can tell whether the result was cut
 }
 
 #[derive(Debug, PartialEq)]
 pub struct QueryParsered {
     pub query: String,
-    pub table_name: String,
+    pub tables: Vec<TableRef>,
+    pub limit: AppliedLimit,
 }
 
 /// validate the query
-pub fn prepare_query(query: &str) -> Result<QueryParsered, QueryParserError> {
+pub fn prepare_query(
+    query: &str,
+    params: &[QueryParam],
+    policy: &SqlPolicy,
+    row_limit: u64,
+) -> Result<QueryParsered, QueryParserError> {
     let dialect = GenericDialect {};
     let mut ast = Parser::parse_sql(&dialect, query)?;
     let query_stmt = ast
@@ -37,76 +113,304 @@ pub fn prepare_query(query: &str) -> Result<QueryParsered, QueryParserError> {
     let Statement::Query(_query) = query_stmt else {
         return Err(QueryParserError::UnsupportedQueryType);
     };
-    let res = prepare_query_worker(&mut ast)?;
+    policy.validate(&ast)?;
+    validate_params(&ast, params)?;
+    let res = prepare_query_worker(&mut ast, row_limit)?;
     Ok(res)
 }
 
-/// validate the query,
-/// prepare the query by adding limit if not exists,
-/// checkening table name
-fn prepare_query_worker(ast: &mut [Statement]) -> Result<QueryParsered, QueryParserError> {
-    if let Some(Statement::Query(query)) = ast.get_mut(0) {
-        let table_name = match &*query.body {
-            SetExpr::Select(select) => {
-                if let Some(from_table) = select.from.first() {
-                    match &from_table.relation {
-                        TableFactor::Table { name, .. } => Some(name.to_string()),
-                        _ => None,
-                    }
-                } else {
-                    None
+/// check that placeholders are $1..$n and every param is bound to one of them,
+/// values are bound by fusion, never spliced into the query text
+fn validate_params(ast: &Vec<Statement>, params: &[QueryParam]) -> Result<(), QueryParserError> {
+    let mut positions = BTreeSet::new();
+    let res = visit_expressions(ast, |expr| {
+        if let Expr::Value(v) = expr
+            && let Value::Placeholder(placeholder) = &v.value
+        {
+            match placeholder
+                .strip_prefix('$')
+                .and_then(|n| n.parse::<usize>().ok())
+                .filter(|n| *n > 0)
+            {
+                Some(n) => {
+                    positions.insert(n);
                 }
+                None => return ControlFlow::Break(placeholder.clone()),
             }
-            _ => None,
+        }
+        ControlFlow::Continue(())
+    });
+    if let ControlFlow::Break(placeholder) = res {
+        return Err(QueryParserError::InvalidPlaceholder(placeholder));
+    }
+
+    let max_position = positions.last().copied().unwrap_or_default();
+    if max_position > params.len() {
+        return Err(QueryParserError::ParamsMismatch {
+            placeholders: max_position,
+            params: params.len(),
+        });
+    }
+    if let Some(unused) = (1..=params.len()).find(|n| !positions.contains(n)) {
+        return Err(QueryParserError::UnusedParam(unused));
+    }
+
+    for (i, param) in params.iter().enumerate() {
+        param.validate(i + 1)?;
+    }
+
+    Ok(())
+}
+
+/// validate the query,
+/// prepare the query by adding or clamping limit,
+/// replacing table paths with table names
+fn prepare_query_worker(
+    ast: &mut Vec<Statement>,
+    row_limit: u64,
+) -> Result<QueryParsered, QueryParserError> {
+    let Some(Statement::Query(query)) = ast.get_mut(0) else {
+        return Err(QueryParserError::UnsupportedQueryType);
+    };
+    let SetExpr::Select(_select) = &*query.body else {
+        return Err(QueryParserError::SelectQueryNotFound);
+    };
+
+    let limit = apply_row_limit(query, row_limit)?;
+
+    let samples = take_table_samples(ast)?;
+    let mut tables = replace_table_paths(ast)?;
+    if tables.is_empty() {
+        return Err(QueryParserError::InvalidTableName);
+    }
+    for table in &mut tables {
+        table.sample = samples.get(&table.path).copied().flatten();
+    }
+
+    Ok(QueryParsered {
+        query: ast[0].to_string(),
+        tables,
+        limit,
+    })
+}
+
+/// Rewrite top level LIMIT / OFFSET / FETCH FIRST into LIMIT n OFFSET m with n <= row_limit.
+/// When the policy imposes the limit, one extra row is requested so fusion can report truncation
+fn apply_row_limit(query: &mut Query, row_limit: u64) -> Result<AppliedLimit, QueryParserError> {
+    let (user_limit, offset) = match query.limit_clause.take() {
+        None => (None, None),
+        Some(LimitClause::LimitOffset { limit_by, .. }) if !limit_by.is_empty() => {
+            return Err(QueryParserError::UnsupportedLimit("LIMIT BY".to_string()));
+        }
+        Some(LimitClause::LimitOffset { limit, offset, .. }) => {
+            (limit.as_ref().map(limit_value).transpose()?, offset)
+        }
+        Some(LimitClause::OffsetCommaLimit { offset, limit }) => {
+            let offset = Offset { value: offset, rows: OffsetRows::None };
+            (Some(limit_value(&limit)?), Some(offset))
+        }
+    };
+    // DataFusion doesn't plan FETCH, it is turned into LIMIT
+    let user_limit = match query.fetch.take() {
+        None => user_limit,
+        Some(_) if user_limit.is_some() => {
+            return Err(QueryParserError::UnsupportedLimit("both LIMIT and FETCH".to_string()));
+        }
+        Some(fetch) if fetch.percent || fetch.with_ties => {
+            return Err(QueryParserError::UnsupportedLimit(fetch.to_string()));
+        }
+        Some(fetch) => Some(fetch.quantity.as_ref().map(limit_value).transpose()?.unwrap_or(1)),
+    };
+
+    let applied = match user_limit {
+        Some(limit) if limit <= row_limit => AppliedLimit { row_limit: limit, clamped: false, probe: false },
+        Some(_) => AppliedLimit { row_limit, clamped: true, probe: true },
+        None => AppliedLimit { row_limit, clamped: false, probe: true },
+    };
+    let fetch = if applied.probe { applied.row_limit + 1 } else { applied.row_limit };
+    query.limit_clause = Some(LimitClause::LimitOffset {
+        limit: Some(Expr::Value(Value::Number(fetch.to_string(), false).into())),
+        offset,
+        limit_by: vec![],
+    });
+    Ok(applied)
+}
+
+fn limit_value(expr: &Expr) -> Result<u64, QueryParserError> {
+    match expr {
+        Expr::Value(v) => match &v.value {
+            Value::Number(n, _) => n.parse().map_err(|_| QueryParserError::InvalidLimit(n.clone())),
+            other => Err(QueryParserError::InvalidLimit(other.to_string())),
+        },
+        other => Err(QueryParserError::InvalidLimit(other.to_string())),
+    }
+}
+
+/// replace quoted table paths ('s3://bucket/data/') in every FROM and JOIN
+/// with generated table names, the same path always gets the same name,
+/// other relations must reference CTEs
+fn replace_table_paths(ast: &mut Vec<Statement>) -> Result<Vec<TableRef>, QueryParserError> {
+    let cte_names = collect_cte_names(ast);
+    let mut taken = cte_names.clone();
+    let mut tables: Vec<TableRef> = vec![];
+
+    let res = visit_relations_mut(ast, |relation| {
+        let [ObjectNamePart::Identifier(ident)] = relation.0.as_slice() else {
+            return ControlFlow::Break(QueryParserError::InvalidTableName);
         };
+        if ident.quote_style.is_none() || !ident.value.contains("://") {
+            if cte_names.contains(&ident.value.to_lowercase()) {
+                return ControlFlow::Continue(());
+            }
+            return ControlFlow::Break(QueryParserError::InvalidTableName);
+        }
 
-        let table_name = table_name.ok_or(QueryParserError::InvalidTableName)?;
-
-        if let SetExpr::Select(_select) = &mut *query.body {
-            // query contains limit
-            if query.limit_clause.is_none() {
-                query.limit_clause = Some(LimitClause::LimitOffset {
-                    limit: Some(Expr::Value(
-                        Value::Number(MAX_ROWS.to_string(), false).into(),
-                    )),
-                    offset: None,
-                    limit_by: vec![],
-                })
-            };
+        let table_path = match ParseredTablePath::new(&ident.value) {
+            Ok(v) => v,
+            Err(e) => return ControlFlow::Break(e.into()),
+        };
+        let path = table_path.as_ref().to_string();
+        let name = match tables.iter().find(|t| t.path == path) {
+            Some(table) => table.name.clone(),
+            None => {
+                let base = match table_path.extract_table_name() {
+                    Ok(v) => v,
+                    Err(e) => return ControlFlow::Break(e.into()),
+                };
+                let name = unique_name(&base, &taken);
+                taken.insert(name.clone());
+                tables.push(TableRef { name: name.clone(), path, sample: None });
+                name
+            }
+        };
+        *relation = ObjectName::from(vec![Ident::with_quote('"', name)]);
+        ControlFlow::Continue(())
+    });
 
-            Ok(QueryParsered {
-                query: ast[0].to_string(),
-                table_name,
-            })
-        } else {
-            Err(QueryParserError::SelectQueryNotFound)
+    match res {
+        ControlFlow::Break(e) => Err(e),
+        ControlFlow::Continue(()) => Ok(tables),
+    }
+}
+
+/// remove TABLESAMPLE clauses, DataFusion doesn't plan them, fusion samples the table instead.
+/// A path is one table, so all its references must have the same sample
+fn take_table_samples(
+    ast: &mut Vec<Statement>,
+) -> Result<HashMap<String, Option<TableSample>>, QueryParserError> {
+    struct SampleCollector(HashMap<String, Option<TableSample>>);
+
+    impl VisitorMut for SampleCollector {
+        type Break = QueryParserError;
+
+        fn pre_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<Self::Break> {
+            let TableFactor::Table { name, sample, .. } = factor else {
+                return ControlFlow::Continue(());
+            };
+            let sample = match sample.take() {
+                None => None,
+                Some(TableSampleKind::BeforeTableAlias(s) | TableSampleKind::AfterTableAlias(s)) => {
+                    match table_sample(&s) {
+                        Ok(sample) => Some(sample),
+                        Err(e) => return ControlFlow::Break(e),
+                    }
+                }
+            };
+            // invalid table names are reported when paths are replaced
+            let [ObjectNamePart::Identifier(ident)] = name.0.as_slice() else {
+                return ControlFlow::Continue(());
+            };
+            let Ok(path) = ParseredTablePath::new(&ident.value) else {
+                return match sample {
+                    Some(_) => ControlFlow::Break(QueryParserError::UnsupportedSample(format!(
+                        "{ident} is not a table path"
+                    ))),
+                    None => ControlFlow::Continue(()),
+                };
+            };
+            match self.0.insert(path.as_ref().to_string(), sample) {
+                Some(other) if other != sample => ControlFlow::Break(
+                    QueryParserError::UnsupportedSample(format!("{ident} is sampled differently")),
+                ),
+                _ => ControlFlow::Continue(()),
+            }
         }
-    } else {
-        Err(QueryParserError::UnsupportedQueryType)
     }
+
+    let mut collector = SampleCollector(HashMap::new());
+    match ast.visit(&mut collector) {
+        ControlFlow::Break(e) => Err(e),
+        ControlFlow::Continue(()) => Ok(collector.0),
+    }
+}
+
+fn table_sample(sample: &sqlparser::ast::TableSample) -> Result<TableSample, QueryParserError> {
+    let unsupported = || QueryParserError::UnsupportedSample(sample.to_string().trim().to_string());
+    let number = |expr: &Expr| match expr {
+        Expr::Value(v) => match &v.value {
+            Value::Number(n, _) => Some(n.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    // fusion samples whole row groups, as SYSTEM and BLOCK do, not single rows
+    let is_row_method = matches!(sample.name, Some(TableSampleMethod::Bernoulli | TableSampleMethod::Row));
+    if is_row_method || sample.bucket.is_some() || sample.offset.is_some() {
+        return Err(unsupported());
+    }
+    let quantity = sample.quantity.as_ref().ok_or_else(unsupported)?;
+    let value = number(&quantity.value).ok_or_else(unsupported)?;
+    let size = match quantity.unit {
+        Some(TableSampleUnit::Rows) => {
+            let rows = value.parse::<u64>().ok().filter(|rows| *rows > 0);
+            SampleSize::Rows(rows.ok_or_else(unsupported)?)
+        }
+        // a quantity without unit is a percentage, as in PostgreSQL
+        Some(TableSampleUnit::Percent) | None => {
+            let percent = value.parse::<f64>().ok().filter(|p| *p > 0.0 && *p <= 100.0);
+            SampleSize::Percent(percent.ok_or_else(unsupported)?)
+        }
+    };
+    let seed = match &sample.seed {
+        None => None,
+        Some(seed) => match &seed.value {
+            Value::Number(n, _) => Some(n.parse::<u64>().map_err(|_| unsupported())?),
+            _ => return Err(unsupported()),
+        },
+    };
+    Ok(TableSample { size, seed })
 }
 
-pub fn replace_table_name(query: &str, table_name: &str) -> String {
-    if let Some(start) = query.find("s3://") {
-        let before = &query[..start];
-        let quote_char = before.chars().rev().find(|&c| c == '\'' || c == '"');
-        let quote_pos = quote_char
-            .and_then(|q| before.rfind(q))
-            .unwrap_or(0);
+fn collect_cte_names(ast: &Vec<Statement>) -> HashSet<String> {
+    struct CteCollector(HashSet<String>);
+
+    impl Visitor for CteCollector {
+        type Break = ();
 
-        let prefix = &query[..quote_pos];
-        let rest = &query[start..];
-        let end_quote_idx = rest
-            .find(['\'', '"'])
-            .map(|idx| start + idx + 1)
-            .unwrap_or(query.len());
+        fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+            if let Some(with) = &query.with {
+                for cte in &with.cte_tables {
+                    self.0.insert(cte.alias.name.value.to_lowercase());
+                }
+            }
+            ControlFlow::Continue(())
+        }
+    }
 
-        let suffix = &query[end_quote_idx..];
+    let mut collector = CteCollector(HashSet::new());
+    let _ = ast.visit(&mut collector);
+    collector.0
+}
 
-        format!("{prefix}{table_name}{suffix}")
-    } else {
-        query.to_string()
+/// name not used by ctes and other tables: images, images_2, images_3, ...
+fn unique_name(base: &str, taken: &HashSet<String>) -> String {
+    if !taken.contains(base) {
+        return base.to_string();
     }
+    (2..)
+        .map(|n| format!("{base}_{n}"))
+        .find(|name| !taken.contains(name))
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
@@ -115,11 +419,20 @@ mod tests {
 
     use super::*;
 
+    fn table(name: &str, path: &str) -> TableRef {
+        TableRef { name: name.to_string(), path: path.to_string(), sample: None }
+    }
+
+    fn sampled(name: &str, path: &str, size: SampleSize, seed: Option<u64>) -> TableRef {
+        TableRef { sample: Some(TableSample { size, seed }), ..table(name, path) }
+    }
+
     #[rstest]
-    #[case("select * from foo", Ok(QueryParsered{ query: "SELECT * FROM foo LIMIT 1000".to_string(), table_name: "foo".to_string() }))]
-    #[case("select * from 's3://bucket/path-to-data/'", Ok(QueryParsered{ query: "SELECT * FROM 's3://bucket/path-to-data/' LIMIT 1000".to_string(), table_name: "'s3://bucket/path-to-data/'".to_string() }))]
-    #[case("select * from 's3://path-to-data'", Ok(QueryParsered{ query: "SELECT * FROM 's3://path-to-data' LIMIT 1000".to_string(), table_name: "'s3://path-to-data'".to_string() }))]
-    #[case("select * from foo limit 10", Ok(QueryParsered{ query: "SELECT * FROM foo LIMIT 10".to_string(), table_name: "foo".to_string() }))]
+    #[case("select * from 's3://bucket/path-to-data/'", Ok(QueryParsered{ query: "SELECT * FROM \"path_to_data\" LIMIT 1001".to_string(), tables: vec![table("path_to_data", "s3://bucket/path-to-data/")], limit: AppliedLimit { row_limit: 1000, clamped: false, probe: true } }))]
+    #[case("select * from 's3://path-to-data'", Ok(QueryParsered{ query: "SELECT * FROM \"path_to_data\" LIMIT 1001".to_string(), tables: vec![table("path_to_data", "s3://path-to-data")], limit: AppliedLimit { row_limit: 1000, clamped: false, probe: true } }))]
+    #[case("select * from 's3://bucket/images/' limit 10", Ok(QueryParsered{ query: "SELECT * FROM \"images\" LIMIT 10".to_string(), tables: vec![table("images", "s3://bucket/images/")], limit: AppliedLimit { row_limit: 10, clamped: false, probe: false } }))]
+    #[case("select 1", Err(QueryParserError::InvalidTableName))]
+    #[case("select * from 'http://bucket/images/'", Err(QueryParserError::InvalidTablePath(PathParserError::InvalidScheme)))]
     #[case("select * from", Err(QueryParserError::SqlParseError(ParserError::ParserError("Expected: identifier, found: EOF".to_string()))))]
     #[case("delete from foo", Err(QueryParserError::UnsupportedQueryType))]
     #[case(
@@ -130,22 +443,169 @@ mod tests {
         "insert into foo(file_name) values('foo')",
         Err(QueryParserError::UnsupportedQueryType)
     )]
+    #[case("select * from 's3://bucket/images/'; drop table foo", Err(QueryParserError::PolicyViolation(SqlPolicyError::MultipleStatements(2))))]
+    #[case("select * into foo from 's3://bucket/images/'", Err(QueryParserError::PolicyViolation(SqlPolicyError::ReadOnlyViolation("SELECT INTO".to_string()))))]
     #[case("foo bar baz", Err(QueryParserError::SqlParseError(ParserError::ParserError("Expected: an SQL statement, found: foo at Line: 1, Column: 1".to_string()))))]
     fn prepare_query_test(
         #[case] input: &str,
         #[case] expected: Result<QueryParsered, QueryParserError>,
     ) {
-        assert_eq!(expected, prepare_query(input));
+        assert_eq!(expected, prepare_query(input, &[], &SqlPolicy::default(), 1000));
     }
 
+    /// bare names used to pass the parser and only failed path validation of the table name
+    #[rstest]
+    #[case("select * from foo")]
+    #[case("select * from foo limit 10")]
+    #[case("select * from foo join 's3://bucket/images/' using (id)")]
+    fn bare_table_name_test(#[case] input: &str) {
+        let res = prepare_query(input, &[], &SqlPolicy::default(), 1000);
+        assert_eq!(res, Err(QueryParserError::InvalidTableName));
+    }
 
     #[rstest]
-    #[case(("select * from 's3://bucket/path/images/'", "images"), "select * from images")]
-    #[case(("select * from foo", "images"), "select * from foo")]
-    fn replace_table_path_test(
-        #[case] input: (&str, &str),
-        #[case] expected: &str,
+    #[case("select * from 's3://bucket/foo/' where or_id = $1 and dt > $2", vec![QueryParam::String("foo".to_string()), QueryParam::Date("2021-01-01".to_string())], Ok(()))]
+    #[case("select * from 's3://bucket/foo/' where a = $1 or b = $1", vec![QueryParam::Integer(1)], Ok(()))]
+    #[case("select * from 's3://bucket/foo/' where a = '$1'", vec![], Ok(()))]
+    #[case("select * from 's3://bucket/foo/' where a = $1", vec![], Err(QueryParserError::ParamsMismatch { placeholders: 1, params: 0 }))]
+    #[case("select * from 's3://bucket/foo/'", vec![QueryParam::Integer(1)], Err(QueryParserError::UnusedParam(1)))]
+    #[case("select * from 's3://bucket/foo/' where a = $2", vec![QueryParam::Integer(1)], Err(QueryParserError::ParamsMismatch { placeholders: 2, params: 1 }))]
+    #[case("select * from 's3://bucket/foo/' where a = $1 and b = $3", vec![QueryParam::Integer(1), QueryParam::Integer(2), QueryParam::Integer(3)], Err(QueryParserError::UnusedParam(2)))]
+    #[case("select * from 's3://bucket/foo/' where a = $0", vec![], Err(QueryParserError::InvalidPlaceholder("$0".to_string())))]
+    #[case("select * from 's3://bucket/foo/' where a = $name", vec![], Err(QueryParserError::InvalidPlaceholder("$name".to_string())))]
+    #[case("select * from 's3://bucket/foo/' where a = ?", vec![QueryParam::Integer(1)], Err(QueryParserError::InvalidPlaceholder("?".to_string())))]
+    #[case("select * from 's3://bucket/foo/' where dt > $1", vec![QueryParam::Date("foo".to_string())], Err(QueryParserError::InvalidParam(QueryParamError::InvalidDate(1))))]
+    fn prepare_query_params_test(
+        #[case] input: &str,
+        #[case] params: Vec<QueryParam>,
+        #[case] expected: Result<(), QueryParserError>,
     ) {
-        assert_eq!(expected, replace_table_name(input.0, input.1));
+        assert_eq!(expected, prepare_query(input, &params, &SqlPolicy::default(), 1000).map(|_| ()));
+    }
+
+    #[rstest]
+    #[case(
+        "select * from 's3://bucket/path/images/'",
+        "SELECT * FROM \"images\" LIMIT 1001",
+        vec![table("images", "s3://bucket/path/images/")],
+    )]
+    #[case(
+        "select * from 's3://bucket/images/' where key = 's3://bucket/images/foo.png'",
+        "SELECT * FROM \"images\" WHERE key = 's3://bucket/images/foo.png' LIMIT 1001",
+        vec![table("images", "s3://bucket/images/")],
+    )]
+    #[case(
+        "select a.id from 's3://bucket/orders/' a join 's3://bucket/dt=2021-01-01/' b on a.id = b.id",
+        "SELECT a.id FROM \"orders\" AS a JOIN \"dt_2021_01_01\" AS b ON a.id = b.id LIMIT 1001",
+        vec![table("orders", "s3://bucket/orders/"), table("dt_2021_01_01", "s3://bucket/dt=2021-01-01/")],
+    )]
+    #[case(
+        "select * from 's3://bucket/images/' where id in (select id from 's3://bucket/images/')",
+        "SELECT * FROM \"images\" WHERE id IN (SELECT id FROM \"images\") LIMIT 1001",
+        vec![table("images", "s3://bucket/images/")],
+    )]
+    #[case(
+        "select * from 's3://foo/images/' join 's3://bar/images/' using (id)",
+        "SELECT * FROM \"images\" JOIN \"images_2\" USING(id) LIMIT 1001",
+        vec![table("images", "s3://foo/images/"), table("images_2", "s3://bar/images/")],
+    )]
+    #[case(
+        "with images as (select * from 's3://bucket/images/') select * from images",
+        "WITH images AS (SELECT * FROM \"images_2\") SELECT * FROM images LIMIT 1001",
+        vec![table("images_2", "s3://bucket/images/")],
+    )]
+    #[case(
+        "select * from 's3://bucket/it''s \"data\"/'",
+        "SELECT * FROM \"it_s_data\" LIMIT 1001",
+        vec![table("it_s_data", "s3://bucket/it's%20%22data%22/")],
+    )]
+    #[case(
+        "select * from \"s3://bucket/2021/\"",
+        "SELECT * FROM \"t_2021\" LIMIT 1001",
+        vec![table("t_2021", "s3://bucket/2021/")],
+    )]
+    fn replace_table_paths_test(
+        #[case] input: &str,
+        #[case] expected_query: &str,
+        #[case] expected_tables: Vec<TableRef>,
+    ) {
+        let res = prepare_query(input, &[], &SqlPolicy::default(), 1000).unwrap();
+        assert_eq!(res.query, expected_query);
+        assert_eq!(res.tables, expected_tables);
+    }
+
+    #[rstest]
+    #[case(
+        "select * from 's3://bucket/images/' tablesample (10 percent)",
+        "SELECT * FROM \"images\" LIMIT 1001",
+        vec![sampled("images", "s3://bucket/images/", SampleSize::Percent(10.0), None)],
+    )]
+    #[case(
+        "select * from 's3://bucket/images/' as i tablesample system (500 rows) repeatable (42)",
+        "SELECT * FROM \"images\" AS i LIMIT 1001",
+        vec![sampled("images", "s3://bucket/images/", SampleSize::Rows(500), Some(42))],
+    )]
+    #[case(
+        "select * from 's3://bucket/images/' tablesample (0.5) join 's3://bucket/orders/' using (id)",
+        "SELECT * FROM \"images\" JOIN \"orders\" USING(id) LIMIT 1001",
+        vec![sampled("images", "s3://bucket/images/", SampleSize::Percent(0.5), None), table("orders", "s3://bucket/orders/")],
+    )]
+    #[case(
+        "with s as (select * from 's3://bucket/images/' tablesample (1 percent)) select * from s join s as b using (id)",
+        "WITH s AS (SELECT * FROM \"images\") SELECT * FROM s JOIN s AS b USING(id) LIMIT 1001",
+        vec![sampled("images", "s3://bucket/images/", SampleSize::Percent(1.0), None)],
+    )]
+    fn table_sample_test(
+        #[case] input: &str,
+        #[case] expected_query: &str,
+        #[case] expected_tables: Vec<TableRef>,
+    ) {
+        let res = prepare_query(input, &[], &SqlPolicy::default(), 1000).unwrap();
+        assert_eq!(res.query, expected_query);
+        assert_eq!(res.tables, expected_tables);
+    }
+
+    #[rstest]
+    #[case("select * from 's3://bucket/images/' tablesample bernoulli (10 percent)", "TABLESAMPLE BERNOULLI (10 PERCENT)")]
+    #[case("select * from 's3://bucket/images/' tablesample (0 percent)", "TABLESAMPLE (0 PERCENT)")]
+    #[case("select * from 's3://bucket/images/' tablesample (150)", "TABLESAMPLE (150)")]
+    #[case("select * from 's3://bucket/images/' tablesample (1.5 rows)", "TABLESAMPLE (1.5 ROWS)")]
+    #[case("select * from 's3://bucket/images/' tablesample ($1 rows)", "TABLESAMPLE ($1 ROWS)")]
+    #[case("select * from 's3://bucket/images/' tablesample (10 percent) repeatable (1.5)", "TABLESAMPLE (10 PERCENT) REPEATABLE (1.5)")]
+    #[case("with s as (select 1 as id) select * from s tablesample (10 percent)", "s is not a table path")]
+    #[case("select * from 's3://bucket/images/' tablesample (10 percent) join 's3://bucket/images/' b using (id)", "'s3://bucket/images/' is sampled differently")]
+    fn table_sample_err_test(#[case] input: &str, #[case] expected: &str) {
+        let params = if input.contains("$1") { vec![QueryParam::Integer(1)] } else { vec![] };
+        assert_eq!(
+            prepare_query(input, &params, &SqlPolicy::default(), 1000),
+            Err(QueryParserError::UnsupportedSample(expected.to_string()))
+        );
+    }
+
+    #[rstest]
+    #[case("select * from 's3://bucket/images/'", "SELECT * FROM \"images\" LIMIT 101", AppliedLimit { row_limit: 100, clamped: false, probe: true })]
+    #[case("select * from 's3://bucket/images/' limit 10", "SELECT * FROM \"images\" LIMIT 10", AppliedLimit { row_limit: 10, clamped: false, probe: false })]
+    #[case("select * from 's3://bucket/images/' limit 100000000", "SELECT * FROM \"images\" LIMIT 101", AppliedLimit { row_limit: 100, clamped: true, probe: true })]
+    #[case("select * from 's3://bucket/images/' offset 10", "SELECT * FROM \"images\" LIMIT 101 OFFSET 10", AppliedLimit { row_limit: 100, clamped: false, probe: true })]
+    #[case("select * from 's3://bucket/images/' limit 1000 offset 10", "SELECT * FROM \"images\" LIMIT 101 OFFSET 10", AppliedLimit { row_limit: 100, clamped: true, probe: true })]
+    #[case("select * from 's3://bucket/images/' limit 10, 1000", "SELECT * FROM \"images\" LIMIT 101 OFFSET 10", AppliedLimit { row_limit: 100, clamped: true, probe: true })]
+    #[case("select * from 's3://bucket/images/' fetch first 50 rows only", "SELECT * FROM \"images\" LIMIT 50", AppliedLimit { row_limit: 50, clamped: false, probe: false })]
+    #[case("select * from 's3://bucket/images/' offset 5 rows fetch next 500 rows only", "SELECT * FROM \"images\" LIMIT 101 OFFSET 5 ROWS", AppliedLimit { row_limit: 100, clamped: true, probe: true })]
+    #[case("select * from 's3://bucket/images/' fetch first row only", "SELECT * FROM \"images\" LIMIT 1", AppliedLimit { row_limit: 1, clamped: false, probe: false })]
+    fn apply_row_limit_test(#[case] input: &str, #[case] expected_query: &str, #[case] expected_limit: AppliedLimit) {
+        let res = prepare_query(input, &[], &SqlPolicy::default(), 100).unwrap();
+        assert_eq!(res.query, expected_query);
+        assert_eq!(res.limit, expected_limit);
+    }
+
+    #[rstest]
+    #[case("select * from 's3://bucket/images/' limit $1", QueryParserError::InvalidLimit("$1".to_string()))]
+    #[case("select * from 's3://bucket/images/' limit 1 + 1", QueryParserError::InvalidLimit("1 + 1".to_string()))]
+    #[case("select * from 's3://bucket/images/' limit -1", QueryParserError::InvalidLimit("-1".to_string()))]
+    #[case("select * from 's3://bucket/images/' fetch first 10 percent rows only", QueryParserError::UnsupportedLimit("FETCH FIRST 10 PERCENT ROWS ONLY".to_string()))]
+    #[case("select * from 's3://bucket/images/' order by id fetch first 10 rows with ties", QueryParserError::UnsupportedLimit("FETCH FIRST 10 ROWS WITH TIES".to_string()))]
+    fn apply_row_limit_err_test(#[case] input: &str, #[case] expected: QueryParserError) {
+        let params = if input.contains("$1") { vec![QueryParam::Integer(1)] } else { vec![] };
+        assert_eq!(prepare_query(input, &params, &SqlPolicy::default(), 100), Err(expected));
     }
 }
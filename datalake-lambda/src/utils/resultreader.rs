@@ -0,0 +1,310 @@
+use arrow::array::RecordBatch;
+use arrow::compute::{SortOptions, concat_batches, sort_to_indices, take_record_batch};
+use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
+use arrow::json::ArrayWriter;
+use futures::TryStreamExt;
+use parquet::arrow::ParquetRecordBatchStreamBuilder;
+use parquet::arrow::ProjectionMask;
+use parquet::arrow::arrow_reader::{ArrowReaderOptions, RowSelection, RowSelector};
+use parquet::arrow::async_reader::AsyncFileReader;
+use parquet::errors::ParquetError;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ResultReaderError {
+    #[error("Parquet error")]
+    ParquetError(#[from] ParquetError),
+
+    #[error("Arrow error")]
+    ArrowError(#[from] ArrowError),
+
+    #[error("Serde error")]
+    SerdeError(#[from] serde_json::Error),
+
+    #[error("Unknown column: {0}")]
+    UnknownColumn(String),
+
+    #[error("Result has {rows} rows, sorting is limited to {max}")]
+    SortTooLarge { rows: u64, max: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortSpec {
+    pub column: String,
+    pub descending: bool,
+}
+
+impl SortSpec {
+    /// "name" sorts ascending, "-name" descending
+    pub fn parse(value: &str) -> Self {
+        match value.strip_prefix('-') {
+            Some(column) => Self { column: column.to_string(), descending: true },
+            None => Self { column: value.to_string(), descending: false },
+        }
+    }
+}
+
+/// Page of result rows, columns None means all columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowsRequest {
+    pub offset: u64,
+    pub limit: u64,
+    pub sort: Option<SortSpec>,
+    pub columns: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RowsPage {
+    pub total_rows: u64,
+    pub schema: Vec<ColumnInfo>,
+    pub rows: Vec<Map<String, Value>>,
+}
+
+/// Read a page of rows from the parquet result.
+/// Unsorted pages read only row groups and pages (with page index) overlapping the page,
+/// sorted pages read the projected columns of the whole file, only for results up to max_sort_rows
+pub async fn read_rows(
+    reader: Box<dyn AsyncFileReader>,
+    request: &RowsRequest,
+    max_sort_rows: u64,
+) -> Result<RowsPage, ResultReaderError> {
+    let options = ArrowReaderOptions::new().with_page_index(true);
+    let builder = ParquetRecordBatchStreamBuilder::new_with_options(reader, options).await?;
+    let total_rows = builder.metadata().file_metadata().num_rows().max(0) as u64;
+    if request.sort.is_some() && total_rows > max_sort_rows {
+        return Err(ResultReaderError::SortTooLarge { rows: total_rows, max: max_sort_rows });
+    }
+    let schema = builder.schema().clone();
+
+    let output = projection(&schema, request.columns.as_deref())?;
+    let sort = match &request.sort {
+        Some(sort) => Some((column_index(&schema, &sort.column)?, sort.descending)),
+        None => None,
+    };
+    // sort column is read even if not requested, and dropped afterwards
+    let mut read = output.clone();
+    if let Some((index, _)) = sort
+        && !read.contains(&index)
+    {
+        read.push(index);
+    }
+    read.sort_unstable();
+    let mask = ProjectionMask::roots(builder.parquet_schema(), read.iter().copied());
+    let builder = builder.with_projection(mask);
+
+    let batch = match sort {
+        None => {
+            let row_counts: Vec<u64> = builder
+                .metadata()
+                .row_groups()
+                .iter()
+                .map(|rg| rg.num_rows().max(0) as u64)
+                .collect();
+            let (row_groups, selection) = page_selection(&row_counts, request.offset, request.limit);
+            if row_groups.is_empty() {
+                RecordBatch::new_empty(schema.project(&read)?.into())
+            } else {
+                let stream = builder
+                    .with_row_groups(row_groups)
+                    .with_row_selection(selection)
+                    .build()?;
+                let batches: Vec<RecordBatch> = stream.try_collect().await?;
+                concat_batches(&schema.project(&read)?.into(), &batches)?
+            }
+        }
+        Some((index, descending)) => {
+            let stream = builder.build()?;
+            let batches: Vec<RecordBatch> = stream.try_collect().await?;
+            let batch = concat_batches(&schema.project(&read)?.into(), &batches)?;
+            let sort_column = read.iter().position(|i| *i == index).unwrap_or_default();
+            let options = SortOptions { descending, nulls_first: descending };
+            let fetch = request.offset.saturating_add(request.limit) as usize;
+            let indices = sort_to_indices(batch.column(sort_column), Some(options), Some(fetch))?;
+            let start = (request.offset as usize).min(indices.len());
+            let indices = indices.slice(start, indices.len() - start);
+            take_record_batch(&batch, &indices)?
+        }
+    };
+
+    // keep requested columns in requested order
+    let positions: Vec<usize> = output
+        .iter()
+        .map(|i| read.iter().position(|r| r == i).unwrap_or_default())
+        .collect();
+    let batch = batch.project(&positions)?;
+
+    Ok(RowsPage {
+        total_rows,
+        schema: batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| ColumnInfo {
+                name: field.name().clone(),
+                data_type: field.data_type().to_string(),
+                nullable: field.is_nullable(),
+            })
+            .collect(),
+        rows: to_json_rows(&batch)?,
+    })
+}
+
+/// Row groups overlapping [offset, offset + limit) and rows to select within them
+fn page_selection(row_counts: &[u64], offset: u64, limit: u64) -> (Vec<usize>, RowSelection) {
+    let end = offset.saturating_add(limit);
+    let mut row_groups = vec![];
+    let mut selectors = vec![];
+    let mut start = 0;
+    for (i, count) in row_counts.iter().enumerate() {
+        let (rg_start, rg_end) = (start, start + count);
+        start = rg_end;
+        if rg_end <= offset || rg_start >= end || *count == 0 {
+            continue;
+        }
+        row_groups.push(i);
+        let skip_before = offset.saturating_sub(rg_start);
+        let skip_after = rg_end.saturating_sub(end);
+        let select = count - skip_before - skip_after;
+        for selector in [
+            RowSelector::skip(skip_before as usize),
+            RowSelector::select(select as usize),
+            RowSelector::skip(skip_after as usize),
+        ] {
+            if selector.row_count > 0 {
+                selectors.push(selector);
+            }
+        }
+    }
+    (row_groups, RowSelection::from(selectors))
+}
+
+fn column_index(schema: &SchemaRef, column: &str) -> Result<usize, ResultReaderError> {
+    schema
+        .index_of(column)
+        .map_err(|_| ResultReaderError::UnknownColumn(column.to_string()))
+}
+
+fn projection(schema: &SchemaRef, columns: Option<&[String]>) -> Result<Vec<usize>, ResultReaderError> {
+    match columns {
+        Some(columns) => columns.iter().map(|c| column_index(schema, c)).collect(),
+        None => Ok((0..schema.fields().len()).collect()),
+    }
+}
+
+fn to_json_rows(batch: &RecordBatch) -> Result<Vec<Map<String, Value>>, ResultReaderError> {
+    if batch.num_rows() == 0 {
+        return Ok(vec![]);
+    }
+    let mut writer = ArrayWriter::new(Vec::new());
+    writer.write(batch)?;
+    writer.finish()?;
+    Ok(serde_json::from_slice(&writer.into_inner())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use parquet::arrow::AsyncArrowWriter;
+    use parquet::file::properties::WriterProperties;
+    use rstest::rstest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::constants::ROWS_SORT_MAX_ROWS;
+
+    #[rstest]
+    #[case(&[10, 10, 10], 0, 5, vec![0], vec![RowSelector::select(5), RowSelector::skip(5)])]
+    #[case(&[10, 10, 10], 15, 10, vec![1, 2], vec![RowSelector::skip(5), RowSelector::select(5), RowSelector::select(5), RowSelector::skip(5)])]
+    #[case(&[10, 10, 10], 10, 10, vec![1], vec![RowSelector::select(10)])]
+    #[case(&[10, 10, 10], 30, 10, vec![], vec![])]
+    fn page_selection_test(
+        #[case] row_counts: &[u64],
+        #[case] offset: u64,
+        #[case] limit: u64,
+        #[case] expected_row_groups: Vec<usize>,
+        #[case] expected_selectors: Vec<RowSelector>,
+    ) {
+        let (row_groups, selection) = page_selection(row_counts, offset, limit);
+        assert_eq!(row_groups, expected_row_groups);
+        assert_eq!(selection, RowSelection::from(expected_selectors));
+    }
+
+    #[rstest]
+    #[case("name", SortSpec { column: "name".to_string(), descending: false })]
+    #[case("-name", SortSpec { column: "name".to_string(), descending: true })]
+    fn sort_spec_parse_test(#[case] input: &str, #[case] expected: SortSpec) {
+        assert_eq!(SortSpec::parse(input), expected);
+    }
+
+    /// 25 rows in row groups of 10, id 0..25, name "n{24 - id}"
+    async fn parquet_file() -> Vec<u8> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..25)),
+                Arc::new(StringArray::from_iter_values((0..25).map(|i| format!("n{:02}", 24 - i)))),
+            ],
+        )
+        .unwrap();
+        let props = WriterProperties::builder().set_max_row_group_size(10).build();
+        let mut buf = vec![];
+        let mut writer = AsyncArrowWriter::try_new(&mut buf, schema, Some(props)).unwrap();
+        writer.write(&batch).await.unwrap();
+        writer.close().await.unwrap();
+        buf
+    }
+
+    #[rstest]
+    #[case(RowsRequest { offset: 8, limit: 4, sort: None, columns: None }, vec![json!({"id": 8, "name": "n16"}), json!({"id": 9, "name": "n15"}), json!({"id": 10, "name": "n14"}), json!({"id": 11, "name": "n13"})])]
+    #[case(RowsRequest { offset: 23, limit: 10, sort: None, columns: Some(vec!["id".to_string()]) }, vec![json!({"id": 23}), json!({"id": 24})])]
+    #[case(RowsRequest { offset: 1, limit: 2, sort: Some(SortSpec::parse("name")), columns: Some(vec!["id".to_string()]) }, vec![json!({"id": 23}), json!({"id": 22})])]
+    #[case(RowsRequest { offset: 0, limit: 2, sort: Some(SortSpec::parse("-id")), columns: Some(vec!["name".to_string(), "id".to_string()]) }, vec![json!({"name": "n00", "id": 24}), json!({"name": "n01", "id": 23})])]
+    #[case(RowsRequest { offset: 100, limit: 2, sort: None, columns: None }, vec![])]
+    #[case(RowsRequest { offset: u64::MAX, limit: 2, sort: None, columns: None }, vec![])]
+    #[case(RowsRequest { offset: u64::MAX, limit: 2, sort: Some(SortSpec::parse("id")), columns: None }, vec![])]
+    #[tokio::test]
+    async fn read_rows_test(#[case] request: RowsRequest, #[case] expected: Vec<Value>) {
+        let file = std::io::Cursor::new(parquet_file().await);
+        let page = read_rows(Box::new(file), &request, ROWS_SORT_MAX_ROWS).await.unwrap();
+        assert_eq!(page.total_rows, 25);
+        let rows: Vec<Value> = page.rows.into_iter().map(Value::Object).collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[tokio::test]
+    async fn read_rows_unknown_column_test() {
+        let file = std::io::Cursor::new(parquet_file().await);
+        let request = RowsRequest { offset: 0, limit: 1, sort: None, columns: Some(vec!["foo".to_string()]) };
+        let res = read_rows(Box::new(file), &request, ROWS_SORT_MAX_ROWS).await;
+        assert!(matches!(res, Err(ResultReaderError::UnknownColumn(c)) if c == "foo"));
+    }
+
+    #[tokio::test]
+    async fn read_rows_sort_too_large_test() {
+        let request = RowsRequest { offset: 0, limit: 2, sort: Some(SortSpec::parse("id")), columns: None };
+        let file = std::io::Cursor::new(parquet_file().await);
+        let res = read_rows(Box::new(file), &request, 20).await;
+        assert!(matches!(res, Err(ResultReaderError::SortTooLarge { rows: 25, max: 20 })));
+
+        let unsorted = RowsRequest { sort: None, ..request };
+        let file = std::io::Cursor::new(parquet_file().await);
+        assert_eq!(read_rows(Box::new(file), &unsorted, 20).await.unwrap().rows.len(), 2);
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::utils::{
+    constants::RESULTS_URL,
+    error::UtilsError,
+    pathparser::PathParserError,
+    storage::{PresignOptions, Storage, StorageLocation},
+};
+
+pub const DEFAULT_FILENAME: &str = "download";
+
+/// Files fusion produces for a query or profile, {request_id}.{extension}
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultFormat {
+    Parquet,
+    Json,
+    Manifest,
+    Profile,
+}
+
+impl ResultFormat {
+    pub const ALL: [ResultFormat; 4] = [
+        ResultFormat::Parquet,
+        ResultFormat::Json,
+        ResultFormat::Manifest,
+        ResultFormat::Profile,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ResultFormat::Parquet => "parquet",
+            ResultFormat::Json => "json",
+            ResultFormat::Manifest => "manifest.json",
+            ResultFormat::Profile => "profile.json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ResultFormat::Parquet => "application/parquet",
+            ResultFormat::Json | ResultFormat::Manifest | ResultFormat::Profile => "application/json",
+        }
+    }
+
+    pub fn location(&self, request_id: &str) -> Result<StorageLocation, PathParserError> {
+        let results = StorageLocation::parse(&RESULTS_URL)?;
+        Ok(results.join(&format!("{request_id}.{}", self.extension())))
+    }
+
+    /// Browser saves results as {filename}.{extension}, manifest and profile are shown inline
+    pub fn content_disposition(&self, filename: &str) -> String {
+        match self {
+            ResultFormat::Manifest | ResultFormat::Profile => "inline".to_string(),
+            _ => format!("attachment; filename=\"{filename}.{}\"", self.extension()),
+        }
+    }
+}
+
+/// Check download filename, it goes into content-disposition header as is,
+/// extension is added per format, so "report.parquet" becomes "report"
+pub fn sanitize_filename(filename: &str) -> Option<String> {
+    let filename = filename.trim();
+    let filename = ResultFormat::ALL
+        .iter()
+        .find_map(|f| filename.strip_suffix(&format!(".{}", f.extension())))
+        .unwrap_or(filename);
+    let is_valid = !filename.is_empty()
+        && filename.len() <= 128
+        && !filename.starts_with('.')
+        && filename
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ' '));
+    is_valid.then(|| filename.to_string())
+}
+
+pub async fn presign_result(
+    storage: &Storage,
+    request_id: &str,
+    format: ResultFormat,
+    expires_in: Duration,
+    filename: &str,
+) -> Result<String, UtilsError> {
+    let location = format
+        .location(request_id)
+        .map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+    tracing::info!("creating presigned object for: {}", location);
+    let options = PresignOptions {
+        content_type: format.content_type().to_string(),
+        content_disposition: format.content_disposition(filename),
+        expires_in,
+    };
+    storage.presign(&location, &options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("report", Some("report".to_string()))]
+    #[case("report.parquet", Some("report".to_string()))]
+    #[case(" sales 2021-01.v2 ", Some("sales 2021-01.v2".to_string()))]
+    #[case("", None)]
+    #[case("../etc/passwd", None)]
+    #[case("a\"; filename=b", None)]
+    #[case(".parquet", None)]
+    fn sanitize_filename_test(#[case] input: &str, #[case] expected: Option<String>) {
+        assert_eq!(sanitize_filename(input), expected);
+    }
+
+    #[rstest]
+    #[case(ResultFormat::Parquet, "attachment; filename=\"report.parquet\"")]
+    #[case(ResultFormat::Json, "attachment; filename=\"report.json\"")]
+    #[case(ResultFormat::Manifest, "inline")]
+    #[case(ResultFormat::Profile, "inline")]
+    fn content_disposition_test(#[case] format: ResultFormat, #[case] expected: &str) {
+        assert_eq!(format.content_disposition("report"), expected);
+    }
+}
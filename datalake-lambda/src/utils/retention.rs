@@ -0,0 +1,262 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{
+    constants::RESULTS_URL,
+    error::UtilsError,
+    jobrecord::{JobRecord, JobStatus},
+    storage::{Storage, StorageLocation, StoredObject},
+};
+
+const DEFAULT_TTL_SECS: u64 = 7 * 24 * 3600; // results are kept for a week
+
+/// How long results are kept and how much storage every principal may use, configured as json:
+/// {"ttl_secs": 604800, "quota_bytes": 10737418240, "principals": {"etl": 107374182400}}
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub ttl_secs: u64,
+    pub quota_bytes: Option<u64>, // per principal, None is unlimited
+    pub principals: HashMap<String, u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_TTL_SECS,
+            quota_bytes: None,
+            principals: HashMap::new(),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn quota(&self, principal: Option<&str>) -> Option<u64> {
+        principal
+            .and_then(|p| self.principals.get(p))
+            .copied()
+            .or(self.quota_bytes)
+    }
+}
+
+/// Result files and job record of one query
+#[derive(Debug, Clone)]
+pub struct ResultSet {
+    pub request_id: String,
+    pub record: Option<JobRecord>,
+    pub objects: Vec<StoredObject>, // result files, without job record
+    pub created_at: DateTime<Utc>,
+}
+
+impl ResultSet {
+    pub fn bytes(&self) -> u64 {
+        self.objects.iter().map(|o| o.size).sum()
+    }
+
+    fn principal(&self) -> Option<&str> {
+        self.record.as_ref().and_then(|r| r.principal.as_deref())
+    }
+
+    fn is_expired(&self) -> bool {
+        self.record.as_ref().is_some_and(|r| r.status == JobStatus::Expired)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionReason {
+    Ttl,
+    Quota,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Eviction {
+    pub request_id: String,
+    pub reason: EvictionReason,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    pub scanned: usize,
+    pub evicted: Vec<Eviction>,
+    pub freed_bytes: u64,
+}
+
+/// Results older than ttl, then oldest results of every principal above its quota
+pub fn plan_cleanup(results: &[ResultSet], policy: &RetentionPolicy, now: DateTime<Utc>) -> Vec<Eviction> {
+    let ttl = Duration::seconds(policy.ttl_secs as i64);
+    let mut evicted = vec![];
+    let mut live: BTreeMap<Option<&str>, Vec<&ResultSet>> = BTreeMap::new();
+    for result in results.iter().filter(|r| !r.is_expired()) {
+        if now - result.created_at > ttl {
+            evicted.push(Eviction {
+                request_id: result.request_id.clone(),
+                reason: EvictionReason::Ttl,
+                bytes: result.bytes(),
+            });
+        } else {
+            live.entry(result.principal()).or_default().push(result);
+        }
+    }
+
+    for (principal, mut results) in live {
+        let Some(quota) = policy.quota(principal) else {
+            continue;
+        };
+        // newest results are kept
+        results.sort_by_key(|r| Reverse(r.created_at));
+        let mut used = 0;
+        for result in results {
+            used += result.bytes();
+            if used > quota {
+                evicted.push(Eviction {
+                    request_id: result.request_id.clone(),
+                    reason: EvictionReason::Quota,
+                    bytes: result.bytes(),
+                });
+            }
+        }
+    }
+    evicted
+}
+
+/// Group objects under results location by request id, {id}.parquet, {id}.json, {id}.job.json, ...
+pub async fn collect_results(storage: &Storage, results: &StorageLocation) -> Result<Vec<ResultSet>, UtilsError> {
+    let mut groups: BTreeMap<String, Vec<StoredObject>> = BTreeMap::new();
+    for object in storage.list(results).await? {
+        let Some((request_id, _)) = object.name.split_once('.') else {
+            continue;
+        };
+        if request_id.is_empty() || request_id.contains('/') {
+            continue;
+        }
+        groups.entry(request_id.to_string()).or_default().push(object);
+    }
+
+    let mut sets = vec![];
+    for (request_id, objects) in groups {
+        let record_name = format!("{request_id}.job.json");
+        let (records, objects): (Vec<_>, Vec<_>) = objects.into_iter().partition(|o| o.name == record_name);
+        let record = match records.first() {
+            Some(object) => serde_json::from_slice::<JobRecord>(&storage.get(&object.location).await?).ok(),
+            None => None,
+        };
+        let created_at = record
+            .as_ref()
+            .map(|r| r.created_at)
+            .or_else(|| objects.iter().filter_map(|o| o.last_modified).min());
+        let Some(created_at) = created_at else {
+            continue;
+        };
+        sets.push(ResultSet { request_id, record, objects, created_at });
+    }
+    Ok(sets)
+}
+
+/// Delete results by retention policy, job records are kept and marked as expired
+pub async fn run_cleanup(
+    storage: &Storage,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+    now: DateTime<Utc>,
+) -> Result<CleanupReport, UtilsError> {
+    let results = StorageLocation::parse(&RESULTS_URL)
+        .map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+    let sets = collect_results(storage, &results).await?;
+    let evicted = plan_cleanup(&sets, policy, now);
+    let freed_bytes = evicted.iter().map(|e| e.bytes).sum();
+    tracing::info!({ scanned = sets.len(), evicted = evicted.len(), freed_bytes, dry_run }, "cleanup planned");
+
+    if !dry_run {
+        let sets: HashMap<&str, &ResultSet> = sets.iter().map(|s| (s.request_id.as_str(), s)).collect();
+        for eviction in &evicted {
+            let Some(set) = sets.get(eviction.request_id.as_str()) else {
+                continue;
+            };
+            for object in &set.objects {
+                storage.delete(&object.location).await?;
+            }
+            if let Some(record) = &set.record {
+                let mut record = record.clone();
+                record.expire(match eviction.reason {
+                    EvictionReason::Ttl => "ttl",
+                    EvictionReason::Quota => "quota",
+                });
+                record.save(storage).await?;
+            }
+            tracing::info!({ request_id = eviction.request_id, reason = ?eviction.reason }, "result deleted");
+        }
+    }
+
+    Ok(CleanupReport {
+        dry_run,
+        scanned: sets.len(),
+        evicted,
+        freed_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn result(request_id: &str, principal: Option<&str>, age_secs: i64, bytes: u64, now: DateTime<Utc>) -> ResultSet {
+        let created_at = now - Duration::seconds(age_secs);
+        let mut record = JobRecord::new(request_id, principal.map(str::to_string), None);
+        record.created_at = created_at;
+        ResultSet {
+            request_id: request_id.to_string(),
+            record: Some(record),
+            objects: vec![StoredObject {
+                location: StorageLocation::Local(format!("/tmp/{request_id}.parquet").into()),
+                name: format!("{request_id}.parquet"),
+                size: bytes,
+                last_modified: Some(created_at),
+            }],
+            created_at,
+        }
+    }
+
+    fn policy() -> RetentionPolicy {
+        serde_json::from_str(r#"{"ttl_secs": 3600, "quota_bytes": 100, "principals": {"etl": 1000}}"#).unwrap()
+    }
+
+    #[rstest]
+    #[case(vec![("a", None, 10, 10)], vec![])]
+    #[case(vec![("a", None, 7200, 10)], vec![("a", EvictionReason::Ttl)])]
+    #[case(vec![("a", None, 30, 60), ("b", None, 20, 60), ("c", None, 10, 60)], vec![("b", EvictionReason::Quota), ("a", EvictionReason::Quota)])]
+    #[case(vec![("a", Some("etl"), 30, 600), ("b", Some("etl"), 20, 300), ("c", Some("alice"), 10, 60)], vec![])]
+    #[case(vec![("a", Some("etl"), 7200, 600), ("b", Some("alice"), 20, 60), ("c", Some("alice"), 10, 60)], vec![("a", EvictionReason::Ttl), ("b", EvictionReason::Quota)])]
+    fn plan_cleanup_test(
+        #[case] input: Vec<(&str, Option<&str>, i64, u64)>,
+        #[case] expected: Vec<(&str, EvictionReason)>,
+    ) {
+        let now = Utc::now();
+        let results: Vec<ResultSet> = input
+            .into_iter()
+            .map(|(id, principal, age, bytes)| result(id, principal, age, bytes, now))
+            .collect();
+        let evicted: Vec<(String, EvictionReason)> = plan_cleanup(&results, &policy(), now)
+            .into_iter()
+            .map(|e| (e.request_id, e.reason))
+            .collect();
+        let expected: Vec<(String, EvictionReason)> =
+            expected.into_iter().map(|(id, reason)| (id.to_string(), reason)).collect();
+        assert_eq!(evicted, expected);
+    }
+
+    #[test]
+    fn plan_cleanup_skips_expired_test() {
+        let now = Utc::now();
+        let mut expired = result("a", None, 7200, 10, now);
+        expired.record.as_mut().unwrap().expire("ttl");
+        assert_eq!(plan_cleanup(&[expired], &policy(), now), vec![]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::utils::constants::MAX_ROWS;
+
+/// Max rows a query result may contain, configured as json:
+/// {"default_limit": 1000, "max_limit": 10000, "principals": {"etl": 1000000}, "routes": {"POST /query": 5000}}
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RowLimitPolicy {
+    pub default_limit: u64, // used when the caller doesn't ask for max_rows
+    pub max_limit: u64,     // cap for callers without own entry
+    pub principals: HashMap<String, u64>,
+    pub routes: HashMap<String, u64>,
+}
+
+impl Default for RowLimitPolicy {
+    fn default() -> Self {
+        Self {
+            default_limit: MAX_ROWS,
+            max_limit: MAX_ROWS,
+            principals: HashMap::new(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl RowLimitPolicy {
+    /// Cap for the caller, principal entry wins over route entry
+    pub fn cap(&self, principal: Option<&str>, route: &str) -> u64 {
+        principal
+            .and_then(|p| self.principals.get(p))
+            .or_else(|| self.routes.get(route))
+            .copied()
+            .unwrap_or(self.max_limit)
+    }
+
+    /// Row limit for the request, caller may ask for more rows than default up to the cap
+    pub fn resolve(&self, requested: Option<u64>, principal: Option<&str>, route: &str) -> u64 {
+        let cap = self.cap(principal, route);
+        requested.unwrap_or(self.default_limit).min(cap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn policy() -> RowLimitPolicy {
+        serde_json::from_str(
+            r#"{"default_limit": 100, "max_limit": 1000, "principals": {"etl": 100000}, "routes": {"POST /query": 5000}}"#,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(None, None, "GET /foo", 100)]
+    #[case(Some(500), None, "GET /foo", 500)]
+    #[case(Some(50000), None, "GET /foo", 1000)]
+    #[case(Some(50000), None, "POST /query", 5000)]
+    #[case(Some(50000), Some("etl"), "POST /query", 50000)]
+    #[case(Some(500000), Some("etl"), "POST /query", 100000)]
+    #[case(Some(50000), Some("alice"), "POST /query", 5000)]
+    fn resolve_test(
+        #[case] requested: Option<u64>,
+        #[case] principal: Option<&str>,
+        #[case] route: &str,
+        #[case] expected: u64,
+    ) {
+        assert_eq!(policy().resolve(requested, principal, route), expected);
+    }
+
+    #[test]
+    fn default_policy_test() {
+        let policy: RowLimitPolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(policy, RowLimitPolicy::default());
+        assert_eq!(policy.resolve(Some(5000), None, "POST /query"), MAX_ROWS);
+    }
+}
@@ -0,0 +1,160 @@
+use std::ops::ControlFlow;
+
+use sqlparser::ast::{Expr, ObjectName, Query, SetExpr, Statement, TableFactor, Visit, Visitor};
+use thiserror::Error;
+
+use crate::utils::constants::DENIED_FUNCTIONS;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SqlPolicyError {
+    #[error("Scripts are not supported: got {0} statements, expected a single query")]
+    MultipleStatements(usize),
+
+    #[error("Only read-only queries are allowed: {0}")]
+    ReadOnlyViolation(String),
+
+    #[error("Function is not allowed: {0}")]
+    DeniedFunction(String),
+}
+
+/// What user queries may contain besides plain read-only selects
+#[derive(Debug, Clone, Default)]
+pub struct SqlPolicy {
+    pub denied_functions: Vec<String>, // lowercase names
+}
+
+impl SqlPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            denied_functions: DENIED_FUNCTIONS.clone(),
+        }
+    }
+
+    /// Check every statement and every nested node of the query:
+    /// a single query statement, no data modification, no denied functions
+    pub fn validate(&self, ast: &Vec<Statement>) -> Result<(), SqlPolicyError> {
+        if ast.len() > 1 {
+            return Err(SqlPolicyError::MultipleStatements(ast.len()));
+        }
+        let mut visitor = ReadOnlyVisitor { policy: self };
+        match ast.visit(&mut visitor) {
+            ControlFlow::Break(e) => Err(e),
+            ControlFlow::Continue(()) => Ok(()),
+        }
+    }
+
+    fn check_function(&self, name: &ObjectName) -> ControlFlow<SqlPolicyError> {
+        let function = name
+            .0
+            .last()
+            .and_then(|part| part.as_ident())
+            .map(|ident| ident.value.to_lowercase())
+            .unwrap_or_default();
+        if self.denied_functions.contains(&function) {
+            return ControlFlow::Break(SqlPolicyError::DeniedFunction(function));
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct ReadOnlyVisitor<'a> {
+    policy: &'a SqlPolicy,
+}
+
+impl Visitor for ReadOnlyVisitor<'_> {
+    type Break = SqlPolicyError;
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<Self::Break> {
+        match statement {
+            Statement::Query(_) => ControlFlow::Continue(()),
+            other => ControlFlow::Break(SqlPolicyError::ReadOnlyViolation(statement_kind(other))),
+        }
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<Self::Break> {
+        if !query.locks.is_empty() {
+            return ControlFlow::Break(SqlPolicyError::ReadOnlyViolation("FOR UPDATE".to_string()));
+        }
+        check_set_expr(&query.body)
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<Self::Break> {
+        match table_factor {
+            TableFactor::Table { name, args: Some(_), .. } => self.policy.check_function(name),
+            TableFactor::Function { name, .. } => self.policy.check_function(name),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        match expr {
+            Expr::Function(function) => self.policy.check_function(&function.name),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+fn check_set_expr(set_expr: &SetExpr) -> ControlFlow<SqlPolicyError> {
+    match set_expr {
+        SetExpr::Select(select) if select.into.is_some() => {
+            ControlFlow::Break(SqlPolicyError::ReadOnlyViolation("SELECT INTO".to_string()))
+        }
+        SetExpr::SetOperation { left, right, .. } => {
+            check_set_expr(left)?;
+            check_set_expr(right)
+        }
+        SetExpr::Insert(statement) | SetExpr::Update(statement) | SetExpr::Delete(statement) => {
+            ControlFlow::Break(SqlPolicyError::ReadOnlyViolation(statement_kind(statement)))
+        }
+        SetExpr::Table(_) => {
+            ControlFlow::Break(SqlPolicyError::ReadOnlyViolation("TABLE statement".to_string()))
+        }
+        _ => ControlFlow::Continue(()),
+    }
+}
+
+/// Statement name for error messages, e.g. DROP TABLE x -> "DROP"
+fn statement_kind(statement: &Statement) -> String {
+    statement
+        .to_string()
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use sqlparser::dialect::GenericDialect;
+    use sqlparser::parser::Parser;
+
+    use super::*;
+
+    fn policy() -> SqlPolicy {
+        SqlPolicy {
+            denied_functions: vec!["pg_sleep".to_string(), "generate_series".to_string()],
+        }
+    }
+
+    #[rstest]
+    #[case("select * from foo", Ok(()))]
+    #[case("with x as (select * from foo) select * from x union all select * from foo", Ok(()))]
+    #[case("select count(*), upper(name) from foo where id in (select id from bar)", Ok(()))]
+    #[case("select 1; drop table x", Err(SqlPolicyError::MultipleStatements(2)))]
+    #[case("select 1; select 2", Err(SqlPolicyError::MultipleStatements(2)))]
+    #[case("drop table x", Err(SqlPolicyError::ReadOnlyViolation("DROP".to_string())))]
+    #[case("set s3.access_key_id = 'foo'", Err(SqlPolicyError::ReadOnlyViolation("SET".to_string())))]
+    #[case("create table foo as select * from bar", Err(SqlPolicyError::ReadOnlyViolation("CREATE".to_string())))]
+    #[case("select * into bar from foo", Err(SqlPolicyError::ReadOnlyViolation("SELECT INTO".to_string())))]
+    #[case("select * from foo for update", Err(SqlPolicyError::ReadOnlyViolation("FOR UPDATE".to_string())))]
+    #[case("select * from foo union select * into bar from foo", Err(SqlPolicyError::ReadOnlyViolation("SELECT INTO".to_string())))]
+    #[case("with x as (delete from foo returning *) select * from x", Err(SqlPolicyError::ReadOnlyViolation("DELETE".to_string())))]
+    #[case("select pg_sleep(10) from foo", Err(SqlPolicyError::DeniedFunction("pg_sleep".to_string())))]
+    #[case("select * from foo where id = (select PG_SLEEP(1))", Err(SqlPolicyError::DeniedFunction("pg_sleep".to_string())))]
+    #[case("select * from generate_series(1, 10)", Err(SqlPolicyError::DeniedFunction("generate_series".to_string())))]
+    fn validate_test(#[case] input: &str, #[case] expected: Result<(), SqlPolicyError>) {
+        let ast = Parser::parse_sql(&GenericDialect {}, input).unwrap();
+        assert_eq!(policy().validate(&ast), expected);
+    }
+}
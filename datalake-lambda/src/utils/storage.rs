@@ -0,0 +1,372 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_sdk_s3::{Client, presigning::PresigningConfig, primitives::ByteStream};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{FutureExt, future::BoxFuture};
+use parquet::arrow::arrow_reader::ArrowReaderOptions;
+use parquet::arrow::async_reader::AsyncFileReader;
+use parquet::errors::ParquetError;
+use parquet::file::metadata::{ParquetMetaData, ParquetMetaDataReader};
+use url::Url;
+
+use crate::utils::{aws::get_aws_client, error::UtilsError, pathparser::PathParserError};
+
+/// Object location resolved from an url, either s3://bucket/key or file:///path
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageLocation {
+    S3 { bucket: String, key: String },
+    Local(PathBuf),
+}
+
+impl StorageLocation {
+    pub fn parse(url: &str) -> Result<Self, PathParserError> {
+        let url = Url::parse(url).map_err(|e| PathParserError::ParseError(e.to_string()))?;
+        match url.scheme() {
+            "s3" => {
+                let bucket = url.host_str().ok_or(PathParserError::MissingBucket)?.to_string();
+                let key = url.path().trim_start_matches('/').to_string();
+                Ok(Self::S3 { bucket, key })
+            }
+            "file" => {
+                let path = url
+                    .to_file_path()
+                    .map_err(|_| PathParserError::ParseError(format!("invalid file path: {url}")))?;
+                Ok(Self::Local(path))
+            }
+            _ => Err(PathParserError::InvalidScheme),
+        }
+    }
+
+    /// Append name to the location as is, same as {prefix}{name} for s3 keys
+    pub fn join(&self, name: &str) -> Self {
+        match self {
+            Self::S3 { bucket, key } => Self::S3 {
+                bucket: bucket.clone(),
+                key: format!("{key}{name}"),
+            },
+            Self::Local(path) => {
+                let mut path = path.clone().into_os_string();
+                path.push(name);
+                Self::Local(path.into())
+            }
+        }
+    }
+}
+
+impl fmt::Display for StorageLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::S3 { bucket, key } => write!(f, "s3://{bucket}/{key}"),
+            Self::Local(path) => write!(f, "file://{}", path.display()),
+        }
+    }
+}
+
+/// Object found by Storage::list, name is relative to the listed location
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredObject {
+    pub location: StorageLocation,
+    pub name: String,
+    pub size: u64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct PresignOptions {
+    pub content_type: String,
+    pub content_disposition: String,
+    pub expires_in: Duration,
+}
+
+/// Storage for table data and query results,
+/// talks to aws s3 (or s3 compatible endpoint) and to local file system
+pub struct Storage {
+    client: Client,
+}
+
+impl Storage {
+    pub async fn new(region: String) -> Self {
+        let client = get_aws_client(region).await;
+        Self { client }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Check that bucket exists and there is at least one object under prefix
+    pub async fn exists(&self, location: &StorageLocation) -> Result<bool, UtilsError> {
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                if self.client.head_bucket().bucket(bucket).send().await.is_err() {
+                    return Ok(false);
+                }
+                if key.is_empty() {
+                    return Ok(true);
+                }
+                let resp = self
+                    .client
+                    .list_objects_v2()
+                    .bucket(bucket)
+                    .prefix(key)
+                    .max_keys(1)
+                    .send()
+                    .await?;
+                Ok(!resp.contents().is_empty())
+            }
+            StorageLocation::Local(path) => match tokio::fs::metadata(path).await {
+                Ok(meta) if meta.is_dir() => {
+                    let mut entries = tokio::fs::read_dir(path).await?;
+                    Ok(entries.next_entry().await?.is_some())
+                }
+                Ok(_) => Ok(true),
+                Err(_) => Ok(false),
+            },
+        }
+    }
+
+    pub async fn get(&self, location: &StorageLocation) -> Result<Vec<u8>, UtilsError> {
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                let resp = self.client.get_object().bucket(bucket).key(key).send().await?;
+                let data = resp.body.collect().await?;
+                Ok(data.to_vec())
+            }
+            StorageLocation::Local(path) => Ok(tokio::fs::read(path).await?),
+        }
+    }
+
+    pub async fn put(&self, location: &StorageLocation, body: Vec<u8>) -> Result<(), UtilsError> {
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                self.client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .body(ByteStream::from(body))
+                    .send()
+                    .await?;
+            }
+            StorageLocation::Local(path) => {
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(path, body).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// List objects whose location starts with the given one,
+    /// e.g. s3://bucket/prefix lists prefix*, file:///tmp/results/ lists files in the directory
+    pub async fn list(&self, location: &StorageLocation) -> Result<Vec<StoredObject>, UtilsError> {
+        let mut objects = vec![];
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                let mut continuation_token = None;
+                loop {
+                    let resp = self
+                        .client
+                        .list_objects_v2()
+                        .bucket(bucket)
+                        .prefix(key)
+                        .set_continuation_token(continuation_token)
+                        .send()
+                        .await?;
+                    for object in resp.contents() {
+                        let Some(object_key) = object.key() else {
+                            continue;
+                        };
+                        objects.push(StoredObject {
+                            location: StorageLocation::S3 {
+                                bucket: bucket.clone(),
+                                key: object_key.to_string(),
+                            },
+                            name: object_key[key.len()..].to_string(),
+                            size: object.size().unwrap_or_default().max(0) as u64,
+                            last_modified: object
+                                .last_modified()
+                                .and_then(|t| DateTime::from_timestamp(t.secs(), t.subsec_nanos())),
+                        });
+                    }
+                    continuation_token = resp.next_continuation_token().map(str::to_string);
+                    if continuation_token.is_none() {
+                        break;
+                    }
+                }
+            }
+            StorageLocation::Local(path) => {
+                // location is either a directory (ends with /) or a file name prefix in a directory
+                let path_str = path.to_string_lossy();
+                let (dir, prefix) = if path_str.ends_with('/') {
+                    (path.clone(), String::new())
+                } else {
+                    let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+                    let prefix = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                    (dir, prefix)
+                };
+                let mut entries = match tokio::fs::read_dir(&dir).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(objects),
+                    Err(e) => return Err(e.into()),
+                };
+                while let Some(entry) = entries.next_entry().await? {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    let Some(name) = file_name.strip_prefix(&prefix) else {
+                        continue;
+                    };
+                    let meta = entry.metadata().await?;
+                    if !meta.is_file() {
+                        continue;
+                    }
+                    objects.push(StoredObject {
+                        location: StorageLocation::Local(entry.path()),
+                        name: name.to_string(),
+                        size: meta.len(),
+                        last_modified: meta.modified().ok().map(DateTime::<Utc>::from),
+                    });
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    pub async fn delete(&self, location: &StorageLocation) -> Result<(), UtilsError> {
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                self.client.delete_object().bucket(bucket).key(key).send().await?;
+            }
+            StorageLocation::Local(path) => match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// Open parquet file for reading byte ranges, only footer and requested pages are fetched
+    pub async fn open_parquet(
+        &self,
+        location: &StorageLocation,
+    ) -> Result<Box<dyn AsyncFileReader>, UtilsError> {
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                let head = self.client.head_object().bucket(bucket).key(key).send().await?;
+                let size = head.content_length().unwrap_or_default() as u64;
+                Ok(Box::new(S3FileReader {
+                    client: self.client.clone(),
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    size,
+                }))
+            }
+            StorageLocation::Local(path) => Ok(Box::new(tokio::fs::File::open(path).await?)),
+        }
+    }
+
+    /// Create url for downloading object,
+    /// local files can't be presigned and are returned as file:// url
+    pub async fn presign(
+        &self,
+        location: &StorageLocation,
+        options: &PresignOptions,
+    ) -> Result<String, UtilsError> {
+        match location {
+            StorageLocation::S3 { bucket, key } => {
+                let presigning_config = PresigningConfig::builder()
+                    .expires_in(options.expires_in)
+                    .build()
+                    .map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+                let presigned = self
+                    .client
+                    .get_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .response_content_type(&options.content_type) // for browser
+                    .response_content_disposition(&options.content_disposition) // for browser
+                    .presigned(presigning_config)
+                    .await
+                    .map_err(|e| UtilsError::UnexpectedError(e.into()))?;
+                Ok(presigned.uri().to_string())
+            }
+            StorageLocation::Local(_) => Ok(location.to_string()),
+        }
+    }
+}
+
+/// Parquet reader over s3 ranged GET requests
+struct S3FileReader {
+    client: Client,
+    bucket: String,
+    key: String,
+    size: u64,
+}
+
+impl AsyncFileReader for S3FileReader {
+    fn get_bytes(&mut self, range: Range<u64>) -> BoxFuture<'_, parquet::errors::Result<Bytes>> {
+        async move {
+            let resp = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .range(format!("bytes={}-{}", range.start, range.end - 1)) // range end is inclusive
+                .send()
+                .await
+                .map_err(|e| ParquetError::External(Box::new(e)))?;
+            let data = resp
+                .body
+                .collect()
+                .await
+                .map_err(|e| ParquetError::External(Box::new(e)))?;
+            Ok(data.into_bytes())
+        }
+        .boxed()
+    }
+
+    fn get_metadata<'a>(
+        &'a mut self,
+        options: Option<&'a ArrowReaderOptions>,
+    ) -> BoxFuture<'a, parquet::errors::Result<Arc<ParquetMetaData>>> {
+        async move {
+            let size = self.size;
+            let metadata = ParquetMetaDataReader::new()
+                .with_page_indexes(options.is_some_and(|o| o.page_index()))
+                .load_and_finish(self, size)
+                .await?;
+            Ok(Arc::new(metadata))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("s3://bucket/prefix/", Some(StorageLocation::S3 { bucket: "bucket".to_string(), key: "prefix/".to_string() }))]
+    #[case("s3://bucket", Some(StorageLocation::S3 { bucket: "bucket".to_string(), key: "".to_string() }))]
+    #[case("file:///tmp/results/", Some(StorageLocation::Local(PathBuf::from("/tmp/results/"))))]
+    #[case("http://localhost/foo", None)]
+    #[case("foo", None)]
+    fn storage_location_parse_test(#[case] input: &str, #[case] expected: Option<StorageLocation>) {
+        assert_eq!(StorageLocation::parse(input).ok(), expected);
+    }
+
+    #[rstest]
+    #[case("s3://bucket/prefix", "id.parquet", "s3://bucket/prefixid.parquet")]
+    #[case("s3://bucket/prefix/", "id.json", "s3://bucket/prefix/id.json")]
+    #[case("file:///tmp/results/", "id.parquet", "file:///tmp/results/id.parquet")]
+    fn storage_location_join_test(#[case] base: &str, #[case] name: &str, #[case] expected: &str) {
+        let location = StorageLocation::parse(base).unwrap().join(name);
+        assert_eq!(location.to_string(), expected);
+    }
+}
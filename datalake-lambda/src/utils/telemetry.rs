@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use color_eyre::Result;
+use serde::Serialize;
+
+#[cfg(feature = "otel")]
+use std::collections::HashMap;
+
+#[cfg(feature = "otel")]
+use opentelemetry::{KeyValue, global, trace::TracerProvider as _};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{
+    Resource, metrics::SdkMeterProvider, propagation::TraceContextPropagator, trace::SdkTracerProvider,
+};
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+#[cfg(feature = "otel")]
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+use crate::utils::{constants::OTEL_ENDPOINT, jobrecord::ErrorKind, launcher::Capacity};
+
+pub const SERVICE_NAME: &str = "datalake-lambda";
+
+/// OTLP/HTTP export of traces and metrics, built with the otel feature
+/// and enabled when OTEL_EXPORTER_OTLP_ENDPOINT is set
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    tracer_provider: SdkTracerProvider,
+    #[cfg(feature = "otel")]
+    meter_provider: SdkMeterProvider,
+}
+
+impl Telemetry {
+    /// None without the otel feature or endpoint
+    pub fn from_env() -> Result<Option<Self>> {
+        match OTEL_ENDPOINT.as_deref() {
+            #[cfg(feature = "otel")]
+            Some(endpoint) => Ok(Some(Self::new(endpoint)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Exporters to {endpoint}/v1/traces and {endpoint}/v1/metrics, installed as global providers
+    #[cfg(feature = "otel")]
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{endpoint}/v1/traces"))
+            .build()?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{endpoint}/v1/metrics"))
+            .build()?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(metrics)
+            .with_resource(resource)
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(tracer_provider.clone());
+        global::set_meter_provider(meter_provider.clone());
+        Ok(Self { tracer_provider, meter_provider })
+    }
+
+    /// Tracing layer which exports spans
+    #[cfg(feature = "otel")]
+    pub fn layer<S>(&self) -> impl Layer<S> + use<S>
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer_provider.tracer(SERVICE_NAME))
+    }
+
+    /// Export buffered spans and metrics, the lambda is frozen between invocations
+    pub fn flush(&self) {
+        #[cfg(feature = "otel")]
+        {
+            if let Err(e) = self.tracer_provider.force_flush() {
+                tracing::warn!("failed to export spans: {e}");
+            }
+            if let Err(e) = self.meter_provider.force_flush() {
+                tracing::warn!("failed to export metrics: {e}");
+            }
+        }
+    }
+}
+
+/// W3C traceparent of the current span, fusion continues the trace with it
+pub fn traceparent() -> Option<String> {
+    #[cfg(feature = "otel")]
+    {
+        let context = tracing::Span::current().context();
+        let mut carrier = HashMap::new();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut carrier));
+        carrier.remove("traceparent")
+    }
+    #[cfg(not(feature = "otel"))]
+    None
+}
+
+/// Latency of an api request by route and response status
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn record_request(route: &str, status: u16, elapsed: Duration) {
+    #[cfg(feature = "otel")]
+    global::meter(SERVICE_NAME)
+        .f64_histogram("datalake.request.duration")
+        .with_unit("s")
+        .build()
+        .record(
+            elapsed.as_secs_f64(),
+            &[KeyValue::new("route", route.to_string()), KeyValue::new("status", status as i64)],
+        );
+}
+
+/// Time until the fusion task was started or the launcher gave up, retries included
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn record_launch(capacity: Capacity, started: bool, elapsed: Duration) {
+    #[cfg(feature = "otel")]
+    global::meter(SERVICE_NAME)
+        .f64_histogram("datalake.ecs.launch.duration")
+        .with_unit("s")
+        .build()
+        .record(
+            elapsed.as_secs_f64(),
+            &[
+                KeyValue::new("capacity", format!("{capacity:?}")),
+                KeyValue::new("started", started),
+            ],
+        );
+}
+
+/// Failed jobs by kind, fusion counts the failures of the queries it runs
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn record_failure(kind: ErrorKind) {
+    #[cfg(feature = "otel")]
+    global::meter(SERVICE_NAME)
+        .u64_counter("datalake.query.failures")
+        .build()
+        .add(1, &[KeyValue::new("error_kind", label(&kind))]);
+}
+
+/// Serialized name of an enum value, same as in job records
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+fn label<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    /// Collector stand-in, accepts every export and keeps the request paths
+    fn collector() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let paths = Arc::new(Mutex::new(vec![]));
+        let received = paths.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                // headers, then content-length bytes of body
+                let body_start = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..body_start]).to_string();
+                let length = head
+                    .lines()
+                    .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                while request.len() < body_start + length {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let path = head.split_whitespace().nth(1).unwrap().to_string();
+                received.lock().unwrap().push(path);
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .unwrap();
+            }
+        });
+        (endpoint, paths)
+    }
+
+    #[test]
+    fn telemetry_export_test() {
+        let (endpoint, paths) = collector();
+        let telemetry = Telemetry::new(&endpoint).unwrap();
+        let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+        let traceparent = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("query", request_id = "id");
+            let _guard = span.enter();
+            record_request("POST /query", 200, Duration::from_millis(120));
+            record_launch(Capacity::FargateSpot, true, Duration::from_secs(2));
+            record_failure(ErrorKind::Launch);
+            traceparent()
+        });
+        telemetry.flush();
+
+        let traceparent = traceparent.unwrap();
+        assert!(traceparent.starts_with("00-") && traceparent.len() == 55, "{traceparent}");
+        let paths = paths.lock().unwrap();
+        assert!(paths.contains(&"/v1/traces".to_string()), "{paths:?}");
+        assert!(paths.contains(&"/v1/metrics".to_string()), "{paths:?}");
+    }
+}
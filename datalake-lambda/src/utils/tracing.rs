@@ -1,12 +1,30 @@
-use tracing::Level;
+use std::env;
 
-pub fn init_tracing() {
-    tracing_subscriber::fmt()
+use color_eyre::Result;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::utils::telemetry::Telemetry;
+
+/// Set by the lambda runtime for every invocation
+const XRAY_TRACE_ID_ENV_VAR: &str = "_X_AMZN_TRACE_ID";
+
+/// JSON logs, spans are exported too when telemetry is enabled
+pub fn init_tracing() -> Result<Option<Telemetry>> {
+    let telemetry = Telemetry::from_env()?;
+    let logs = tracing_subscriber::fmt::layer()
         .json()
-        .with_max_level(Level::INFO)
         .with_current_span(true)
         .with_ansi(false)
         .without_time()
-        .with_target(false)
-        .init();
+        .with_target(false);
+    let registry = tracing_subscriber::registry().with(LevelFilter::INFO).with(logs);
+    #[cfg(feature = "otel")]
+    let registry = registry.with(telemetry.as_ref().map(Telemetry::layer));
+    registry.init();
+    Ok(telemetry)
+}
+
+/// X-Ray trace of the current invocation, passed to fusion so both logs can be joined
+pub fn xray_trace_id() -> Option<String> {
+    env::var(XRAY_TRACE_ID_ENV_VAR).ok().filter(|v| !v.is_empty())
 }
@@ -33,7 +33,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/query", &self.address))
+            .post(format!("{}/query", &self.address))
             .json(body)
             .send()
             .await